@@ -0,0 +1,32 @@
+//! Commits recorded by the async functions taking a [`CommitInfo`]. Reading the history back is
+//! done with [`crate::commit`].
+
+use crate::{
+    commit::{build_commit, CommitInfo, HISTORY_REF},
+    error::SyngError,
+};
+
+use super::AsyncSyngBackend;
+
+/// Writes the commit of `tree` and moves [`HISTORY_REF`] to it
+pub(crate) async fn record_commit(
+    backend: &mut impl AsyncSyngBackend,
+    tree: &str,
+    info: &CommitInfo,
+) -> Result<String, SyngError> {
+    // Retry if another writer committed in between, so that their commit becomes the parent
+    loop {
+        let head = backend.read_ref(HISTORY_REF).await?;
+
+        let commit_id = backend
+            .write_object(&build_commit(tree, head.clone(), info).to_object())
+            .await?;
+
+        if backend
+            .compare_and_set_ref(HISTORY_REF, head.as_deref(), &commit_id)
+            .await?
+        {
+            return Ok(commit_id);
+        }
+    }
+}
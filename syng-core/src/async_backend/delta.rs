@@ -0,0 +1,191 @@
+//! Async versions of the functions in [`crate::delta`]
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    delta::{validate_delta_objects, ApplyDeltaError, ApplyDeltaOptions, SyngDelta},
+    error::SyngError,
+    objects::SyngObjectDef,
+};
+
+use super::{
+    commit::record_commit,
+    tree_ops::{descendent_object_ids, read_referred_objects},
+    AsyncSyngBackend,
+};
+
+async fn validate_delta(
+    backend: &impl AsyncSyngBackend,
+    delta: &SyngDelta,
+    options: &ApplyDeltaOptions,
+) -> Result<(), ApplyDeltaError> {
+    // Check if start point is the current root tree of the backend
+    let root_object_id = backend
+        .get_root_object_id()
+        .await
+        .map_err(ApplyDeltaError::BackendReadFailed)?;
+
+    if root_object_id != delta.start_point {
+        return Err(ApplyDeltaError::CurrentTreeDrifted);
+    }
+
+    validate_delta_objects(delta, options)?;
+
+    // Check if all the objects on the tree properly resolve out into valid nodes that exist. A
+    // child should either be in the delta or in the backend, the ones that aren't in the delta are
+    // looked up in one batch
+    let mut backend_child_ids = delta
+        .new_objects
+        .values()
+        .flat_map(|object| object.children.iter().map(String::as_str))
+        .filter(|child_node_id| !delta.new_objects.contains_key(*child_node_id))
+        .collect::<Vec<_>>();
+
+    backend_child_ids.sort();
+    backend_child_ids.dedup();
+
+    let backend_children = backend
+        .read_objects(&backend_child_ids)
+        .await
+        .map_err(ApplyDeltaError::BackendReadFailed)?;
+
+    let unresolved_nodes = backend_child_ids
+        .into_iter()
+        .zip(backend_children)
+        .filter(|(_, child)| child.is_none())
+        .map(|(child_node_id, _)| child_node_id.to_owned())
+        .collect::<Vec<_>>();
+
+    if !unresolved_nodes.is_empty() {
+        return Err(ApplyDeltaError::DeltaMissingObjects(unresolved_nodes));
+    }
+
+    Ok(())
+}
+
+/// See [`crate::delta::apply_delta`]
+pub async fn apply_delta(
+    backend: &mut impl AsyncSyngBackend,
+    delta: &SyngDelta,
+) -> Result<(String, SyngObjectDef), ApplyDeltaError> {
+    apply_delta_with_options(backend, delta, &ApplyDeltaOptions::default()).await
+}
+
+pub async fn apply_delta_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    delta: &SyngDelta,
+    options: &ApplyDeltaOptions,
+) -> Result<(String, SyngObjectDef), ApplyDeltaError> {
+    // Try validating and see if the delta actually makes sense for this backend
+    validate_delta(backend, delta, options).await?;
+
+    backend
+        .begin_transaction()
+        .await
+        .map_err(ApplyDeltaError::TransactionFailed)?;
+
+    if let Err(e) = write_delta(backend, delta, options).await {
+        // The write error is more useful to the caller than a failed rollback
+        let _ = backend.rollback_transaction().await;
+
+        return Err(e);
+    }
+
+    if let Err(e) = backend.commit_transaction().await {
+        let _ = backend.rollback_transaction().await;
+
+        return Err(ApplyDeltaError::TransactionFailed(e));
+    }
+
+    Ok((
+        delta.new_root_node.clone(),
+        delta.new_objects.get(&delta.new_root_node).unwrap().clone(),
+    ))
+}
+
+async fn write_delta(
+    backend: &mut impl AsyncSyngBackend,
+    delta: &SyngDelta,
+    options: &ApplyDeltaOptions,
+) -> Result<(), ApplyDeltaError> {
+    let objects = delta
+        .new_objects
+        .iter()
+        .map(|(id, object)| (id.as_str(), object))
+        .collect::<Vec<_>>();
+
+    backend
+        .write_objects_with_ids(&objects)
+        .await
+        .map_err(ApplyDeltaError::ObjectWriteFailed)?;
+
+    // The root could have moved since the delta was validated, so only move it if it is still at
+    // the start point
+    let root_updated = backend
+        .compare_and_set_root(delta.start_point.as_deref(), &delta.new_root_node)
+        .await
+        .map_err(ApplyDeltaError::SetRootFailed)?;
+
+    if !root_updated {
+        return Err(ApplyDeltaError::CurrentTreeDrifted);
+    }
+
+    if let Some(info) = &options.commit {
+        record_commit(backend, &delta.new_root_node, info)
+            .await
+            .map_err(ApplyDeltaError::CommitFailed)?;
+    }
+
+    Ok(())
+}
+
+pub async fn generate_delta_from_point(
+    backend: &impl AsyncSyngBackend,
+    past_head_object_id: &str,
+) -> Result<SyngDelta, SyngError> {
+    // Storing this into a BTreeSet so that we can binary search through the objects
+    let past_tree_object_ids: BTreeSet<String> =
+        descendent_object_ids(backend, past_head_object_id)
+            .await?
+            .into_iter()
+            .collect();
+
+    let current_head_id = backend
+        .get_root_object_id()
+        .await?
+        .ok_or(SyngError::NoRootObject)?;
+
+    let mut new_objects = HashMap::new();
+
+    // Walk the current tree a level at a time, skipping the subtrees the past tree already has
+    let mut level = vec![current_head_id.clone()];
+
+    while !level.is_empty() {
+        let objs = read_referred_objects(backend, &level).await?;
+
+        let mut next_level = vec![];
+
+        for (obj_id, obj) in level.into_iter().zip(objs) {
+            next_level.extend(
+                obj.children
+                    .iter()
+                    .filter(|child_id| !past_tree_object_ids.contains(*child_id))
+                    .cloned(),
+            );
+
+            new_objects.insert(obj_id, obj);
+        }
+
+        next_level.sort();
+        next_level.dedup();
+        next_level.retain(|child_id| !new_objects.contains_key(child_id));
+
+        level = next_level;
+    }
+
+    Ok(SyngDelta {
+        start_point: Some(past_head_object_id.to_string()),
+        new_root_node: current_head_id,
+        new_objects,
+    })
+}
@@ -0,0 +1,630 @@
+//! Async counterparts of [`SyngBackend`] and of the [`crate::tree_ops`] and [`crate::delta`]
+//! functions, for backends that need to await I/O (network or database backed stores).
+//!
+//! The other modules (history, garbage collection, fsck...) only come in a sync version. A sync
+//! backend wrapped in a [`SyncBackendAdapter`] can still be handed to them through
+//! [`SyncBackendAdapter::inner`].
+
+use std::collections::BTreeMap;
+
+use crate::{
+    backend::{check_ref_name, SyngBackend, DEFAULT_REF},
+    error::SyngError,
+    objects::{HashAlgorithm, SyngObjectDef},
+};
+
+mod commit;
+pub mod delta;
+pub mod tree_ops;
+
+#[allow(async_fn_in_trait)]
+pub trait AsyncSyngBackend {
+    async fn has_object(&self, object_id: &str) -> Result<bool, SyngError> {
+        Ok(self.read_object(object_id).await?.is_some())
+    }
+
+    async fn get_root_object_id(&self) -> Result<Option<String>, SyngError>;
+    async fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError>;
+    async fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError>;
+
+    /// See [`SyngBackend::compare_and_set_root`]
+    async fn compare_and_set_root(
+        &mut self,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        if self.get_root_object_id().await?.as_deref() != expected {
+            return Ok(false);
+        }
+
+        self.set_root_object(new).await?;
+
+        Ok(true)
+    }
+
+    /// See [`SyngBackend::list_refs`]
+    async fn list_refs(&self) -> Result<BTreeMap<String, String>, SyngError> {
+        Ok(self
+            .get_root_object_id()
+            .await?
+            .map(|root_id| (DEFAULT_REF.to_owned(), root_id))
+            .into_iter()
+            .collect())
+    }
+
+    /// See [`SyngBackend::read_ref`]
+    async fn read_ref(&self, name: &str) -> Result<Option<String>, SyngError> {
+        check_ref_name(name)?;
+
+        if name != DEFAULT_REF {
+            return Ok(None);
+        }
+
+        self.get_root_object_id().await
+    }
+
+    /// See [`SyngBackend::compare_and_set_ref`]
+    async fn compare_and_set_ref(
+        &mut self,
+        name: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        if name != DEFAULT_REF {
+            return Err(SyngError::Unsupported("named refs".to_owned()));
+        }
+
+        self.compare_and_set_root(expected, new).await
+    }
+
+    /// See [`SyngBackend::delete_ref`]
+    async fn delete_ref(&mut self, name: &str) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        Err(SyngError::Unsupported("deleting refs".to_owned()))
+    }
+
+    /// See [`SyngBackend::hash_algorithm`]
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::default()
+    }
+
+    /// See [`SyngBackend::object_id`]
+    fn object_id(&self, def: &SyngObjectDef) -> Result<String, SyngError> {
+        Ok(def.get_id(self.hash_algorithm())?.to_string())
+    }
+
+    async fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError>;
+    async fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError>;
+
+    /// See [`SyngBackend::read_objects`]
+    async fn read_objects(&self, ids: &[&str]) -> Result<Vec<Option<SyngObjectDef>>, SyngError> {
+        let mut objects = Vec::with_capacity(ids.len());
+
+        for id in ids {
+            objects.push(self.read_object(id).await?);
+        }
+
+        Ok(objects)
+    }
+
+    /// See [`SyngBackend::write_objects`]
+    async fn write_objects(&mut self, defs: &[SyngObjectDef]) -> Result<Vec<String>, SyngError> {
+        let mut ids = Vec::with_capacity(defs.len());
+
+        for def in defs {
+            ids.push(self.write_object(def).await?);
+        }
+
+        Ok(ids)
+    }
+
+    /// See [`SyngBackend::write_objects_with_ids`]
+    async fn write_objects_with_ids(
+        &mut self,
+        objects: &[(&str, &SyngObjectDef)],
+    ) -> Result<(), SyngError> {
+        let mut defs = Vec::with_capacity(objects.len());
+
+        for (id, def) in objects {
+            if self.object_id(def)? != *id {
+                return Err(SyngError::Unsupported(format!(
+                    "storing {} under an ID of another hash algorithm",
+                    id
+                )));
+            }
+
+            defs.push((*def).clone());
+        }
+
+        self.write_objects(&defs).await?;
+
+        Ok(())
+    }
+
+    /// See [`SyngBackend::list_objects`]
+    async fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        Err(SyngError::Unsupported("listing objects".to_owned()))
+    }
+
+    /// See [`SyngBackend::delete_object`]
+    async fn delete_object(&mut self, _id: &str) -> Result<bool, SyngError> {
+        Err(SyngError::Unsupported("deleting objects".to_owned()))
+    }
+
+    /// See [`SyngBackend::begin_transaction`]
+    async fn begin_transaction(&mut self) -> Result<(), SyngError> {
+        Ok(())
+    }
+
+    async fn commit_transaction(&mut self) -> Result<(), SyngError> {
+        Ok(())
+    }
+
+    async fn rollback_transaction(&mut self) -> Result<(), SyngError> {
+        Ok(())
+    }
+}
+
+/// Wraps a [`SyngBackend`] so it can be used where an [`AsyncSyngBackend`] is expected. The
+/// calls run on the awaiting task, so this is only meant for backends that don't block for long
+/// (like in-memory ones).
+#[derive(Debug, Clone, Default)]
+pub struct SyncBackendAdapter<B: SyngBackend> {
+    backend: B,
+}
+
+impl<B: SyngBackend> SyncBackendAdapter<B> {
+    pub fn new(backend: B) -> Self {
+        Self { backend }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_inner(self) -> B {
+        self.backend
+    }
+}
+
+impl<B: SyngBackend> AsyncSyngBackend for SyncBackendAdapter<B> {
+    async fn has_object(&self, object_id: &str) -> Result<bool, SyngError> {
+        self.backend.has_object(object_id)
+    }
+
+    async fn get_root_object_id(&self) -> Result<Option<String>, SyngError> {
+        self.backend.get_root_object_id()
+    }
+
+    async fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError> {
+        self.backend.get_root_object()
+    }
+
+    async fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError> {
+        self.backend.set_root_object(node_id)
+    }
+
+    async fn compare_and_set_root(
+        &mut self,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        self.backend.compare_and_set_root(expected, new)
+    }
+
+    async fn list_refs(&self) -> Result<BTreeMap<String, String>, SyngError> {
+        self.backend.list_refs()
+    }
+
+    async fn read_ref(&self, name: &str) -> Result<Option<String>, SyngError> {
+        self.backend.read_ref(name)
+    }
+
+    async fn compare_and_set_ref(
+        &mut self,
+        name: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        self.backend.compare_and_set_ref(name, expected, new)
+    }
+
+    async fn delete_ref(&mut self, name: &str) -> Result<bool, SyngError> {
+        self.backend.delete_ref(name)
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.backend.hash_algorithm()
+    }
+
+    async fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError> {
+        self.backend.read_object(id)
+    }
+
+    async fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError> {
+        self.backend.write_object(def)
+    }
+
+    async fn read_objects(&self, ids: &[&str]) -> Result<Vec<Option<SyngObjectDef>>, SyngError> {
+        self.backend.read_objects(ids)
+    }
+
+    async fn write_objects(&mut self, defs: &[SyngObjectDef]) -> Result<Vec<String>, SyngError> {
+        self.backend.write_objects(defs)
+    }
+
+    async fn write_objects_with_ids(
+        &mut self,
+        objects: &[(&str, &SyngObjectDef)],
+    ) -> Result<(), SyngError> {
+        self.backend.write_objects_with_ids(objects)
+    }
+
+    async fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        self.backend.list_objects()
+    }
+
+    async fn delete_object(&mut self, id: &str) -> Result<bool, SyngError> {
+        self.backend.delete_object(id)
+    }
+
+    async fn begin_transaction(&mut self) -> Result<(), SyngError> {
+        self.backend.begin_transaction()
+    }
+
+    async fn commit_transaction(&mut self) -> Result<(), SyngError> {
+        self.backend.commit_transaction()
+    }
+
+    async fn rollback_transaction(&mut self) -> Result<(), SyngError> {
+        self.backend.rollback_transaction()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+    use crate::{
+        commit::{history, CommitInfo},
+        delta::{self as sync_delta, ApplyDeltaOptions, SyngDelta},
+        testing::{block_on, named, MemoryBackend},
+        tree_ops::{self as sync_tree_ops, ChildAdditionPosition, TreeOpOptions, TreeTransaction},
+    };
+
+    type Adapter = SyncBackendAdapter<MemoryBackend>;
+
+    /// The root and the trees and messages of the history. Commit IDs and the order of the
+    /// history are left out, since they depend on when the commits were made.
+    fn state(backend: &MemoryBackend) -> (Option<String>, Vec<(String, String)>) {
+        let mut commits = history(backend)
+            .unwrap()
+            .into_iter()
+            .map(|(_, commit)| (commit.tree, commit.message))
+            .collect::<Vec<_>>();
+        commits.sort();
+
+        (backend.get_root_object_id().unwrap(), commits)
+    }
+
+    /// Runs the sync and the async version of the same operations on copies of `backend`, checking
+    /// that they return the same and leave the backend in the same state
+    fn assert_same<T: PartialEq + Debug>(
+        backend: &MemoryBackend,
+        sync_op: impl FnOnce(&mut MemoryBackend) -> T,
+        async_op: impl FnOnce(&mut Adapter) -> T,
+    ) {
+        let mut sync_backend = backend.clone();
+        let mut async_backend = SyncBackendAdapter::new(backend.clone());
+
+        assert_eq!(sync_op(&mut sync_backend), async_op(&mut async_backend));
+        assert_eq!(state(&sync_backend), state(async_backend.inner()));
+    }
+
+    fn committing(message: &str) -> TreeOpOptions {
+        TreeOpOptions {
+            commit: Some(CommitInfo::new("test", message)),
+        }
+    }
+
+    #[test]
+    fn path_operations_match() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a, b(c))");
+
+        assert_same(
+            &backend,
+            |backend| {
+                use sync_tree_ops::*;
+
+                (
+                    update_object(backend, &[0], &named("edited a")).map(|(id, _)| id),
+                    add_child_object_with_options(
+                        backend,
+                        &[1],
+                        &named("d"),
+                        ChildAdditionPosition::AddAt(0),
+                        &committing("add"),
+                    )
+                    .map(|(id, _)| id),
+                    move_child_object(backend, &[0], &[1], ChildAdditionPosition::AddToEnd),
+                    remove_child_object_with_options(backend, &[0, 0], &committing("remove")),
+                    get_object_at_path(backend, &[0, 1]).map(|(id, _)| id),
+                )
+            },
+            |backend| {
+                use tree_ops::*;
+
+                block_on(async {
+                    (
+                        update_object(backend, &[0], &named("edited a"))
+                            .await
+                            .map(|(id, _)| id),
+                        add_child_object_with_options(
+                            backend,
+                            &[1],
+                            &named("d"),
+                            ChildAdditionPosition::AddAt(0),
+                            &committing("add"),
+                        )
+                        .await
+                        .map(|(id, _)| id),
+                        move_child_object(backend, &[0], &[1], ChildAdditionPosition::AddToEnd)
+                            .await,
+                        remove_child_object_with_options(backend, &[0, 0], &committing("remove"))
+                            .await,
+                        get_object_at_path(backend, &[0, 1]).await.map(|(id, _)| id),
+                    )
+                })
+            },
+        );
+    }
+
+    #[test]
+    fn path_operation_errors_match() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a, b(c))");
+
+        assert_same(
+            &backend,
+            |backend| {
+                use sync_tree_ops::*;
+
+                (
+                    update_object(backend, &[5], &named("x")).map(|(id, _)| id),
+                    add_child_object(backend, &[0], &named("x"), ChildAdditionPosition::AddAt(3))
+                        .map(|(id, _)| id),
+                    remove_child_object(backend, &[]),
+                    move_child_object(backend, &[1], &[1, 0], ChildAdditionPosition::AddToEnd),
+                )
+            },
+            |backend| {
+                use tree_ops::*;
+
+                block_on(async {
+                    (
+                        update_object(backend, &[5], &named("x"))
+                            .await
+                            .map(|(id, _)| id),
+                        add_child_object(
+                            backend,
+                            &[0],
+                            &named("x"),
+                            ChildAdditionPosition::AddAt(3),
+                        )
+                        .await
+                        .map(|(id, _)| id),
+                        remove_child_object(backend, &[]).await,
+                        move_child_object(backend, &[1], &[1, 0], ChildAdditionPosition::AddToEnd)
+                            .await,
+                    )
+                })
+            },
+        );
+    }
+
+    #[test]
+    fn node_id_operations_match() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a, b)");
+
+        let parent = named("parent").with_new_node_id();
+        let child = named("child").with_new_node_id();
+        let parent_id = parent.node_id().unwrap().to_owned();
+        let child_id = child.node_id().unwrap().to_owned();
+
+        sync_tree_ops::add_child_object(
+            &mut backend,
+            &[0],
+            &parent,
+            ChildAdditionPosition::AddToEnd,
+        )
+        .unwrap();
+        sync_tree_ops::add_child_object(
+            &mut backend,
+            &[1],
+            &child,
+            ChildAdditionPosition::AddToEnd,
+        )
+        .unwrap();
+
+        assert_same(
+            &backend,
+            |backend| {
+                use sync_tree_ops::*;
+
+                (
+                    find_node_path(backend, &child_id),
+                    update_object_by_node_id_with_options(
+                        backend,
+                        &child_id,
+                        &named("edited child"),
+                        &committing("edit"),
+                    )
+                    .map(|(id, _)| id),
+                    move_child_object_by_node_id(
+                        backend,
+                        &child_id,
+                        &parent_id,
+                        ChildAdditionPosition::AddToEnd,
+                    ),
+                    remove_child_object_by_node_id(backend, "unknown"),
+                    get_object_by_node_id(backend, &child_id).map(|(id, _)| id),
+                )
+            },
+            |backend| {
+                use tree_ops::*;
+
+                block_on(async {
+                    (
+                        find_node_path(backend, &child_id).await,
+                        update_object_by_node_id_with_options(
+                            backend,
+                            &child_id,
+                            &named("edited child"),
+                            &committing("edit"),
+                        )
+                        .await
+                        .map(|(id, _)| id),
+                        move_child_object_by_node_id(
+                            backend,
+                            &child_id,
+                            &parent_id,
+                            ChildAdditionPosition::AddToEnd,
+                        )
+                        .await,
+                        remove_child_object_by_node_id(backend, "unknown").await,
+                        get_object_by_node_id(backend, &child_id)
+                            .await
+                            .map(|(id, _)| id),
+                    )
+                })
+            },
+        );
+    }
+
+    #[test]
+    fn tree_transactions_match() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a, b(c))");
+
+        let transaction = TreeTransaction::new(&base)
+            .update_object(&[0], &named("edited a"))
+            .add_child_object(&[1], &named("d"), ChildAdditionPosition::AddToEnd)
+            .move_child_object(&[1, 0], &[], ChildAdditionPosition::AddAt(0))
+            .remove_child_object(&[1]);
+
+        let failing = TreeTransaction::new(&base).remove_child_object(&[7]);
+        let stale = TreeTransaction::new(&backend.write_tree("old root"));
+
+        assert_same(
+            &backend,
+            |backend| {
+                (
+                    transaction
+                        .clone()
+                        .commit_with_options(backend, &committing("transaction")),
+                    failing.clone().commit(backend),
+                    stale.clone().commit(backend),
+                )
+            },
+            |backend| {
+                use tree_ops::*;
+
+                block_on(async {
+                    (
+                        commit_tree_transaction_with_options(
+                            backend,
+                            transaction.clone(),
+                            &committing("transaction"),
+                        )
+                        .await,
+                        commit_tree_transaction(backend, failing.clone()).await,
+                        commit_tree_transaction(backend, stale.clone()).await,
+                    )
+                })
+            },
+        );
+    }
+
+    /// The delta as what the sync and async versions are compared on
+    fn delta_summary(delta: &SyngDelta) -> (Option<String>, String, Vec<String>) {
+        let mut ids = delta.new_objects.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+
+        (delta.start_point.clone(), delta.new_root_node.clone(), ids)
+    }
+
+    #[test]
+    fn generated_deltas_match() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a, b(c))");
+        backend.set_tree("root(a, b(c, d), e)");
+
+        assert_same(
+            &backend,
+            |backend| {
+                sync_delta::generate_delta_from_point(backend, &base).map(|d| delta_summary(&d))
+            },
+            |backend| {
+                block_on(delta::generate_delta_from_point(backend, &base))
+                    .map(|d| delta_summary(&d))
+            },
+        );
+    }
+
+    #[test]
+    fn applying_deltas_matches() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a)");
+
+        let mut local = backend.clone();
+        local.set_tree("root(a, b(c))");
+        let delta = sync_delta::generate_delta_from_point(&local, &base).unwrap();
+
+        let mut tampered = delta.clone();
+        let b = backend.object_id(&named("b")).unwrap();
+        tampered.new_objects.insert(b, named("not b"));
+
+        let options = ApplyDeltaOptions {
+            commit: Some(CommitInfo::new("test", "delta")),
+            ..ApplyDeltaOptions::default()
+        };
+
+        // The errors aren't comparable, their debug output is
+        let summary = |result: Result<(String, SyngObjectDef), _>| {
+            result.map(|(id, _)| id).map_err(|e| format!("{:?}", e))
+        };
+
+        assert_same(
+            &backend,
+            |backend| {
+                (
+                    summary(sync_delta::apply_delta(backend, &tampered)),
+                    summary(sync_delta::apply_delta_with_options(
+                        backend, &delta, &options,
+                    )),
+                    // The root moved away from the start point of the delta
+                    summary(sync_delta::apply_delta(backend, &delta)),
+                )
+            },
+            |backend| {
+                block_on(async {
+                    (
+                        summary(delta::apply_delta(backend, &tampered).await),
+                        summary(delta::apply_delta_with_options(backend, &delta, &options).await),
+                        summary(delta::apply_delta(backend, &delta).await),
+                    )
+                })
+            },
+        );
+    }
+}
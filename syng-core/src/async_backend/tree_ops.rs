@@ -0,0 +1,514 @@
+//! Async versions of the functions in [`crate::tree_ops`]
+
+use std::collections::HashSet;
+
+use crate::{
+    error::SyngError,
+    objects::SyngObjectDef,
+    tree_ops::{
+        addition_index, check_move, keep_node_id, next_child_id, plan_move,
+        transaction::{ApplyStep, TxTree},
+        ChildAdditionPosition, TreeOpError, TreeOpOptions, TreeTransaction,
+    },
+};
+
+use super::{commit::record_commit, AsyncSyngBackend};
+
+pub(crate) async fn read_referred_object(
+    backend: &impl AsyncSyngBackend,
+    id: &str,
+) -> Result<SyngObjectDef, SyngError> {
+    backend
+        .read_object(id)
+        .await?
+        .ok_or_else(|| SyngError::ObjectNotFound(id.to_owned()))
+}
+
+pub(crate) async fn read_referred_objects(
+    backend: &impl AsyncSyngBackend,
+    ids: &[String],
+) -> Result<Vec<SyngObjectDef>, SyngError> {
+    let id_refs = ids.iter().map(String::as_str).collect::<Vec<_>>();
+
+    backend
+        .read_objects(&id_refs)
+        .await?
+        .into_iter()
+        .zip(ids)
+        .map(|(obj, id)| obj.ok_or_else(|| SyngError::ObjectNotFound(id.clone())))
+        .collect()
+}
+
+pub async fn get_object_at_path(
+    backend: &impl AsyncSyngBackend,
+    obj_path: &[usize],
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let mut objs = get_objects_along_index_path(backend, obj_path).await?;
+
+    Ok(objs.pop().unwrap())
+}
+
+pub async fn find_node_path(
+    backend: &impl AsyncSyngBackend,
+    node_id: &str,
+) -> Result<Option<Vec<usize>>, TreeOpError> {
+    let Some(root_id) = backend.get_root_object_id().await? else {
+        return Ok(None);
+    };
+
+    let mut visited = HashSet::from([root_id.clone()]);
+    let mut level = vec![(vec![], root_id)];
+
+    while !level.is_empty() {
+        let ids = level.iter().map(|(_, id)| id.clone()).collect::<Vec<_>>();
+        let objs = read_referred_objects(backend, &ids).await?;
+
+        let mut next_level = vec![];
+
+        for ((path, _), obj) in level.into_iter().zip(objs) {
+            if obj.node_id() == Some(node_id) {
+                return Ok(Some(path));
+            }
+
+            for (index, child_id) in obj.children.into_iter().enumerate() {
+                if visited.insert(child_id.clone()) {
+                    next_level.push(([&path[..], &[index]].concat(), child_id));
+                }
+            }
+        }
+
+        level = next_level;
+    }
+
+    Ok(None)
+}
+
+async fn node_path(
+    backend: &impl AsyncSyngBackend,
+    node_id: &str,
+) -> Result<Vec<usize>, TreeOpError> {
+    find_node_path(backend, node_id)
+        .await?
+        .ok_or_else(|| TreeOpError::NodeNotFound(node_id.to_owned()))
+}
+
+pub async fn get_object_by_node_id(
+    backend: &impl AsyncSyngBackend,
+    node_id: &str,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let obj_path = node_path(backend, node_id).await?;
+
+    get_object_at_path(backend, &obj_path).await
+}
+
+async fn get_objects_along_index_path(
+    backend: &impl AsyncSyngBackend,
+    obj_path: &[usize],
+) -> Result<Vec<(String, SyngObjectDef)>, TreeOpError> {
+    let root_id = backend
+        .get_root_object_id()
+        .await?
+        .ok_or(SyngError::NoRootObject)?;
+
+    let root_obj = read_referred_object(backend, &root_id).await?;
+
+    let mut objs = Vec::with_capacity(obj_path.len() + 1);
+
+    objs.push((root_id, root_obj));
+
+    for depth in 0..obj_path.len() {
+        let curr_obj = &objs.last().unwrap().1;
+
+        let index_obj_id = next_child_id(curr_obj, obj_path, depth)?.clone();
+        let index_obj = read_referred_object(backend, &index_obj_id).await?;
+
+        objs.push((index_obj_id, index_obj));
+    }
+
+    Ok(objs)
+}
+
+async fn get_descendent_id_object_pairs(
+    backend: &impl AsyncSyngBackend,
+    id: &str,
+) -> Result<Vec<(String, SyngObjectDef)>, SyngError> {
+    let mut result = vec![];
+
+    let mut level = vec![id.to_owned()];
+
+    while !level.is_empty() {
+        let objs = read_referred_objects(backend, &level).await?;
+
+        let next_level = objs
+            .iter()
+            .flat_map(|obj| obj.children.iter().cloned())
+            .collect();
+
+        result.extend(level.into_iter().zip(objs));
+
+        level = next_level;
+    }
+
+    Ok(result)
+}
+
+pub(crate) async fn descendent_object_ids(
+    backend: &impl AsyncSyngBackend,
+    id: &str,
+) -> Result<Vec<String>, SyngError> {
+    Ok(get_descendent_id_object_pairs(backend, id)
+        .await?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect())
+}
+
+pub async fn get_descendent_object_ids(
+    backend: &impl AsyncSyngBackend,
+    id: &str,
+) -> Result<Vec<String>, TreeOpError> {
+    Ok(descendent_object_ids(backend, id).await?)
+}
+
+pub async fn get_descendent_objects(
+    backend: &impl AsyncSyngBackend,
+    id: &str,
+) -> Result<Vec<SyngObjectDef>, TreeOpError> {
+    Ok(get_descendent_id_object_pairs(backend, id)
+        .await?
+        .into_iter()
+        .map(|(_, obj)| obj)
+        .collect())
+}
+
+/// Writes the ancestors of a changed object with `obj_path` pointing to their new child and moves
+/// the root to the rewritten root
+async fn rewrite_ancestors(
+    backend: &mut impl AsyncSyngBackend,
+    ancestor_objs: &[(String, SyngObjectDef)],
+    obj_path: &[usize],
+    new_obj_id: String,
+    options: &TreeOpOptions,
+) -> Result<(), SyngError> {
+    let mut last_obj_id = new_obj_id;
+
+    for (index, (_, obj)) in ancestor_objs.iter().enumerate().rev() {
+        let mut new_obj = obj.clone();
+
+        new_obj.children[obj_path[index]] = last_obj_id;
+
+        last_obj_id = backend.write_object(&new_obj).await?;
+    }
+
+    backend.set_root_object(&last_obj_id).await?;
+
+    if let Some(info) = &options.commit {
+        record_commit(backend, &last_obj_id, info).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn update_object(
+    backend: &mut impl AsyncSyngBackend,
+    obj_path: &[usize],
+    new_def: &SyngObjectDef,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    update_object_with_options(backend, obj_path, new_def, &TreeOpOptions::default()).await
+}
+
+pub async fn update_object_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    obj_path: &[usize],
+    new_def: &SyngObjectDef,
+    options: &TreeOpOptions,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let ancestor_objs = get_objects_along_index_path(backend, obj_path).await?;
+
+    let (_, old_obj) = ancestor_objs.last().unwrap();
+    let new_def = &keep_node_id(old_obj, new_def);
+
+    let hash = backend.write_object(new_def).await?;
+
+    // Skip the last one because it is the actual object
+    let ancestors = &ancestor_objs[..ancestor_objs.len() - 1];
+
+    rewrite_ancestors(backend, ancestors, obj_path, hash.clone(), options).await?;
+
+    Ok((hash, new_def.clone()))
+}
+
+pub async fn add_child_object(
+    backend: &mut impl AsyncSyngBackend,
+    parent_obj_path: &[usize],
+    new_def: &SyngObjectDef,
+    position: ChildAdditionPosition,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    add_child_object_with_options(
+        backend,
+        parent_obj_path,
+        new_def,
+        position,
+        &TreeOpOptions::default(),
+    )
+    .await
+}
+
+pub async fn add_child_object_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    parent_obj_path: &[usize],
+    new_def: &SyngObjectDef,
+    position: ChildAdditionPosition,
+    options: &TreeOpOptions,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let ancestor_objs = get_objects_along_index_path(backend, parent_obj_path).await?;
+
+    let [ancestors @ .., (_, direct_parent_obj)] = ancestor_objs.as_slice() else {
+        unreachable!("the root is always along the path");
+    };
+
+    let index = addition_index(parent_obj_path, direct_parent_obj.children.len(), position)?;
+
+    let hash = backend.write_object(new_def).await?;
+
+    let mut new_parent = direct_parent_obj.clone();
+
+    new_parent.children.insert(index, hash.clone());
+
+    let new_parent_id = backend.write_object(&new_parent).await?;
+
+    rewrite_ancestors(backend, ancestors, parent_obj_path, new_parent_id, options).await?;
+
+    Ok((hash, new_def.clone()))
+}
+
+pub async fn remove_child_object(
+    backend: &mut impl AsyncSyngBackend,
+    obj_path: &[usize],
+) -> Result<(), TreeOpError> {
+    remove_child_object_with_options(backend, obj_path, &TreeOpOptions::default()).await
+}
+
+pub async fn remove_child_object_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    obj_path: &[usize],
+    options: &TreeOpOptions,
+) -> Result<(), TreeOpError> {
+    let ancestor_objs = get_objects_along_index_path(backend, obj_path).await?;
+
+    // Since we are removing a child object, we have to make sure atleast 2 objects are there in the
+    // path (the root and the given object)
+    let [remaining_ancestors @ .., (_, parent_obj), _] = ancestor_objs.as_slice() else {
+        return Err(TreeOpError::RootHasNoParent);
+    };
+
+    let mut new_parent_obj = parent_obj.clone();
+
+    new_parent_obj.children.remove(*obj_path.last().unwrap());
+
+    let new_parent_id = backend.write_object(&new_parent_obj).await?;
+
+    rewrite_ancestors(
+        backend,
+        remaining_ancestors,
+        obj_path,
+        new_parent_id,
+        options,
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn move_child_object(
+    backend: &mut impl AsyncSyngBackend,
+    from_path: &[usize],
+    to_parent_path: &[usize],
+    position: ChildAdditionPosition,
+) -> Result<Vec<usize>, TreeOpError> {
+    move_child_object_with_options(
+        backend,
+        from_path,
+        to_parent_path,
+        position,
+        &TreeOpOptions::default(),
+    )
+    .await
+}
+
+pub async fn move_child_object_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    from_path: &[usize],
+    to_parent_path: &[usize],
+    position: ChildAdditionPosition,
+    options: &TreeOpOptions,
+) -> Result<Vec<usize>, TreeOpError> {
+    check_move(from_path, to_parent_path)?;
+
+    let from_objs = get_objects_along_index_path(backend, from_path).await?;
+    let to_objs = get_objects_along_index_path(backend, to_parent_path).await?;
+
+    let plan = plan_move(
+        from_objs,
+        to_objs,
+        from_path,
+        to_parent_path,
+        position,
+        backend.hash_algorithm(),
+    )?;
+
+    backend.write_objects(&plan.objects).await?;
+    backend.set_root_object(&plan.root_id).await?;
+
+    if let Some(info) = &options.commit {
+        record_commit(backend, &plan.root_id, info).await?;
+    }
+
+    Ok(plan.new_path)
+}
+
+/// Async version of [`TreeTransaction::commit`]
+pub async fn commit_tree_transaction(
+    backend: &mut impl AsyncSyngBackend,
+    transaction: TreeTransaction,
+) -> Result<String, TreeOpError> {
+    commit_tree_transaction_with_options(backend, transaction, &TreeOpOptions::default()).await
+}
+
+pub async fn commit_tree_transaction_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    transaction: TreeTransaction,
+    options: &TreeOpOptions,
+) -> Result<String, TreeOpError> {
+    let mut tree = TxTree::new(&transaction.base_root);
+
+    for op in &transaction.operations {
+        loop {
+            match tree.apply(op) {
+                ApplyStep::Read { path, id } => {
+                    let obj = read_referred_object(backend, &id).await?;
+
+                    tree.load(&path, obj);
+                }
+                ApplyStep::Done => break,
+                ApplyStep::Failed(e) => return Err(e),
+            }
+        }
+    }
+
+    let (root_id, objects) = tree.into_objects(backend.hash_algorithm())?;
+
+    backend.write_objects(&objects).await?;
+
+    if !backend
+        .compare_and_set_root(Some(&transaction.base_root), &root_id)
+        .await?
+    {
+        return Err(TreeOpError::RootMoved {
+            expected: transaction.base_root,
+            actual: backend.get_root_object_id().await?,
+        });
+    }
+
+    if let Some(info) = &options.commit {
+        record_commit(backend, &root_id, info).await?;
+    }
+
+    Ok(root_id)
+}
+
+/// See [`crate::tree_ops::update_object_by_node_id`], which also describes the cost of finding
+/// nodes by ID
+pub async fn update_object_by_node_id(
+    backend: &mut impl AsyncSyngBackend,
+    node_id: &str,
+    new_def: &SyngObjectDef,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    update_object_by_node_id_with_options(backend, node_id, new_def, &TreeOpOptions::default())
+        .await
+}
+
+pub async fn update_object_by_node_id_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    node_id: &str,
+    new_def: &SyngObjectDef,
+    options: &TreeOpOptions,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let obj_path = node_path(backend, node_id).await?;
+
+    update_object_with_options(backend, &obj_path, new_def, options).await
+}
+
+pub async fn add_child_object_by_node_id(
+    backend: &mut impl AsyncSyngBackend,
+    parent_node_id: &str,
+    new_def: &SyngObjectDef,
+    position: ChildAdditionPosition,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    add_child_object_by_node_id_with_options(
+        backend,
+        parent_node_id,
+        new_def,
+        position,
+        &TreeOpOptions::default(),
+    )
+    .await
+}
+
+pub async fn add_child_object_by_node_id_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    parent_node_id: &str,
+    new_def: &SyngObjectDef,
+    position: ChildAdditionPosition,
+    options: &TreeOpOptions,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let parent_obj_path = node_path(backend, parent_node_id).await?;
+
+    add_child_object_with_options(backend, &parent_obj_path, new_def, position, options).await
+}
+
+pub async fn remove_child_object_by_node_id(
+    backend: &mut impl AsyncSyngBackend,
+    node_id: &str,
+) -> Result<(), TreeOpError> {
+    remove_child_object_by_node_id_with_options(backend, node_id, &TreeOpOptions::default()).await
+}
+
+pub async fn remove_child_object_by_node_id_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    node_id: &str,
+    options: &TreeOpOptions,
+) -> Result<(), TreeOpError> {
+    let obj_path = node_path(backend, node_id).await?;
+
+    remove_child_object_with_options(backend, &obj_path, options).await
+}
+
+pub async fn move_child_object_by_node_id(
+    backend: &mut impl AsyncSyngBackend,
+    node_id: &str,
+    to_parent_node_id: &str,
+    position: ChildAdditionPosition,
+) -> Result<Vec<usize>, TreeOpError> {
+    move_child_object_by_node_id_with_options(
+        backend,
+        node_id,
+        to_parent_node_id,
+        position,
+        &TreeOpOptions::default(),
+    )
+    .await
+}
+
+pub async fn move_child_object_by_node_id_with_options(
+    backend: &mut impl AsyncSyngBackend,
+    node_id: &str,
+    to_parent_node_id: &str,
+    position: ChildAdditionPosition,
+    options: &TreeOpOptions,
+) -> Result<Vec<usize>, TreeOpError> {
+    let from_path = node_path(backend, node_id).await?;
+    let to_parent_path = node_path(backend, to_parent_node_id).await?;
+
+    move_child_object_with_options(backend, &from_path, &to_parent_path, position, options).await
+}
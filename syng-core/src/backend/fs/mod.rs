@@ -0,0 +1,656 @@
+//! Backend storing the objects on disk, laid out like git's loose objects:
+//!
+//! ```text
+//! <dir>/HEAD              id of the root object (the default ref)
+//! <dir>/refs/draft/alice  id the ref "draft/alice" points to
+//! <dir>/objects/ab/cdef.. CBOR encoded object with the id "abcdef.."
+//! <dir>/tmp/              files being written, renamed into place once complete
+//! ```
+//!
+//! IDs of algorithms other than SHA-256 start with the algorithm code (see [`ObjectId`]), which is
+//! kept in the directory name along with the first two digits of the digest so that the objects
+//! still spread over the directories (`<dir>/objects/1e20ab/cdef..` for a BLAKE3 ID).
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use ciborium::{de::from_reader, ser::into_writer};
+
+use crate::{
+    error::SyngError,
+    objects::{HashAlgorithm, ObjectId, SyngObjectDef},
+};
+
+use super::{check_ref_name, is_valid_ref_name, SyngBackend, DEFAULT_REF};
+
+const HEAD_FILE: &str = "HEAD";
+const REFS_DIR: &str = "refs";
+const OBJECTS_DIR: &str = "objects";
+const TMP_DIR: &str = "tmp";
+
+/// Used to give temp files unique names within the process
+static TMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn storage_error(e: std::io::Error) -> SyngError {
+    SyngError::StorageFailed(e.to_string())
+}
+
+/// IDs are turned into paths, so only valid IDs are accepted. This keeps IDs like `../HEAD` from
+/// reaching outside the objects directory.
+fn is_valid_object_id(id: &str) -> bool {
+    id.parse::<ObjectId>().is_ok()
+}
+
+/// Length of the directory name the object is stored in, the algorithm prefix of the ID (if any)
+/// and the first two digits of the digest
+fn fan_out_len(id: &ObjectId) -> usize {
+    id.to_string().len() - id.digest().len() * 2 + 2
+}
+
+/// What [`FsBackend::update_ref`] should do with the ref
+enum RefChange {
+    Keep,
+    Set(String),
+    Delete,
+}
+
+#[derive(Debug, Clone)]
+pub struct FsBackend {
+    dir: PathBuf,
+    hash_algorithm: HashAlgorithm,
+}
+
+impl FsBackend {
+    /// Opens the store at `dir`, creating the directories if they don't exist yet
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, SyngError> {
+        let dir = dir.into();
+
+        fs::create_dir_all(dir.join(OBJECTS_DIR)).map_err(storage_error)?;
+        fs::create_dir_all(dir.join(TMP_DIR)).map_err(storage_error)?;
+
+        Ok(Self {
+            dir,
+            hash_algorithm: HashAlgorithm::default(),
+        })
+    }
+
+    /// Hashes the objects written from now on with `algorithm`, see
+    /// [`SyngBackend::hash_algorithm`]
+    pub fn with_hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = algorithm;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the object file, `id` has to be a valid ID
+    fn object_path(&self, id: &str) -> PathBuf {
+        let (fan_out, rest) = id.split_at(fan_out_len(&id.parse().unwrap()));
+
+        self.dir.join(OBJECTS_DIR).join(fan_out).join(rest)
+    }
+
+    /// Writes `data` to a temp file and renames it to `path`, so readers either see the old file
+    /// or the complete new one
+    fn write_atomically(&self, path: &Path, data: &[u8]) -> Result<(), SyngError> {
+        let tmp_path = self.dir.join(TMP_DIR).join(format!(
+            "{}-{}",
+            std::process::id(),
+            TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let result = File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, path));
+
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp_path);
+
+            return Err(storage_error(e));
+        }
+
+        Ok(())
+    }
+
+    /// Stores the object under `id`, which has to be a valid ID
+    fn write_object_file(&self, id: &str, def: &SyngObjectDef) -> Result<(), SyngError> {
+        let path = self.object_path(id);
+
+        // Objects are stored under their hash, so an existing file already has this content
+        if path.is_file() {
+            return Ok(());
+        }
+
+        let mut data = vec![];
+
+        into_writer(def, &mut data).map_err(|e| SyngError::EncodeFailed(e.to_string()))?;
+
+        fs::create_dir_all(path.parent().unwrap()).map_err(storage_error)?;
+
+        self.write_atomically(&path, &data)
+    }
+
+    fn ref_path(&self, name: &str) -> PathBuf {
+        if name == DEFAULT_REF {
+            return self.dir.join(HEAD_FILE);
+        }
+
+        self.dir.join(REFS_DIR).join(name)
+    }
+
+    fn read_ref_file(path: &Path) -> Result<Option<String>, SyngError> {
+        match fs::read_to_string(path) {
+            Ok(id) => Ok(Some(id.trim().to_owned())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+
+    /// Adds the refs under `dir` to `refs`, with their names prefixed by `prefix`
+    fn collect_refs(
+        dir: &Path,
+        prefix: &str,
+        refs: &mut BTreeMap<String, String>,
+    ) -> Result<(), SyngError> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(storage_error(e)),
+        };
+
+        for entry in entries {
+            let entry = entry.map_err(storage_error)?;
+
+            // Anything that isn't a valid ref name wasn't written by us (or is a lock file)
+            let Some(ref_name) = entry
+                .file_name()
+                .to_str()
+                .map(|name| format!("{}{}", prefix, name))
+            else {
+                continue;
+            };
+
+            if !is_valid_ref_name(&ref_name) {
+                continue;
+            }
+
+            if entry.file_type().map_err(storage_error)?.is_dir() {
+                Self::collect_refs(&entry.path(), &format!("{}/", ref_name), refs)?;
+            } else if let Some(id) = Self::read_ref_file(&entry.path())? {
+                refs.insert(ref_name, id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `update` with the current value of the ref while holding its lock file, and applies
+    /// the change it returns. Returns whether the ref was changed.
+    ///
+    /// The lock file (`<ref>.lock`) is created exclusively, so only one writer (in any process)
+    /// can hold it. It is renamed over the ref to set the new value, the same way git updates its
+    /// refs.
+    fn update_ref(
+        &self,
+        name: &str,
+        update: impl FnOnce(Option<String>) -> RefChange,
+    ) -> Result<bool, SyngError> {
+        let ref_path = self.ref_path(name);
+        let lock_path = ref_path.with_file_name(format!(
+            "{}.lock",
+            ref_path.file_name().unwrap().to_string_lossy()
+        ));
+
+        fs::create_dir_all(ref_path.parent().unwrap()).map_err(storage_error)?;
+
+        let mut lock_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(SyngError::StorageFailed(format!(
+                    "{} exists, another writer is updating the ref (or crashed while doing so)",
+                    lock_path.display()
+                )))
+            }
+            Err(e) => return Err(storage_error(e)),
+        };
+
+        let current = match Self::read_ref_file(&ref_path) {
+            Ok(current) => current,
+            Err(e) => {
+                let _ = fs::remove_file(&lock_path);
+
+                return Err(e);
+            }
+        };
+
+        let result = match update(current.clone()) {
+            RefChange::Keep => Ok(false),
+            RefChange::Set(new) => {
+                let renamed = lock_file
+                    .write_all(format!("{}\n", new).as_bytes())
+                    .and_then(|_| lock_file.sync_all())
+                    .and_then(|_| fs::rename(&lock_path, &ref_path));
+
+                // Once renamed the lock file is the ref, and removing the lock path then could
+                // remove the lock of another writer
+                if renamed.is_ok() {
+                    return Ok(true);
+                }
+
+                renamed.map(|_| true).map_err(storage_error)
+            }
+            RefChange::Delete if current.is_none() => Ok(false),
+            RefChange::Delete => fs::remove_file(&ref_path)
+                .map(|_| true)
+                .map_err(storage_error),
+        };
+
+        let _ = fs::remove_file(&lock_path);
+
+        result
+    }
+}
+
+impl SyngBackend for FsBackend {
+    fn has_object(&self, object_id: &str) -> Result<bool, SyngError> {
+        Ok(is_valid_object_id(object_id) && self.object_path(object_id).is_file())
+    }
+
+    fn get_root_object_id(&self) -> Result<Option<String>, SyngError> {
+        self.read_ref(DEFAULT_REF)
+    }
+
+    fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError> {
+        let Some(root_id) = self.get_root_object_id()? else {
+            return Ok(None);
+        };
+
+        self.read_object(&root_id)
+    }
+
+    fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError> {
+        if !self.has_object(node_id)? {
+            return Err(SyngError::ObjectNotFound(node_id.to_owned()));
+        }
+
+        self.update_ref(DEFAULT_REF, |_| RefChange::Set(node_id.to_owned()))?;
+
+        Ok(())
+    }
+
+    fn compare_and_set_root(
+        &mut self,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        self.compare_and_set_ref(DEFAULT_REF, expected, new)
+    }
+
+    fn list_refs(&self) -> Result<BTreeMap<String, String>, SyngError> {
+        let mut refs = BTreeMap::new();
+
+        if let Some(root_id) = self.get_root_object_id()? {
+            refs.insert(DEFAULT_REF.to_owned(), root_id);
+        }
+
+        Self::collect_refs(&self.dir.join(REFS_DIR), "", &mut refs)?;
+
+        Ok(refs)
+    }
+
+    fn read_ref(&self, name: &str) -> Result<Option<String>, SyngError> {
+        check_ref_name(name)?;
+
+        Self::read_ref_file(&self.ref_path(name))
+    }
+
+    fn compare_and_set_ref(
+        &mut self,
+        name: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        if !self.has_object(new)? {
+            return Err(SyngError::ObjectNotFound(new.to_owned()));
+        }
+
+        self.update_ref(name, |current| {
+            if current.as_deref() == expected {
+                RefChange::Set(new.to_owned())
+            } else {
+                RefChange::Keep
+            }
+        })
+    }
+
+    fn delete_ref(&mut self, name: &str) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        self.update_ref(name, |_| RefChange::Delete)
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError> {
+        if !is_valid_object_id(id) {
+            return Ok(None);
+        }
+
+        let file = match File::open(self.object_path(id)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(storage_error(e)),
+        };
+
+        from_reader(file)
+            .map(Some)
+            .map_err(|e| SyngError::DecodeFailed(e.to_string()))
+    }
+
+    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError> {
+        let hash = self.object_id(def)?;
+
+        self.write_object_file(&hash, def)?;
+
+        Ok(hash)
+    }
+
+    fn write_objects_with_ids(
+        &mut self,
+        objects: &[(&str, &SyngObjectDef)],
+    ) -> Result<(), SyngError> {
+        for (id, def) in objects {
+            if !is_valid_object_id(id) {
+                return Err(SyngError::InvalidObjectId((*id).to_owned()));
+            }
+
+            self.write_object_file(id, def)?;
+        }
+
+        Ok(())
+    }
+
+    /// There are no transactions to hold off other writers, so objects another process wrote but
+    /// hasn't pointed a ref to yet are listed too (and can be collected by [`crate::gc`])
+    fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        let mut ids = vec![];
+
+        for fan_out_dir in fs::read_dir(self.dir.join(OBJECTS_DIR)).map_err(storage_error)? {
+            let fan_out_dir = fan_out_dir.map_err(storage_error)?;
+
+            let Some(fan_out) = fan_out_dir.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+
+            if !fan_out_dir.file_type().map_err(storage_error)?.is_dir() {
+                continue;
+            }
+
+            for entry in fs::read_dir(fan_out_dir.path()).map_err(storage_error)? {
+                let entry = entry.map_err(storage_error)?;
+
+                let Some(id) = entry
+                    .file_name()
+                    .to_str()
+                    .map(|rest| format!("{}{}", fan_out, rest))
+                else {
+                    continue;
+                };
+
+                // Files in the wrong directory wouldn't be found under their ID
+                if id
+                    .parse::<ObjectId>()
+                    .is_ok_and(|object_id| fan_out_len(&object_id) == fan_out.len())
+                {
+                    ids.push(id);
+                }
+            }
+        }
+
+        Ok(ids)
+    }
+
+    fn delete_object(&mut self, id: &str) -> Result<bool, SyngError> {
+        if !is_valid_object_id(id) {
+            return Ok(false);
+        }
+
+        match fs::remove_file(self.object_path(id)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(storage_error(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::named;
+
+    /// Directory under the system temp dir that is removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "syng-fs-test-{}-{}",
+                std::process::id(),
+                TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn backend(dir: &TempDir) -> FsBackend {
+        FsBackend::open(&dir.0).unwrap()
+    }
+
+    #[test]
+    fn objects_round_trip() {
+        let dir = TempDir::new();
+        let mut backend = backend(&dir);
+
+        let sha256_id = backend.write_object(&named("a")).unwrap();
+        let mut backend = backend.with_hash_algorithm(HashAlgorithm::Blake3);
+        let blake3_id = backend.write_object(&named("b")).unwrap();
+
+        assert!(dir.0.join(OBJECTS_DIR).join(&sha256_id[..2]).is_dir());
+        assert!(dir.0.join(OBJECTS_DIR).join(&blake3_id[..6]).is_dir());
+
+        // Written atomically, nothing is left behind in the temp dir
+        assert_eq!(fs::read_dir(dir.0.join(TMP_DIR)).unwrap().count(), 0);
+
+        // Read back by a backend that didn't write them
+        let backend = FsBackend::open(&dir.0).unwrap();
+
+        for (id, name) in [(&sha256_id, "a"), (&blake3_id, "b")] {
+            let obj = backend.read_object(id).unwrap().unwrap();
+
+            assert_eq!(obj.fields["name"].as_str(), Some(name));
+            assert_eq!(obj.get_hash_like(id).unwrap(), *id);
+            assert!(backend.has_object(id).unwrap());
+        }
+
+        let missing = named("missing").get_hash().unwrap();
+        assert!(backend.read_object(&missing).unwrap().is_none());
+        assert!(!backend.has_object(&missing).unwrap());
+    }
+
+    #[test]
+    fn invalid_object_ids_are_not_paths() {
+        let dir = TempDir::new();
+        let mut backend = backend(&dir);
+        let obj = named("a");
+
+        assert!(backend.read_object("../HEAD").unwrap().is_none());
+        assert!(!backend.has_object("../HEAD").unwrap());
+        assert!(!backend.delete_object("../HEAD").unwrap());
+        assert_eq!(
+            backend.write_objects_with_ids(&[("../HEAD", &obj)]),
+            Err(SyngError::InvalidObjectId("../HEAD".to_owned()))
+        );
+    }
+
+    #[test]
+    fn lists_the_objects_of_every_fan_out_directory() {
+        let dir = TempDir::new();
+        let mut backend = backend(&dir);
+
+        let mut ids = (0..20)
+            .map(|i| backend.write_object(&named(&i.to_string())).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut backend = backend.with_hash_algorithm(HashAlgorithm::Blake3);
+        ids.push(backend.write_object(&named("blake3")).unwrap());
+
+        // Files that aren't objects, or are in the wrong directory, aren't listed
+        let stray_dir = dir.0.join(OBJECTS_DIR).join(&ids[0][..2]);
+        fs::write(stray_dir.join("not-an-id"), b"").unwrap();
+        fs::write(dir.0.join(OBJECTS_DIR).join("stray"), b"").unwrap();
+
+        let misplaced = named("misplaced").get_hash().unwrap();
+        fs::write(stray_dir.join(&misplaced), b"").unwrap();
+
+        let mut listed = backend.list_objects().unwrap();
+        listed.sort();
+        ids.sort();
+
+        assert_eq!(listed, ids);
+
+        assert!(backend.delete_object(&ids[0]).unwrap());
+        assert!(!backend.delete_object(&ids[0]).unwrap());
+        assert_eq!(backend.list_objects().unwrap().len(), ids.len() - 1);
+    }
+
+    #[test]
+    fn refs_are_compared_and_set() {
+        let dir = TempDir::new();
+        let mut backend = backend(&dir);
+        let a = backend.write_object(&named("a")).unwrap();
+        let b = backend.write_object(&named("b")).unwrap();
+
+        assert!(backend
+            .compare_and_set_ref("draft/alice", None, &a)
+            .unwrap());
+        assert!(!backend
+            .compare_and_set_ref("draft/alice", None, &b)
+            .unwrap());
+        assert!(!backend
+            .compare_and_set_ref("draft/alice", Some(&b), &b)
+            .unwrap());
+        assert_eq!(backend.read_ref("draft/alice").unwrap(), Some(a.clone()));
+
+        assert!(backend
+            .compare_and_set_ref("draft/alice", Some(&a), &b)
+            .unwrap());
+        assert!(backend.compare_and_set_root(None, &a).unwrap());
+
+        assert_eq!(
+            backend.list_refs().unwrap(),
+            BTreeMap::from([
+                (DEFAULT_REF.to_owned(), a.clone()),
+                ("draft/alice".to_owned(), b.clone()),
+            ])
+        );
+
+        assert!(backend.delete_ref("draft/alice").unwrap());
+        assert!(!backend.delete_ref("draft/alice").unwrap());
+        assert_eq!(backend.read_ref("draft/alice").unwrap(), None);
+
+        let missing = named("missing").get_hash().unwrap();
+        assert_eq!(
+            backend.compare_and_set_ref("draft/alice", None, &missing),
+            Err(SyngError::ObjectNotFound(missing))
+        );
+    }
+
+    #[test]
+    fn stale_locks_block_ref_updates() {
+        let dir = TempDir::new();
+        let mut backend = backend(&dir);
+        let a = backend.write_object(&named("a")).unwrap();
+        let b = backend.write_object(&named("b")).unwrap();
+
+        backend.compare_and_set_ref("main", None, &a).unwrap();
+
+        // Left behind by a writer that crashed
+        let lock_path = dir.0.join(REFS_DIR).join("main.lock");
+        fs::write(&lock_path, b"").unwrap();
+
+        assert!(matches!(
+            backend.compare_and_set_ref("main", Some(&a), &b),
+            Err(SyngError::StorageFailed(_))
+        ));
+        assert!(matches!(
+            backend.delete_ref("main"),
+            Err(SyngError::StorageFailed(_))
+        ));
+
+        // The lock belongs to whoever created it, so it is left alone
+        assert!(lock_path.is_file());
+        assert_eq!(backend.read_ref("main").unwrap(), Some(a.clone()));
+        assert_eq!(
+            backend.list_refs().unwrap(),
+            BTreeMap::from([("main".to_owned(), a.clone())])
+        );
+
+        fs::remove_file(&lock_path).unwrap();
+
+        assert!(backend.compare_and_set_ref("main", Some(&a), &b).unwrap());
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn invalid_ref_names_are_rejected() {
+        let dir = TempDir::new();
+        let mut backend = backend(&dir);
+        let a = backend.write_object(&named("a")).unwrap();
+
+        for name in [
+            "",
+            "../HEAD",
+            "draft/../../x",
+            "main.lock",
+            "draft/",
+            ".hidden",
+        ] {
+            let invalid = SyngError::InvalidRefName(name.to_owned());
+
+            assert_eq!(
+                backend.compare_and_set_ref(name, None, &a),
+                Err(invalid.clone())
+            );
+            assert_eq!(backend.read_ref(name), Err(invalid.clone()));
+            assert_eq!(backend.delete_ref(name), Err(invalid));
+        }
+
+        assert!(backend.list_refs().unwrap().is_empty());
+        assert!(!dir.0.join("x").exists());
+    }
+}
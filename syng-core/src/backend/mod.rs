@@ -1,15 +1,219 @@
-use crate::objects::SyngObjectDef;
-use anyhow::Result;
+pub mod fs;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use std::collections::BTreeMap;
+
+use crate::{
+    error::SyngError,
+    objects::{HashAlgorithm, SyngObjectDef},
+};
+
+/// The ref holding the root of the backend, which is what [`SyngBackend::get_root_object_id`] and
+/// [`SyngBackend::set_root_object`] read and move
+pub const DEFAULT_REF: &str = "HEAD";
+
+/// Ref names are `/` separated segments (like `main`, `draft/alice` or `remote/origin`) made of
+/// ASCII letters, digits, `-`, `_` and `.`. Segments can't be empty, start with a `.` or end with
+/// `.lock`, so that names can be used as file paths by backends storing refs as files.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    name.split('/').all(|segment| {
+        !segment.is_empty()
+            && !segment.starts_with('.')
+            && !segment.ends_with(".lock")
+            && segment
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+    })
+}
+
+pub(crate) fn check_ref_name(name: &str) -> Result<(), SyngError> {
+    if !is_valid_ref_name(name) {
+        return Err(SyngError::InvalidRefName(name.to_owned()));
+    }
+
+    Ok(())
+}
 
 pub trait SyngBackend {
-    fn has_object(&self, object_id: &str) -> bool {
-        self.read_object(object_id).is_some()
+    fn has_object(&self, object_id: &str) -> Result<bool, SyngError> {
+        Ok(self.read_object(object_id)?.is_some())
     }
 
-    fn get_root_object_id(&self) -> Option<String>;
-    fn get_root_object(&self) -> Option<SyngObjectDef>;
-    fn set_root_object(&mut self, node_id: &str) -> Result<()>;
+    fn get_root_object_id(&self) -> Result<Option<String>, SyngError>;
+    fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError>;
+    fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError>;
+
+    /// Sets the root to `new` only if the current root is `expected`. Returns `Ok(false)` without
+    /// touching the root if it is something else.
+    ///
+    /// The default implementation checks and sets in two steps, backends that can be written to
+    /// from multiple places should override this to do it atomically.
+    fn compare_and_set_root(
+        &mut self,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        if self.get_root_object_id()?.as_deref() != expected {
+            return Ok(false);
+        }
+
+        self.set_root_object(new)?;
 
-    fn read_object(&self, id: &str) -> Option<SyngObjectDef>;
-    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String>;
+        Ok(true)
+    }
+
+    /// Lists every ref with the ID of the object it points to, including [`DEFAULT_REF`] if the
+    /// root is set.
+    ///
+    /// The ref functions default to a backend with just the default ref, backends storing named
+    /// refs should override all of them.
+    fn list_refs(&self) -> Result<BTreeMap<String, String>, SyngError> {
+        Ok(self
+            .get_root_object_id()?
+            .map(|root_id| (DEFAULT_REF.to_owned(), root_id))
+            .into_iter()
+            .collect())
+    }
+
+    /// Reads the object ID the ref points to, returning `Ok(None)` if there is no such ref
+    fn read_ref(&self, name: &str) -> Result<Option<String>, SyngError> {
+        check_ref_name(name)?;
+
+        if name != DEFAULT_REF {
+            return Ok(None);
+        }
+
+        self.get_root_object_id()
+    }
+
+    /// Points the ref at `new` only if it currently points at `expected` (`None` meaning the ref
+    /// doesn't exist yet), in the same way as [`SyngBackend::compare_and_set_root`]
+    fn compare_and_set_ref(
+        &mut self,
+        name: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        if name != DEFAULT_REF {
+            return Err(SyngError::Unsupported("named refs".to_owned()));
+        }
+
+        self.compare_and_set_root(expected, new)
+    }
+
+    /// Deletes the ref, returning whether it existed. The objects it pointed to are left in the
+    /// backend.
+    fn delete_ref(&mut self, name: &str) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        Err(SyngError::Unsupported("deleting refs".to_owned()))
+    }
+
+    /// The algorithm objects written to the backend are hashed with. The IDs record their
+    /// algorithm, so objects hashed with another one (like everything written before a switch to
+    /// a new algorithm) can still be read and referred to, and a store can mix them.
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::default()
+    }
+
+    /// The ID the object gets when written to the backend
+    fn object_id(&self, def: &SyngObjectDef) -> Result<String, SyngError> {
+        Ok(def.get_id(self.hash_algorithm())?.to_string())
+    }
+
+    /// Reads an object, returning `Ok(None)` if the backend doesn't have it
+    fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError>;
+    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError>;
+
+    /// Reads several objects at once, returning them in the same order as `ids` with `None` for
+    /// the ones the backend doesn't have. Backends that can fetch many objects in one round trip
+    /// should override this, the tree walking and delta functions read a whole tree level with it.
+    fn read_objects(&self, ids: &[&str]) -> Result<Vec<Option<SyngObjectDef>>, SyngError> {
+        ids.iter().map(|id| self.read_object(id)).collect()
+    }
+
+    /// Writes several objects at once, returning their IDs in the same order as `defs`
+    fn write_objects(&mut self, defs: &[SyngObjectDef]) -> Result<Vec<String>, SyngError> {
+        defs.iter().map(|def| self.write_object(def)).collect()
+    }
+
+    /// Writes objects under the IDs they already have, like the objects of a delta, which can be
+    /// hashed with another algorithm than the backend's. The IDs are not checked against the
+    /// objects.
+    ///
+    /// The default implementation only takes objects whose ID is their hash with
+    /// [`SyngBackend::hash_algorithm`], backends that can store objects of any algorithm should
+    /// override it.
+    fn write_objects_with_ids(
+        &mut self,
+        objects: &[(&str, &SyngObjectDef)],
+    ) -> Result<(), SyngError> {
+        let defs = objects
+            .iter()
+            .map(|(id, def)| {
+                if self.object_id(def)? != *id {
+                    return Err(SyngError::Unsupported(format!(
+                        "storing {} under an ID of another hash algorithm",
+                        id
+                    )));
+                }
+
+                Ok((*def).clone())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.write_objects(&defs)?;
+
+        Ok(())
+    }
+
+    /// Lists the IDs of every object in the backend
+    fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        Err(SyngError::Unsupported("listing objects".to_owned()))
+    }
+
+    /// Lists up to `limit` object IDs in ascending order, starting after `after` (or from the
+    /// first ID). Garbage collection and fsck page through the store with it, so that stores too
+    /// big to list at once don't have to be.
+    ///
+    /// The default implementation lists every object for each page, backends holding large
+    /// stores should override it.
+    fn list_objects_page(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, SyngError> {
+        let mut ids = self.list_objects()?;
+
+        ids.retain(|id| after.map_or(true, |after| id.as_str() > after));
+        ids.sort_unstable();
+        ids.truncate(limit);
+
+        Ok(ids)
+    }
+
+    /// Deletes an object, returning whether the backend had it. Nothing checks whether the object
+    /// is still referenced, see [`crate::gc`] for a safe way to drop objects.
+    fn delete_object(&mut self, _id: &str) -> Result<bool, SyngError> {
+        Err(SyngError::Unsupported("deleting objects".to_owned()))
+    }
+
+    /// Starts a transaction. Writes and root changes made until the transaction is committed
+    /// should either all be applied or none of them. Backends without transactions can leave
+    /// these as no-ops.
+    fn begin_transaction(&mut self) -> Result<(), SyngError> {
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), SyngError> {
+        Ok(())
+    }
+
+    /// Undoes every write and root change made since the transaction began
+    fn rollback_transaction(&mut self) -> Result<(), SyngError> {
+        Ok(())
+    }
 }
@@ -0,0 +1,630 @@
+//! Backend storing the objects in an SQLite database, enabled with the `sqlite` feature.
+//!
+//! Objects are CBOR encoded into the `objects` table keyed by their hash, and refs are rows of the
+//! `refs` table (with the root being the [`DEFAULT_REF`] row). Nothing is cached in memory, so
+//! the store can grow well past what fits in RAM.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use ciborium::{de::from_reader, ser::into_writer};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+
+use crate::{
+    error::SyngError,
+    objects::{HashAlgorithm, SyngObjectDef},
+};
+
+use super::{check_ref_name, SyngBackend, DEFAULT_REF};
+
+/// SQLite limits the number of parameters in a statement, so batch reads are split into chunks
+const READ_BATCH_SIZE: usize = 500;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS objects (
+        hash TEXT PRIMARY KEY NOT NULL,
+        data BLOB NOT NULL
+    ) WITHOUT ROWID;
+
+    CREATE TABLE IF NOT EXISTS refs (
+        name TEXT PRIMARY KEY NOT NULL,
+        object_hash TEXT NOT NULL
+    ) WITHOUT ROWID;
+";
+
+fn storage_error(e: rusqlite::Error) -> SyngError {
+    SyngError::StorageFailed(e.to_string())
+}
+
+fn encode_object(def: &SyngObjectDef) -> Result<Vec<u8>, SyngError> {
+    let mut data = vec![];
+
+    into_writer(def, &mut data).map_err(|e| SyngError::EncodeFailed(e.to_string()))?;
+
+    Ok(data)
+}
+
+fn decode_object(data: &[u8]) -> Result<SyngObjectDef, SyngError> {
+    from_reader(data).map_err(|e| SyngError::DecodeFailed(e.to_string()))
+}
+
+/// The connection is behind a mutex so that the backend can be shared between threads (like the
+/// workers of a web server) even though SQLite connections can't be
+pub struct SqliteBackend {
+    conn: Mutex<Connection>,
+    hash_algorithm: HashAlgorithm,
+}
+
+impl SqliteBackend {
+    /// Opens the database at `path`, creating it and the tables if they don't exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SyngError> {
+        Self::from_connection(Connection::open(path).map_err(storage_error)?)
+    }
+
+    /// Opens a database that only lives as long as the backend
+    pub fn open_in_memory() -> Result<Self, SyngError> {
+        Self::from_connection(Connection::open_in_memory().map_err(storage_error)?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, SyngError> {
+        conn.execute_batch(SCHEMA).map_err(storage_error)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+            hash_algorithm: HashAlgorithm::default(),
+        })
+    }
+
+    /// Hashes the objects written from now on with `algorithm`, see
+    /// [`SyngBackend::hash_algorithm`]
+    pub fn with_hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = algorithm;
+        self
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>, SyngError> {
+        self.conn
+            .lock()
+            .map_err(|_| SyngError::StorageFailed("SQLite connection lock poisoned".to_owned()))
+    }
+
+    fn check_object_exists(conn: &Connection, id: &str) -> Result<(), SyngError> {
+        let exists = conn
+            .prepare_cached("SELECT 1 FROM objects WHERE hash = ?1")
+            .and_then(|mut stmt| stmt.exists(params![id]))
+            .map_err(storage_error)?;
+
+        if !exists {
+            return Err(SyngError::ObjectNotFound(id.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// Inserts the encoded objects under their IDs, skipping the ones already stored
+    fn insert_objects(&self, rows: &[(String, Vec<u8>)]) -> Result<(), SyngError> {
+        let conn = self.conn()?;
+
+        // A savepoint works both inside and outside of a transaction, and writing all the rows in
+        // one is much faster than committing each
+        conn.execute_batch("SAVEPOINT write_objects")
+            .map_err(storage_error)?;
+
+        let result = conn
+            .prepare_cached("INSERT OR IGNORE INTO objects (hash, data) VALUES (?1, ?2)")
+            .and_then(|mut stmt| {
+                for (hash, data) in rows {
+                    stmt.execute(params![hash, data])?;
+                }
+
+                Ok(())
+            });
+
+        match result {
+            Ok(()) => conn.execute_batch("RELEASE write_objects"),
+            Err(e) => {
+                let _ = conn.execute_batch("ROLLBACK TO write_objects; RELEASE write_objects");
+
+                Err(e)
+            }
+        }
+        .map_err(storage_error)
+    }
+}
+
+impl SyngBackend for SqliteBackend {
+    fn has_object(&self, object_id: &str) -> Result<bool, SyngError> {
+        self.conn()?
+            .prepare_cached("SELECT 1 FROM objects WHERE hash = ?1")
+            .and_then(|mut stmt| stmt.exists(params![object_id]))
+            .map_err(storage_error)
+    }
+
+    fn get_root_object_id(&self) -> Result<Option<String>, SyngError> {
+        self.read_ref(DEFAULT_REF)
+    }
+
+    fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError> {
+        let Some(root_id) = self.get_root_object_id()? else {
+            return Ok(None);
+        };
+
+        self.read_object(&root_id)
+    }
+
+    fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError> {
+        let conn = self.conn()?;
+
+        Self::check_object_exists(&conn, node_id)?;
+
+        conn.prepare_cached(
+            "INSERT INTO refs (name, object_hash) VALUES (?1, ?2)
+                ON CONFLICT (name) DO UPDATE SET object_hash = excluded.object_hash",
+        )
+        .and_then(|mut stmt| stmt.execute(params![DEFAULT_REF, node_id]))
+        .map_err(storage_error)?;
+
+        Ok(())
+    }
+
+    fn compare_and_set_root(
+        &mut self,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        self.compare_and_set_ref(DEFAULT_REF, expected, new)
+    }
+
+    fn list_refs(&self) -> Result<BTreeMap<String, String>, SyngError> {
+        self.conn()?
+            .prepare_cached("SELECT name, object_hash FROM refs")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect()
+            })
+            .map_err(storage_error)
+    }
+
+    fn read_ref(&self, name: &str) -> Result<Option<String>, SyngError> {
+        check_ref_name(name)?;
+
+        self.conn()?
+            .prepare_cached("SELECT object_hash FROM refs WHERE name = ?1")
+            .and_then(|mut stmt| stmt.query_row(params![name], |row| row.get(0)).optional())
+            .map_err(storage_error)
+    }
+
+    fn compare_and_set_ref(
+        &mut self,
+        name: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        let conn = self.conn()?;
+
+        Self::check_object_exists(&conn, new)?;
+
+        // Both are a single statement, so the check and the update can't be split by another
+        // connection writing in between
+        let updated_rows = match expected {
+            Some(expected) => conn
+                .prepare_cached(
+                    "UPDATE refs SET object_hash = ?1 WHERE name = ?2 AND object_hash = ?3",
+                )
+                .and_then(|mut stmt| stmt.execute(params![new, name, expected])),
+            None => conn
+                .prepare_cached(
+                    "INSERT INTO refs (name, object_hash) VALUES (?1, ?2)
+                        ON CONFLICT (name) DO NOTHING",
+                )
+                .and_then(|mut stmt| stmt.execute(params![name, new])),
+        }
+        .map_err(storage_error)?;
+
+        Ok(updated_rows == 1)
+    }
+
+    fn delete_ref(&mut self, name: &str) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        let deleted_rows = self
+            .conn()?
+            .prepare_cached("DELETE FROM refs WHERE name = ?1")
+            .and_then(|mut stmt| stmt.execute(params![name]))
+            .map_err(storage_error)?;
+
+        Ok(deleted_rows == 1)
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError> {
+        let data: Option<Vec<u8>> = self
+            .conn()?
+            .prepare_cached("SELECT data FROM objects WHERE hash = ?1")
+            .and_then(|mut stmt| stmt.query_row(params![id], |row| row.get(0)).optional())
+            .map_err(storage_error)?;
+
+        data.as_deref().map(decode_object).transpose()
+    }
+
+    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError> {
+        Ok(self.write_objects(std::slice::from_ref(def))?.remove(0))
+    }
+
+    fn read_objects(&self, ids: &[&str]) -> Result<Vec<Option<SyngObjectDef>>, SyngError> {
+        let conn = self.conn()?;
+
+        let mut objects = Vec::with_capacity(ids.len());
+
+        for chunk in ids.chunks(READ_BATCH_SIZE) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT hash, data FROM objects WHERE hash IN ({})",
+                    placeholders
+                ))
+                .map_err(storage_error)?;
+
+            let rows = stmt
+                .query_map(params_from_iter(chunk), |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })
+                .and_then(|rows| rows.collect::<Result<HashMap<_, _>, _>>())
+                .map_err(storage_error)?;
+
+            // The rows come back in any order (and once per distinct ID), so put them back in the
+            // order they were asked for
+            for id in chunk {
+                let object = rows.get(*id).map(|data| decode_object(data)).transpose()?;
+
+                objects.push(object);
+            }
+        }
+
+        Ok(objects)
+    }
+
+    fn write_objects(&mut self, defs: &[SyngObjectDef]) -> Result<Vec<String>, SyngError> {
+        let mut rows = Vec::with_capacity(defs.len());
+
+        for def in defs {
+            rows.push((self.object_id(def)?, encode_object(def)?));
+        }
+
+        self.insert_objects(&rows)?;
+
+        Ok(rows.into_iter().map(|(hash, _)| hash).collect())
+    }
+
+    fn write_objects_with_ids(
+        &mut self,
+        objects: &[(&str, &SyngObjectDef)],
+    ) -> Result<(), SyngError> {
+        let mut rows = Vec::with_capacity(objects.len());
+
+        for (id, def) in objects {
+            rows.push(((*id).to_owned(), encode_object(def)?));
+        }
+
+        self.insert_objects(&rows)
+    }
+
+    fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        self.conn()?
+            .prepare_cached("SELECT hash FROM objects")
+            .and_then(|mut stmt| stmt.query_map([], |row| row.get(0))?.collect())
+            .map_err(storage_error)
+    }
+
+    fn list_objects_page(
+        &self,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, SyngError> {
+        // The table is ordered by the hash, so every page is a seek into it
+        self.conn()?
+            .prepare_cached("SELECT hash FROM objects WHERE hash > ?1 ORDER BY hash LIMIT ?2")
+            .and_then(|mut stmt| {
+                stmt.query_map(params![after.unwrap_or(""), limit as i64], |row| row.get(0))?
+                    .collect()
+            })
+            .map_err(storage_error)
+    }
+
+    fn delete_object(&mut self, id: &str) -> Result<bool, SyngError> {
+        let deleted_rows = self
+            .conn()?
+            .prepare_cached("DELETE FROM objects WHERE hash = ?1")
+            .and_then(|mut stmt| stmt.execute(params![id]))
+            .map_err(storage_error)?;
+
+        Ok(deleted_rows == 1)
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), SyngError> {
+        let conn = self.conn()?;
+
+        if !conn.is_autocommit() {
+            return Err(SyngError::TransactionFailed(
+                "a transaction is already in progress".to_owned(),
+            ));
+        }
+
+        // Take the write lock straight away, so that a concurrent writer fails here instead of
+        // halfway through the transaction
+        conn.execute_batch("BEGIN IMMEDIATE")
+            .map_err(|e| SyngError::TransactionFailed(e.to_string()))
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), SyngError> {
+        let conn = self.conn()?;
+
+        if conn.is_autocommit() {
+            return Err(SyngError::TransactionFailed(
+                "no transaction in progress".to_owned(),
+            ));
+        }
+
+        conn.execute_batch("COMMIT")
+            .map_err(|e| SyngError::TransactionFailed(e.to_string()))
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), SyngError> {
+        let conn = self.conn()?;
+
+        if conn.is_autocommit() {
+            return Err(SyngError::TransactionFailed(
+                "no transaction in progress".to_owned(),
+            ));
+        }
+
+        conn.execute_batch("ROLLBACK")
+            .map_err(|e| SyngError::TransactionFailed(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commit::{CommitInfo, HISTORY_REF},
+        delta::{
+            apply_delta, apply_delta_with_options, ApplyDeltaError, ApplyDeltaOptions, SyngDelta,
+        },
+        testing::named,
+    };
+
+    fn backend() -> SqliteBackend {
+        SqliteBackend::open_in_memory().unwrap()
+    }
+
+    #[test]
+    fn refs_are_compared_and_set() {
+        let mut backend = backend();
+        let a = backend.write_object(&named("a")).unwrap();
+        let b = backend.write_object(&named("b")).unwrap();
+
+        assert!(backend.compare_and_set_ref("draft", None, &a).unwrap());
+        assert!(!backend.compare_and_set_ref("draft", None, &b).unwrap());
+        assert!(!backend.compare_and_set_ref("draft", Some(&b), &b).unwrap());
+        assert_eq!(backend.read_ref("draft").unwrap(), Some(a.clone()));
+
+        assert!(backend.compare_and_set_ref("draft", Some(&a), &b).unwrap());
+        assert_eq!(backend.read_ref("draft").unwrap(), Some(b.clone()));
+
+        // A missing ref can't be expected to be at anything
+        assert!(!backend.compare_and_set_ref("other", Some(&a), &b).unwrap());
+        assert_eq!(backend.read_ref("other").unwrap(), None);
+
+        assert_eq!(
+            backend.list_refs().unwrap(),
+            BTreeMap::from([("draft".to_owned(), b.clone())])
+        );
+    }
+
+    #[test]
+    fn refs_only_point_to_stored_objects() {
+        let mut backend = backend();
+        let missing = named("missing").get_hash().unwrap();
+
+        assert_eq!(
+            backend.compare_and_set_ref("draft", None, &missing),
+            Err(SyngError::ObjectNotFound(missing.clone()))
+        );
+        assert_eq!(
+            backend.set_root_object(&missing),
+            Err(SyngError::ObjectNotFound(missing))
+        );
+    }
+
+    #[test]
+    fn invalid_ref_names_are_rejected() {
+        let mut backend = backend();
+        let a = backend.write_object(&named("a")).unwrap();
+
+        for name in ["", "../HEAD", "draft/", "draft.lock"] {
+            assert!(matches!(
+                backend.compare_and_set_ref(name, None, &a),
+                Err(SyngError::InvalidRefName(_))
+            ));
+            assert!(matches!(
+                backend.read_ref(name),
+                Err(SyngError::InvalidRefName(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rollback_undoes_the_transaction() {
+        let mut backend = backend();
+        let a = backend.write_object(&named("a")).unwrap();
+        backend.set_root_object(&a).unwrap();
+
+        backend.begin_transaction().unwrap();
+
+        assert!(matches!(
+            backend.begin_transaction(),
+            Err(SyngError::TransactionFailed(_))
+        ));
+
+        let b = backend.write_object(&named("b")).unwrap();
+        backend.set_root_object(&b).unwrap();
+        backend.delete_object(&a).unwrap();
+
+        backend.rollback_transaction().unwrap();
+
+        assert_eq!(backend.get_root_object_id().unwrap(), Some(a.clone()));
+        assert!(backend.has_object(&a).unwrap());
+        assert!(!backend.has_object(&b).unwrap());
+
+        assert!(matches!(
+            backend.rollback_transaction(),
+            Err(SyngError::TransactionFailed(_))
+        ));
+        assert!(matches!(
+            backend.commit_transaction(),
+            Err(SyngError::TransactionFailed(_))
+        ));
+    }
+
+    #[test]
+    fn commit_keeps_the_transaction() {
+        let mut backend = backend();
+
+        backend.begin_transaction().unwrap();
+        let a = backend.write_object(&named("a")).unwrap();
+        backend.set_root_object(&a).unwrap();
+        backend.commit_transaction().unwrap();
+
+        assert_eq!(backend.get_root_object_id().unwrap(), Some(a));
+    }
+
+    #[test]
+    fn read_objects_keeps_the_order_asked_for() {
+        let mut backend = backend();
+
+        // More than one batch, written in another order than they are read in
+        let defs = (0..READ_BATCH_SIZE + 10)
+            .map(|i| named(&i.to_string()))
+            .collect::<Vec<_>>();
+        let mut ids = backend.write_objects(&defs).unwrap();
+        ids.reverse();
+
+        let missing = named("missing").get_hash().unwrap();
+        let mut asked = ids.iter().map(String::as_str).collect::<Vec<_>>();
+        asked.insert(3, &missing);
+        asked.push(ids[0].as_str());
+
+        let objects = backend.read_objects(&asked).unwrap();
+
+        assert_eq!(objects.len(), asked.len());
+        assert!(objects[3].is_none());
+
+        for (id, obj) in asked.iter().zip(&objects) {
+            if let Some(obj) = obj {
+                assert_eq!(obj.get_hash().unwrap(), *id);
+            }
+        }
+
+        assert_eq!(objects.iter().filter(|obj| obj.is_none()).count(), 1);
+    }
+
+    #[test]
+    fn objects_are_listed_in_pages() {
+        let mut backend = backend();
+        let defs = (0..25).map(|i| named(&i.to_string())).collect::<Vec<_>>();
+
+        let mut ids = backend.write_objects(&defs).unwrap();
+        ids.sort();
+
+        let mut listed = vec![];
+        let mut after = None;
+
+        loop {
+            let page = backend.list_objects_page(after.as_deref(), 10).unwrap();
+
+            if page.is_empty() {
+                break;
+            }
+
+            assert!(page.len() <= 10);
+            after = page.last().cloned();
+            listed.extend(page);
+        }
+
+        assert_eq!(listed, ids);
+    }
+
+    /// A delta on top of the root `a`, moving it to `b(a)`
+    fn delta_from(backend: &mut SqliteBackend) -> (String, SyngDelta) {
+        let a = backend.write_object(&named("a")).unwrap();
+        backend.set_root_object(&a).unwrap();
+
+        let mut b = named("b");
+        b.children = vec![a.clone()];
+
+        let delta = SyngDelta {
+            start_point: Some(a.clone()),
+            new_root_node: b.get_hash().unwrap(),
+            new_objects: [(b.get_hash().unwrap(), b)].into(),
+        };
+
+        (a, delta)
+    }
+
+    #[test]
+    fn applies_deltas() {
+        let mut backend = backend();
+        let (_, delta) = delta_from(&mut backend);
+
+        apply_delta(&mut backend, &delta).unwrap();
+
+        assert_eq!(
+            backend.get_root_object_id().unwrap(),
+            Some(delta.new_root_node.clone())
+        );
+        assert!(backend.conn().unwrap().is_autocommit());
+    }
+
+    #[test]
+    fn failed_deltas_are_rolled_back() {
+        let mut backend = backend();
+        let (a, delta) = delta_from(&mut backend);
+
+        // Fail the last step of applying the delta, after the objects were written and the root
+        // was moved
+        backend
+            .conn()
+            .unwrap()
+            .execute_batch(&format!(
+                "CREATE TRIGGER fail_commit BEFORE INSERT ON refs WHEN NEW.name = '{}'
+                    BEGIN SELECT RAISE(ABORT, 'no history'); END;",
+                HISTORY_REF
+            ))
+            .unwrap();
+
+        let options = ApplyDeltaOptions {
+            commit: Some(CommitInfo::new("alice", "b")),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            apply_delta_with_options(&mut backend, &delta, &options),
+            Err(ApplyDeltaError::CommitFailed(_))
+        ));
+
+        assert_eq!(backend.get_root_object_id().unwrap(), Some(a));
+        assert!(!backend.has_object(&delta.new_root_node).unwrap());
+        assert_eq!(backend.list_objects().unwrap().len(), 1);
+        assert!(backend.conn().unwrap().is_autocommit());
+    }
+}
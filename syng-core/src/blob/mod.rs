@@ -0,0 +1,401 @@
+//! Large binary payloads stored as chunked blob objects.
+//!
+//! Keeping a big payload in a field means every edit to it produces a new object holding all of
+//! it, which sync then has to transfer whole. [`write_blob`] splits the payload into chunks along
+//! boundaries picked from the content with a rolling hash instead, so an edit only changes the
+//! chunks around it while the rest keep their boundaries (and so their IDs).
+//!
+//! Every chunk is stored as its own object, and the blob object has the chunks as its children in
+//! order. A blob can be put anywhere in the tree like any other object, and since the unchanged
+//! chunks are shared with the previous version of the blob,
+//! [`generate_delta_from_point`](crate::delta::generate_delta_from_point) only picks up the blob
+//! object and the chunks that changed.
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    error::SyngError,
+    objects::{HashAlgorithm, SyngObjectDef, SyngValue},
+    tree_ops::{read_referred_object, read_referred_objects},
+};
+
+const TYPE_FIELD: &str = "syng.type";
+const BLOB_TYPE: &str = "blob";
+const CHUNK_TYPE: &str = "chunk";
+const SIZE_FIELD: &str = "syng.size";
+const DATA_FIELD: &str = "syng.data";
+
+/// Where the chunk boundaries of a blob go. Different options split the same data differently, so
+/// everyone writing the same blobs should use the same options to share chunks.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChunkingOptions {
+    /// No boundary is placed before a chunk reaches this many bytes
+    pub min_chunk_size: usize,
+
+    /// The size chunks grow to on average past `min_chunk_size`, rounded up to a power of two
+    pub avg_chunk_size: usize,
+
+    /// A boundary is forced once a chunk reaches this many bytes
+    pub max_chunk_size: usize,
+}
+
+impl Default for ChunkingOptions {
+    fn default() -> Self {
+        Self {
+            min_chunk_size: 2 * 1024,
+            avg_chunk_size: 8 * 1024,
+            max_chunk_size: 64 * 1024,
+        }
+    }
+}
+
+/// Values the gear rolling hash adds for each byte, generated with splitmix64 so that every build
+/// (and every peer) places the same boundaries
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x5379_6e67_426c_6f62;
+    let mut i = 0;
+
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+
+        i += 1;
+    }
+
+    table
+};
+
+/// Splits `data` into chunks with content-defined boundaries. A boundary is placed where the top
+/// bits of a gear hash over the last 64 bytes are all zero, so inserting or removing bytes only
+/// moves the boundaries near the edit.
+pub fn split_into_chunks<'a>(data: &'a [u8], options: &ChunkingOptions) -> Vec<&'a [u8]> {
+    let max_size = options.max_chunk_size.max(1);
+    let min_size = options.min_chunk_size.min(max_size);
+    let mask_bits = options
+        .avg_chunk_size
+        .max(1)
+        .checked_next_power_of_two()
+        .map_or(usize::BITS, usize::trailing_zeros);
+    let mask = !(u64::MAX.checked_shr(mask_bits).unwrap_or(0));
+
+    let mut chunks = vec![];
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let limit = rest.len().min(max_size);
+        let mut end = limit;
+        let mut hash: u64 = 0;
+
+        for (index, &byte) in rest[..limit].iter().enumerate().skip(min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+
+            if hash & mask == 0 {
+                end = index + 1;
+                break;
+            }
+        }
+
+        let (chunk, remaining) = rest.split_at(end);
+
+        chunks.push(chunk);
+        rest = remaining;
+    }
+
+    chunks
+}
+
+/// A blob object, which has the chunks of the data as its children
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SyngBlob {
+    /// Length of the data in bytes
+    pub size: u64,
+
+    /// IDs of the chunk objects, in the order their data goes in
+    pub chunks: Vec<String>,
+}
+
+impl SyngBlob {
+    pub fn to_object(&self) -> SyngObjectDef {
+        SyngObjectDef {
+            fields: BTreeMap::from([
+                (TYPE_FIELD.to_owned(), BLOB_TYPE.into()),
+                (SIZE_FIELD.to_owned(), SyngValue::Integer(self.size as i64)),
+            ]),
+            children: self.chunks.clone(),
+        }
+    }
+
+    /// Returns `None` if the object is not a blob
+    pub fn from_object(obj: &SyngObjectDef) -> Option<Self> {
+        if obj.fields.get(TYPE_FIELD).and_then(SyngValue::as_str) != Some(BLOB_TYPE) {
+            return None;
+        }
+
+        Some(Self {
+            size: u64::try_from(obj.fields.get(SIZE_FIELD)?.as_i64()?).ok()?,
+            chunks: obj.children.clone(),
+        })
+    }
+}
+
+fn chunk_object(data: &[u8]) -> SyngObjectDef {
+    SyngObjectDef {
+        fields: BTreeMap::from([
+            (TYPE_FIELD.to_owned(), CHUNK_TYPE.into()),
+            (DATA_FIELD.to_owned(), data.to_vec().into()),
+        ]),
+        children: vec![],
+    }
+}
+
+/// The data of a chunk object, `None` if the object is not a chunk
+fn chunk_data(obj: &SyngObjectDef) -> Option<&[u8]> {
+    if obj.fields.get(TYPE_FIELD).and_then(SyngValue::as_str) != Some(CHUNK_TYPE) {
+        return None;
+    }
+
+    obj.fields.get(DATA_FIELD)?.as_bytes()
+}
+
+fn not_a_blob_error(id: &str) -> SyngError {
+    SyngError::DecodeFailed(format!("{} is not a blob", id))
+}
+
+/// Splits the data into the blob object and the chunk objects it refers to, with the chunks hashed
+/// with `algorithm`. A chunk that appears more than once in the data is only returned once.
+fn build_blob(
+    data: &[u8],
+    options: &ChunkingOptions,
+    algorithm: HashAlgorithm,
+) -> Result<(SyngObjectDef, Vec<SyngObjectDef>), SyngError> {
+    let mut chunk_ids = vec![];
+    let mut chunk_objects = vec![];
+    let mut seen = HashSet::new();
+
+    for chunk in split_into_chunks(data, options) {
+        let obj = chunk_object(chunk);
+        let id = obj.get_id(algorithm)?.to_string();
+
+        if seen.insert(id.clone()) {
+            chunk_objects.push(obj);
+        }
+
+        chunk_ids.push(id);
+    }
+
+    let blob = SyngBlob {
+        size: data.len() as u64,
+        chunks: chunk_ids,
+    };
+
+    Ok((blob.to_object(), chunk_objects))
+}
+
+/// Puts the data of the blob `id` back together from its chunk objects, which are in the order of
+/// the blob's children
+fn assemble_blob(
+    id: &str,
+    blob: &SyngBlob,
+    chunks: &[SyngObjectDef],
+) -> Result<Vec<u8>, SyngError> {
+    let mut data = vec![];
+
+    for (chunk_id, chunk) in blob.chunks.iter().zip(chunks) {
+        let chunk_data = chunk_data(chunk).ok_or_else(|| {
+            SyngError::DecodeFailed(format!("{} in blob {} is not a chunk", chunk_id, id))
+        })?;
+
+        data.extend_from_slice(chunk_data);
+    }
+
+    if data.len() as u64 != blob.size {
+        return Err(SyngError::DecodeFailed(format!(
+            "blob {} has {} bytes of chunks instead of {}",
+            id,
+            data.len(),
+            blob.size
+        )));
+    }
+
+    Ok(data)
+}
+
+/// Writes the data as a blob with the default [`ChunkingOptions`], returning the ID and the blob
+/// object. Nothing refers to the blob yet, it has to be added to the tree (for example as a child
+/// with [`crate::tree_ops::add_child_object`]) to be kept and synced.
+pub fn write_blob(
+    backend: &mut impl SyngBackend,
+    data: &[u8],
+) -> Result<(String, SyngObjectDef), SyngError> {
+    write_blob_with_options(backend, data, &ChunkingOptions::default())
+}
+
+pub fn write_blob_with_options(
+    backend: &mut impl SyngBackend,
+    data: &[u8],
+    options: &ChunkingOptions,
+) -> Result<(String, SyngObjectDef), SyngError> {
+    let (blob, mut objects) = build_blob(data, options, backend.hash_algorithm())?;
+
+    objects.push(blob.clone());
+
+    let ids = backend.write_objects(&objects)?;
+
+    Ok((ids.last().unwrap().clone(), blob))
+}
+
+/// Reads the data of the blob `id`, failing with [`SyngError::DecodeFailed`] if the object is not
+/// a blob or its chunks don't add up to it
+pub fn read_blob(backend: &impl SyngBackend, id: &str) -> Result<Vec<u8>, SyngError> {
+    let obj = read_referred_object(backend, id)?;
+    let blob = SyngBlob::from_object(&obj).ok_or_else(|| not_a_blob_error(id))?;
+
+    let chunks = read_referred_objects(backend, &blob.chunks)?;
+
+    assemble_blob(id, &blob, &chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryBackend;
+
+    /// Deterministic bytes that don't repeat, so the boundaries depend on the content like they
+    /// would for real data
+    fn pseudo_random_data(len: usize) -> Vec<u8> {
+        let mut state: u64 = 1;
+
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn small_chunks() -> ChunkingOptions {
+        ChunkingOptions {
+            min_chunk_size: 64,
+            avg_chunk_size: 256,
+            max_chunk_size: 1024,
+        }
+    }
+
+    fn assert_sizes_respected(data: &[u8], options: &ChunkingOptions) -> Vec<usize> {
+        let chunks = split_into_chunks(data, options);
+        let sizes = chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>();
+
+        assert_eq!(chunks.concat(), data);
+        assert!(sizes
+            .iter()
+            .all(|&size| size > 0 && size <= options.max_chunk_size));
+        assert!(sizes[..sizes.len() - 1]
+            .iter()
+            .all(|&size| size >= options.min_chunk_size));
+
+        sizes
+    }
+
+    #[test]
+    fn boundaries_stay_after_an_insertion_near_the_start() {
+        let data = pseudo_random_data(64 * 1024);
+        let mut edited = data.clone();
+        edited.splice(100..100, *b"inserted");
+
+        let chunks = split_into_chunks(&data, &small_chunks());
+        let edited_chunks = split_into_chunks(&edited, &small_chunks());
+
+        assert!(chunks.len() > 50);
+
+        // Past the chunk with the insertion the boundaries line up again, so only the first few
+        // chunks differ
+        let changed = chunks
+            .iter()
+            .rev()
+            .zip(edited_chunks.iter().rev())
+            .take_while(|(chunk, edited_chunk)| chunk == edited_chunk)
+            .count();
+
+        assert!(chunks.len() - changed <= 2);
+        assert!(edited_chunks.len() - changed <= 2);
+    }
+
+    #[test]
+    fn chunk_sizes_stay_within_the_limits() {
+        let data = pseudo_random_data(16 * 1024);
+        let sizes = assert_sizes_respected(&data, &small_chunks());
+
+        assert!(sizes
+            .iter()
+            .any(|&size| size < small_chunks().max_chunk_size));
+    }
+
+    #[test]
+    fn zero_avg_size_cuts_at_the_min_size() {
+        let options = ChunkingOptions {
+            avg_chunk_size: 0,
+            ..small_chunks()
+        };
+        let data = pseudo_random_data(1000);
+        let sizes = assert_sizes_respected(&data, &options);
+
+        assert!(sizes[..sizes.len() - 1].iter().all(|&size| size == 65));
+    }
+
+    #[test]
+    fn avg_size_past_the_max_cuts_at_the_max_size() {
+        for avg_chunk_size in [1024 * 1024, usize::MAX] {
+            let options = ChunkingOptions {
+                avg_chunk_size,
+                ..small_chunks()
+            };
+            let data = pseudo_random_data(10 * 1024 + 10);
+            let sizes = assert_sizes_respected(&data, &options);
+
+            assert_eq!(sizes, [vec![1024; 10], vec![10]].concat());
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        for data in [vec![], vec![42], pseudo_random_data(8 * 1024)] {
+            let mut backend = MemoryBackend::default();
+            let (id, obj) = write_blob_with_options(&mut backend, &data, &small_chunks()).unwrap();
+            let blob = SyngBlob::from_object(&obj).unwrap();
+
+            assert_eq!(blob.size, data.len() as u64);
+            assert_eq!(
+                blob.chunks.len(),
+                split_into_chunks(&data, &small_chunks()).len()
+            );
+            assert_eq!(read_blob(&backend, &id).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn multi_chunk_blobs_are_split() {
+        let mut backend = MemoryBackend::default();
+        let data = pseudo_random_data(8 * 1024);
+        let (_, obj) = write_blob_with_options(&mut backend, &data, &small_chunks()).unwrap();
+
+        assert!(SyngBlob::from_object(&obj).unwrap().chunks.len() > 1);
+    }
+
+    #[test]
+    fn reading_a_non_blob_fails() {
+        let mut backend = MemoryBackend::default();
+        let id = backend.write_named("a", &[]);
+
+        assert_eq!(read_blob(&backend, &id), Err(not_a_blob_error(&id)));
+    }
+}
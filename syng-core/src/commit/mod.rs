@@ -0,0 +1,420 @@
+//! History of the root. Every recorded root change is a commit object pointing to the tree root and
+//! the commits before it, with the latest commit kept in [`HISTORY_REF`].
+//!
+//! Commits are stored as regular objects, with the parent commits as their children and the tree
+//! in a field. Following the children of a commit walks its history without going through the
+//! trees of every past commit, so [`crate::gc`] keeps the whole chain of commits but only the trees
+//! of the commits refs point to and of their [`merge_base`]s, and the trees of older commits can be
+//! collected.
+//!
+//! Recording commits needs a backend that supports named refs.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    error::SyngError,
+    objects::{SyngObjectDef, SyngValue},
+    tree_ops::read_referred_object,
+};
+
+/// Ref pointing to the latest commit of the root
+pub const HISTORY_REF: &str = "history/HEAD";
+
+const TYPE_FIELD: &str = "syng.type";
+const COMMIT_TYPE: &str = "commit";
+const TIMESTAMP_FIELD: &str = "syng.timestamp";
+const AUTHOR_FIELD: &str = "syng.author";
+const MESSAGE_FIELD: &str = "syng.message";
+const TREE_FIELD: &str = "syng.tree";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SyngCommit {
+    /// Root object of the tree at this commit
+    pub tree: String,
+
+    /// Commits this one follows, more than one for merges
+    pub parents: Vec<String>,
+
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+
+    pub author: String,
+    pub message: String,
+}
+
+impl SyngCommit {
+    pub fn to_object(&self) -> SyngObjectDef {
+        SyngObjectDef {
+            fields: BTreeMap::from([
+                (TYPE_FIELD.to_owned(), COMMIT_TYPE.into()),
+                (
+                    TIMESTAMP_FIELD.to_owned(),
+                    SyngValue::Integer(self.timestamp as i64),
+                ),
+                (AUTHOR_FIELD.to_owned(), self.author.as_str().into()),
+                (MESSAGE_FIELD.to_owned(), self.message.as_str().into()),
+                (TREE_FIELD.to_owned(), self.tree.as_str().into()),
+            ]),
+            children: self.parents.clone(),
+        }
+    }
+
+    /// Returns `None` if the object is not a commit
+    pub fn from_object(obj: &SyngObjectDef) -> Option<Self> {
+        if obj.fields.get(TYPE_FIELD).and_then(SyngValue::as_str) != Some(COMMIT_TYPE) {
+            return None;
+        }
+
+        Some(Self {
+            tree: obj.fields.get(TREE_FIELD)?.as_str()?.to_owned(),
+            parents: obj.children.clone(),
+            timestamp: u64::try_from(obj.fields.get(TIMESTAMP_FIELD)?.as_i64()?).ok()?,
+            author: obj.fields.get(AUTHOR_FIELD)?.as_str()?.to_owned(),
+            message: obj.fields.get(MESSAGE_FIELD)?.as_str()?.to_owned(),
+        })
+    }
+}
+
+/// What to record in the commit of a root change
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CommitInfo {
+    pub author: String,
+    pub message: String,
+
+    /// Commits merged in along with the change. They become parents of the commit next to the
+    /// previous commit of the root.
+    pub merged_commits: Vec<String>,
+}
+
+impl CommitInfo {
+    pub fn new(author: &str, message: &str) -> Self {
+        Self {
+            author: author.to_owned(),
+            message: message.to_owned(),
+            merged_commits: vec![],
+        }
+    }
+}
+
+fn now_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Builds the commit of `tree` following `head` (the current commit in [`HISTORY_REF`], if any)
+pub(crate) fn build_commit(tree: &str, head: Option<String>, info: &CommitInfo) -> SyngCommit {
+    SyngCommit {
+        tree: tree.to_owned(),
+        parents: head
+            .into_iter()
+            .chain(info.merged_commits.iter().cloned())
+            .collect(),
+        timestamp: now_timestamp(),
+        author: info.author.clone(),
+        message: info.message.clone(),
+    }
+}
+
+fn not_a_commit_error(id: &str) -> SyngError {
+    SyngError::DecodeFailed(format!("{} is not a commit", id))
+}
+
+pub fn read_commit(backend: &impl SyngBackend, id: &str) -> Result<SyngCommit, SyngError> {
+    let obj = read_referred_object(backend, id)?;
+
+    SyngCommit::from_object(&obj).ok_or_else(|| not_a_commit_error(id))
+}
+
+/// Records a commit of the current root in [`HISTORY_REF`], returning the ID of the commit
+pub fn commit_root(backend: &mut impl SyngBackend, info: &CommitInfo) -> Result<String, SyngError> {
+    let root_id = backend
+        .get_root_object_id()?
+        .ok_or(SyngError::NoRootObject)?;
+
+    record_commit(backend, &root_id, info)
+}
+
+/// Writes the commit of `tree` and moves [`HISTORY_REF`] to it
+pub(crate) fn record_commit(
+    backend: &mut impl SyngBackend,
+    tree: &str,
+    info: &CommitInfo,
+) -> Result<String, SyngError> {
+    // Retry if another writer committed in between, so that their commit becomes the parent
+    loop {
+        let head = backend.read_ref(HISTORY_REF)?;
+
+        let commit_id =
+            backend.write_object(&build_commit(tree, head.clone(), info).to_object())?;
+
+        if backend.compare_and_set_ref(HISTORY_REF, head.as_deref(), &commit_id)? {
+            return Ok(commit_id);
+        }
+    }
+}
+
+/// Sorts the commits newest first, keeping the order stable for commits with the same timestamp
+fn sort_log(log: &mut [(String, SyngCommit)]) {
+    log.sort_by(|(a_id, a), (b_id, b)| b.timestamp.cmp(&a.timestamp).then(a_id.cmp(b_id)));
+}
+
+/// Reads `from` and every commit before it
+fn read_ancestors(
+    backend: &impl SyngBackend,
+    from: &str,
+) -> Result<BTreeMap<String, SyngCommit>, SyngError> {
+    let mut ancestors = BTreeMap::new();
+    let mut queue = vec![from.to_owned()];
+
+    while let Some(commit_id) = queue.pop() {
+        if ancestors.contains_key(&commit_id) {
+            continue;
+        }
+
+        let commit = read_commit(backend, &commit_id)?;
+
+        queue.extend(commit.parents.iter().cloned());
+        ancestors.insert(commit_id, commit);
+    }
+
+    Ok(ancestors)
+}
+
+/// Returns `from` and every commit before it, newest first
+pub fn log(backend: &impl SyngBackend, from: &str) -> Result<Vec<(String, SyngCommit)>, SyngError> {
+    let mut result = read_ancestors(backend, from)?
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    sort_log(&mut result);
+
+    Ok(result)
+}
+
+/// Picks the lowest common ancestors out of the commits both sides have: the ones that are not an
+/// ancestor of another common commit. If there are several (criss-cross merges) the newest one is
+/// returned.
+fn lowest_common_ancestor(
+    a_ancestors: &BTreeMap<String, SyngCommit>,
+    b_ancestors: &BTreeMap<String, SyngCommit>,
+) -> Option<String> {
+    let common = a_ancestors
+        .keys()
+        .filter(|id| b_ancestors.contains_key(*id))
+        .collect::<BTreeSet<_>>();
+
+    // Everything reachable from the parents of a common commit is an ancestor of it, so it can't
+    // be the lowest one
+    let mut not_lowest = BTreeSet::new();
+    let mut queue = common
+        .iter()
+        .flat_map(|id| &a_ancestors[*id].parents)
+        .collect::<Vec<_>>();
+
+    while let Some(id) = queue.pop() {
+        if not_lowest.insert(id) {
+            queue.extend(&a_ancestors[id].parents);
+        }
+    }
+
+    common
+        .into_iter()
+        .filter(|id| !not_lowest.contains(id))
+        .max_by(|a, b| {
+            a_ancestors[*a]
+                .timestamp
+                .cmp(&a_ancestors[*b].timestamp)
+                .then(b.cmp(a))
+        })
+        .cloned()
+}
+
+/// Finds the lowest common ancestor of the commits `a` and `b`, the commit both of them were
+/// built on. Returns `None` if their histories are unrelated.
+///
+/// `a` and `b` are commits rather than roots, like the commits in [`HISTORY_REF`] and in the ref
+/// the remote root is tracked in. The tree of the merge base is the base to
+/// [`merge`](crate::delta::merge) their trees with, which [`crate::gc`] keeps as long as refs
+/// point to `a` and `b`.
+pub fn merge_base(
+    backend: &impl SyngBackend,
+    a: &str,
+    b: &str,
+) -> Result<Option<String>, SyngError> {
+    let a_ancestors = read_ancestors(backend, a)?;
+
+    // Fast path for when one side is simply ahead of the other
+    if a_ancestors.contains_key(b) {
+        return Ok(Some(b.to_owned()));
+    }
+
+    let b_ancestors = read_ancestors(backend, b)?;
+
+    Ok(lowest_common_ancestor(&a_ancestors, &b_ancestors))
+}
+
+/// The merge bases of every pair of `commits` along with their trees, as `(commit, tree)`. Merging
+/// the commits needs the trees of their merge bases, so these are kept reachable next to the trees
+/// of the commits themselves.
+pub(crate) fn merge_base_trees(
+    backend: &impl SyngBackend,
+    commits: &[String],
+) -> Result<Vec<(String, String)>, SyngError> {
+    let mut bases = BTreeSet::new();
+
+    for (index, a) in commits.iter().enumerate() {
+        for b in &commits[index + 1..] {
+            bases.extend(merge_base(backend, a, b)?);
+        }
+    }
+
+    bases
+        .into_iter()
+        .filter(|base| !commits.contains(base))
+        .map(|base| {
+            let tree = read_commit(backend, &base)?.tree;
+
+            Ok((base, tree))
+        })
+        .collect()
+}
+
+/// The [`log`] of the root, empty if no commit was recorded yet
+pub fn history(backend: &impl SyngBackend) -> Result<Vec<(String, SyngCommit)>, SyngError> {
+    match backend.read_ref(HISTORY_REF)? {
+        Some(head) => log(backend, &head),
+        None => Ok(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MemoryBackend;
+
+    fn commit(tree: &str, parents: &[&str]) -> SyngCommit {
+        SyngCommit {
+            tree: tree.to_owned(),
+            parents: parents.iter().map(|id| id.to_string()).collect(),
+            timestamp: 1,
+            author: "test".to_owned(),
+            message: "message".to_owned(),
+        }
+    }
+
+    #[test]
+    fn tree_is_not_a_child_of_the_commit() {
+        let commit = commit("tree", &["parent-a", "parent-b"]);
+        let obj = commit.to_object();
+
+        assert_eq!(obj.children, vec!["parent-a", "parent-b"]);
+        assert_eq!(SyngCommit::from_object(&obj), Some(commit));
+    }
+
+    /// Writes a commit of an empty tree, with `name` as the message
+    fn write_commit(
+        backend: &mut MemoryBackend,
+        name: &str,
+        timestamp: u64,
+        parents: &[&str],
+    ) -> String {
+        let tree = backend.write_named("tree", &[]);
+
+        let commit = SyngCommit {
+            timestamp,
+            message: name.to_owned(),
+            ..commit(&tree, parents)
+        };
+
+        backend.write_object(&commit.to_object()).unwrap()
+    }
+
+    #[test]
+    fn merge_base_of_linear_history_is_the_older_commit() {
+        let mut backend = MemoryBackend::default();
+        let first = write_commit(&mut backend, "first", 1, &[]);
+        let second = write_commit(&mut backend, "second", 2, &[&first]);
+        let third = write_commit(&mut backend, "third", 3, &[&second]);
+
+        assert_eq!(
+            merge_base(&backend, &third, &first).unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(merge_base(&backend, &first, &third).unwrap(), Some(first));
+        assert_eq!(merge_base(&backend, &third, &third).unwrap(), Some(third));
+    }
+
+    #[test]
+    fn merge_base_of_diverged_history_is_the_fork_point() {
+        let mut backend = MemoryBackend::default();
+        let first = write_commit(&mut backend, "first", 1, &[]);
+        let fork = write_commit(&mut backend, "fork", 2, &[&first]);
+        let ours = write_commit(&mut backend, "ours", 3, &[&fork]);
+        let ours = write_commit(&mut backend, "ours again", 5, &[&ours]);
+        let theirs = write_commit(&mut backend, "theirs", 4, &[&fork]);
+
+        assert_eq!(
+            merge_base(&backend, &ours, &theirs).unwrap(),
+            Some(fork.clone())
+        );
+        assert_eq!(merge_base(&backend, &theirs, &ours).unwrap(), Some(fork));
+    }
+
+    #[test]
+    fn merge_base_of_criss_cross_merges_is_the_newest_lowest_ancestor() {
+        let mut backend = MemoryBackend::default();
+        let first = write_commit(&mut backend, "first", 1, &[]);
+        let a = write_commit(&mut backend, "a", 2, &[&first]);
+        let b = write_commit(&mut backend, "b", 3, &[&first]);
+
+        // Both sides merged the other one in, so `a` and `b` are both lowest common ancestors
+        let ours = write_commit(&mut backend, "ours", 4, &[&a, &b]);
+        let theirs = write_commit(&mut backend, "theirs", 4, &[&b, &a]);
+
+        assert_eq!(
+            merge_base(&backend, &ours, &theirs).unwrap(),
+            Some(b.clone())
+        );
+        assert_eq!(merge_base(&backend, &theirs, &ours).unwrap(), Some(b));
+    }
+
+    #[test]
+    fn merge_base_breaks_timestamp_ties_by_id() {
+        let mut backend = MemoryBackend::default();
+        let first = write_commit(&mut backend, "first", 1, &[]);
+        let a = write_commit(&mut backend, "a", 2, &[&first]);
+        let b = write_commit(&mut backend, "b", 2, &[&first]);
+
+        let ours = write_commit(&mut backend, "ours", 3, &[&a, &b]);
+        let theirs = write_commit(&mut backend, "theirs", 3, &[&b, &a]);
+
+        let expected = a.clone().min(b.clone());
+
+        assert_eq!(
+            merge_base(&backend, &ours, &theirs).unwrap(),
+            Some(expected.clone())
+        );
+        assert_eq!(
+            merge_base(&backend, &theirs, &ours).unwrap(),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn merge_base_of_unrelated_histories_is_none() {
+        let mut backend = MemoryBackend::default();
+        let ours = write_commit(&mut backend, "ours", 1, &[]);
+        let ours = write_commit(&mut backend, "ours again", 2, &[&ours]);
+        let theirs = write_commit(&mut backend, "theirs", 1, &[]);
+
+        assert_eq!(merge_base(&backend, &ours, &theirs).unwrap(), None);
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::objects::{SyngObjectDef, SyngValue};
+
+/// One side of a merge conflict
+#[derive(Clone, Debug)]
+pub struct ConflictObject {
+    pub id: String,
+    pub path: Vec<usize>,
+    pub def: SyngObjectDef,
+
+    /// Node IDs of the children, in the order of `def.children`, `None` for the children without
+    /// one
+    pub child_node_ids: Vec<Option<String>>,
+}
+
+/// An object that was changed on both sides of a merge
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub base: ConflictObject,
+    pub ours: ConflictObject,
+    pub theirs: ConflictObject,
+}
+
+#[derive(Clone, Debug)]
+pub enum ConflictResolution {
+    /// Keep our (local) version of the object along with its subtree
+    TakeOurs,
+
+    /// Keep their (remote) version of the object along with its subtree
+    TakeTheirs,
+
+    /// Replace the object with a new one. The children of the new object should be objects
+    /// available in the backend or on either side of the merge.
+    Merged(SyngObjectDef),
+
+    /// The resolver could not resolve the conflict, it is reported back as a merge conflict
+    Unresolved,
+}
+
+pub trait ConflictResolver {
+    fn resolve(&self, conflict: &Conflict) -> ConflictResolution;
+}
+
+/// Resolver that leaves every conflict unresolved
+pub struct NoResolution;
+
+impl ConflictResolver for NoResolution {
+    fn resolve(&self, _conflict: &Conflict) -> ConflictResolution {
+        ConflictResolution::Unresolved
+    }
+}
+
+/// Resolves every conflict with the local version
+pub struct PreferLocal;
+
+impl ConflictResolver for PreferLocal {
+    fn resolve(&self, _conflict: &Conflict) -> ConflictResolution {
+        ConflictResolution::TakeOurs
+    }
+}
+
+/// Resolves every conflict with the remote version
+pub struct PreferRemote;
+
+impl ConflictResolver for PreferRemote {
+    fn resolve(&self, _conflict: &Conflict) -> ConflictResolution {
+        ConflictResolution::TakeTheirs
+    }
+}
+
+/// Three-way merges the fields of an object key by key. Returns `None` if any key was changed
+/// differently on both sides.
+pub fn merge_fields(
+    base: &BTreeMap<String, SyngValue>,
+    ours: &BTreeMap<String, SyngValue>,
+    theirs: &BTreeMap<String, SyngValue>,
+) -> Option<BTreeMap<String, SyngValue>> {
+    merge_fields_with(base, ours, theirs, |_, _, _| None)
+}
+
+fn merge_fields_with(
+    base: &BTreeMap<String, SyngValue>,
+    ours: &BTreeMap<String, SyngValue>,
+    theirs: &BTreeMap<String, SyngValue>,
+    mut on_conflict: impl FnMut(
+        &str,
+        Option<&SyngValue>,
+        Option<&SyngValue>,
+    ) -> Option<Option<SyngValue>>,
+) -> Option<BTreeMap<String, SyngValue>> {
+    let keys: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+
+    let mut result = BTreeMap::new();
+
+    for key in keys {
+        let base_value = base.get(key);
+        let ours_value = ours.get(key);
+        let theirs_value = theirs.get(key);
+
+        let value = if ours_value == base_value || ours_value == theirs_value {
+            theirs_value.cloned()
+        } else if theirs_value == base_value {
+            ours_value.cloned()
+        } else {
+            on_conflict(key, ours_value, theirs_value)?
+        };
+
+        if let Some(value) = value {
+            result.insert(key.clone(), value);
+        }
+    }
+
+    Some(result)
+}
+
+/// Picks the children list changed by only one side. Returns `None` if both sides changed it.
+fn pick_children(base: &[String], ours: &[String], theirs: &[String]) -> Option<Vec<String>> {
+    if ours == base || ours == theirs {
+        Some(theirs.to_vec())
+    } else if theirs == base {
+        Some(ours.to_vec())
+    } else {
+        None
+    }
+}
+
+/// Merges fields key by key. The keys changed differently on both sides all take the value of the
+/// side with the newer timestamp in `timestamp_field` (an integer, or a string holding one), local
+/// winning ties or when timestamps are missing. The timestamp is the one of the whole object,
+/// there are no per-field timestamps, so the newer side wins every conflicting key even if the
+/// older side edited some of them later.
+///
+/// Children are only taken if one side left them untouched, otherwise the conflict is left
+/// unresolved.
+pub struct LastWriterWins {
+    pub timestamp_field: String,
+}
+
+impl LastWriterWins {
+    pub fn new(timestamp_field: &str) -> Self {
+        Self {
+            timestamp_field: timestamp_field.to_owned(),
+        }
+    }
+
+    fn timestamp(&self, def: &SyngObjectDef) -> Option<u64> {
+        match def.fields.get(&self.timestamp_field)? {
+            SyngValue::Integer(timestamp) => u64::try_from(*timestamp).ok(),
+            SyngValue::String(timestamp) => timestamp.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl ConflictResolver for LastWriterWins {
+    fn resolve(&self, conflict: &Conflict) -> ConflictResolution {
+        let (base, ours, theirs) = (&conflict.base.def, &conflict.ours.def, &conflict.theirs.def);
+
+        let Some(children) = pick_children(&base.children, &ours.children, &theirs.children) else {
+            return ConflictResolution::Unresolved;
+        };
+
+        let theirs_is_newer = self.timestamp(theirs) > self.timestamp(ours);
+
+        let fields = merge_fields_with(
+            &base.fields,
+            &ours.fields,
+            &theirs.fields,
+            |_, ours_value, theirs_value| {
+                Some(if theirs_is_newer {
+                    theirs_value.cloned()
+                } else {
+                    ours_value.cloned()
+                })
+            },
+        );
+
+        match fields {
+            Some(fields) => ConflictResolution::Merged(SyngObjectDef { fields, children }),
+            None => ConflictResolution::Unresolved,
+        }
+    }
+}
+
+/// The children of the object paired with what identifies them across versions: their node ID,
+/// or their object ID if they don't have one
+fn keyed_children(obj: &ConflictObject) -> Vec<(&str, &String)> {
+    obj.def
+        .children
+        .iter()
+        .enumerate()
+        .map(|(index, id)| {
+            let node_id = obj.child_node_ids.get(index).and_then(Option::as_deref);
+
+            (node_id.unwrap_or(id), id)
+        })
+        .collect()
+}
+
+/// Keeps every child added on either side and drops every child removed on either side. Children
+/// added by the remote are placed after the local children.
+///
+/// Children are matched up across the versions by their node ID, so a child edited on one side is
+/// kept in its edited version. The conflict is left unresolved if both sides edited the same child
+/// differently, or if one side edited a child the other removed. Children without a node ID can
+/// only be matched by their object ID, so an edit of one looks like a removal plus an addition,
+/// and a child without a node ID edited on both sides ends up in the merged list in both versions.
+///
+/// Fields are merged key by key, the conflict is left unresolved if both sides changed the same
+/// field.
+pub struct UnionOfChildren;
+
+impl ConflictResolver for UnionOfChildren {
+    fn resolve(&self, conflict: &Conflict) -> ConflictResolution {
+        let (base, ours, theirs) = (&conflict.base.def, &conflict.ours.def, &conflict.theirs.def);
+
+        let Some(fields) = merge_fields(&base.fields, &ours.fields, &theirs.fields) else {
+            return ConflictResolution::Unresolved;
+        };
+
+        let base_children: HashMap<&str, &String> =
+            keyed_children(&conflict.base).into_iter().collect();
+        let ours_children = keyed_children(&conflict.ours);
+        let theirs_children = keyed_children(&conflict.theirs);
+
+        let ours_keys: BTreeSet<&str> = ours_children.iter().map(|(key, _)| *key).collect();
+        let theirs_by_key: HashMap<&str, &String> = theirs_children.iter().copied().collect();
+
+        let mut children = vec![];
+
+        for (key, ours_id) in &ours_children {
+            let child = match (base_children.get(key), theirs_by_key.get(key)) {
+                (None, None) => Some(*ours_id),
+                (None, Some(theirs_id)) if theirs_id == ours_id => Some(*ours_id),
+                (Some(base_id), None) if base_id == ours_id => None,
+                (Some(base_id), Some(theirs_id)) if base_id == ours_id => Some(*theirs_id),
+                (Some(base_id), Some(theirs_id))
+                    if base_id == theirs_id || theirs_id == ours_id =>
+                {
+                    Some(*ours_id)
+                }
+                // Added or edited differently on both sides, or edited by us and removed by them
+                _ => return ConflictResolution::Unresolved,
+            };
+
+            children.extend(child.cloned());
+        }
+
+        for (key, theirs_id) in &theirs_children {
+            if ours_keys.contains(key) {
+                continue;
+            }
+
+            match base_children.get(key) {
+                None => children.push((*theirs_id).clone()),
+                Some(base_id) if base_id == theirs_id => {}
+                // Edited by them and removed by us
+                Some(_) => return ConflictResolution::Unresolved,
+            }
+        }
+
+        ConflictResolution::Merged(SyngObjectDef { fields, children })
+    }
+}
+
+/// Picks the resolver to use based on the value of a field of the conflicting object, for
+/// example to merge objects differently by their `type`.
+pub struct ResolveByField {
+    field: String,
+    resolvers: HashMap<String, Box<dyn ConflictResolver>>,
+    fallback: Box<dyn ConflictResolver>,
+}
+
+impl ResolveByField {
+    /// Creates a resolver dispatching on `field`, leaving conflicts of unknown values unresolved
+    pub fn new(field: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            resolvers: HashMap::new(),
+            fallback: Box::new(NoResolution),
+        }
+    }
+
+    pub fn with(mut self, value: &str, resolver: impl ConflictResolver + 'static) -> Self {
+        self.resolvers.insert(value.to_owned(), Box::new(resolver));
+        self
+    }
+
+    pub fn fallback(mut self, resolver: impl ConflictResolver + 'static) -> Self {
+        self.fallback = Box::new(resolver);
+        self
+    }
+}
+
+impl ConflictResolver for ResolveByField {
+    fn resolve(&self, conflict: &Conflict) -> ConflictResolution {
+        let value = conflict
+            .ours
+            .def
+            .fields
+            .get(&self.field)
+            .or_else(|| conflict.theirs.def.fields.get(&self.field))
+            .and_then(SyngValue::as_str);
+
+        match value.and_then(|value| self.resolvers.get(value)) {
+            Some(resolver) => resolver.resolve(conflict),
+            None => self.fallback.resolve(conflict),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::NODE_ID_FIELD;
+
+    /// ID of the child object, and the node ID if it was given one
+    fn child(name: &str, node_id: Option<&str>) -> (String, Option<String>) {
+        let mut fields = BTreeMap::from([("name".to_owned(), name.into())]);
+
+        if let Some(node_id) = node_id {
+            fields.insert(NODE_ID_FIELD.to_owned(), node_id.into());
+        }
+
+        let def = SyngObjectDef {
+            fields,
+            children: vec![],
+        };
+
+        (def.get_hash().unwrap(), node_id.map(str::to_owned))
+    }
+
+    fn side(
+        fields: &[(&str, SyngValue)],
+        children: &[&(String, Option<String>)],
+    ) -> ConflictObject {
+        let def = SyngObjectDef {
+            fields: fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+            children: children.iter().map(|(id, _)| id.clone()).collect(),
+        };
+
+        ConflictObject {
+            id: def.get_hash().unwrap(),
+            path: vec![],
+            def,
+            child_node_ids: children
+                .iter()
+                .map(|(_, node_id)| node_id.clone())
+                .collect(),
+        }
+    }
+
+    fn conflict(base: ConflictObject, ours: ConflictObject, theirs: ConflictObject) -> Conflict {
+        Conflict { base, ours, theirs }
+    }
+
+    fn merged(resolution: ConflictResolution) -> SyngObjectDef {
+        match resolution {
+            ConflictResolution::Merged(def) => def,
+            other => panic!("expected a merged object, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn merge_fields_combines_keys_changed_on_one_side() {
+        let base = BTreeMap::from([("a".to_owned(), 1.into()), ("b".to_owned(), 2.into())]);
+        let ours = BTreeMap::from([("a".to_owned(), 10.into()), ("b".to_owned(), 2.into())]);
+        let theirs = BTreeMap::from([("a".to_owned(), 1.into()), ("c".to_owned(), 3.into())]);
+
+        assert_eq!(
+            merge_fields(&base, &ours, &theirs),
+            Some(BTreeMap::from([
+                ("a".to_owned(), 10.into()),
+                ("c".to_owned(), 3.into())
+            ]))
+        );
+
+        let theirs = BTreeMap::from([("a".to_owned(), 20.into()), ("b".to_owned(), 2.into())]);
+
+        assert_eq!(merge_fields(&base, &ours, &theirs), None);
+    }
+
+    #[test]
+    fn last_writer_wins_takes_conflicting_keys_from_the_newer_side() {
+        let base = side(
+            &[("t", 1.into()), ("a", "a".into()), ("b", "b".into())],
+            &[],
+        );
+        let ours = side(
+            &[("t", 2.into()), ("a", "ours".into()), ("b", "b2".into())],
+            &[],
+        );
+        let theirs = side(
+            &[("t", 3.into()), ("a", "theirs".into()), ("b", "b".into())],
+            &[],
+        );
+
+        let resolved = merged(LastWriterWins::new("t").resolve(&conflict(base, ours, theirs)));
+
+        assert_eq!(resolved.fields["t"], 3.into());
+        assert_eq!(resolved.fields["a"], "theirs".into());
+        assert_eq!(resolved.fields["b"], "b2".into());
+    }
+
+    #[test]
+    fn last_writer_wins_prefers_local_on_ties_and_missing_timestamps() {
+        let base = side(&[("a", "a".into())], &[]);
+        let ours = side(&[("t", "5".into()), ("a", "ours".into())], &[]);
+        let theirs = side(&[("t", 5.into()), ("a", "theirs".into())], &[]);
+
+        let resolved =
+            merged(LastWriterWins::new("t").resolve(&conflict(base.clone(), ours.clone(), theirs)));
+        assert_eq!(resolved.fields["a"], "ours".into());
+
+        let theirs = side(&[("a", "theirs".into())], &[]);
+
+        let resolved = merged(LastWriterWins::new("t").resolve(&conflict(base, ours, theirs)));
+        assert_eq!(resolved.fields["a"], "ours".into());
+    }
+
+    #[test]
+    fn last_writer_wins_leaves_children_changed_on_both_sides_unresolved() {
+        let (a, b) = (child("a", None), child("b", None));
+
+        let resolution = LastWriterWins::new("t").resolve(&conflict(
+            side(&[], &[]),
+            side(&[("t", 2.into())], &[&a]),
+            side(&[("t", 1.into())], &[&b]),
+        ));
+
+        assert!(matches!(resolution, ConflictResolution::Unresolved));
+    }
+
+    #[test]
+    fn union_of_children_keeps_additions_and_removals_of_both_sides() {
+        let (a, b, c, d) = (
+            child("a", None),
+            child("b", None),
+            child("c", None),
+            child("d", None),
+        );
+
+        let resolved = merged(UnionOfChildren.resolve(&conflict(
+            side(&[], &[&a, &b]),
+            side(&[], &[&a, &b, &c]),
+            side(&[], &[&b, &d]),
+        )));
+
+        assert_eq!(resolved.children, vec![b.0, c.0, d.0]);
+    }
+
+    #[test]
+    fn union_of_children_takes_the_edited_version_of_a_child() {
+        let a = child("a", Some("node-a"));
+        let a2 = child("a2", Some("node-a"));
+        let b = child("b", Some("node-b"));
+
+        let resolved = merged(UnionOfChildren.resolve(&conflict(
+            side(&[], &[&a]),
+            side(&[], &[&a2]),
+            side(&[], &[&a, &b]),
+        )));
+
+        assert_eq!(resolved.children, vec![a2.0.clone(), b.0.clone()]);
+
+        let resolved = merged(UnionOfChildren.resolve(&conflict(
+            side(&[], &[&a]),
+            side(&[], &[&a, &b]),
+            side(&[], &[&a2]),
+        )));
+
+        assert_eq!(resolved.children, vec![a2.0, b.0]);
+    }
+
+    #[test]
+    fn union_of_children_leaves_a_child_edited_on_both_sides_unresolved() {
+        let a = child("a", Some("node-a"));
+        let ours_a = child("ours", Some("node-a"));
+        let theirs_a = child("theirs", Some("node-a"));
+
+        let resolution = UnionOfChildren.resolve(&conflict(
+            side(&[], &[&a]),
+            side(&[("x", 1.into())], &[&ours_a]),
+            side(&[], &[&theirs_a]),
+        ));
+
+        assert!(matches!(resolution, ConflictResolution::Unresolved));
+    }
+
+    #[test]
+    fn union_of_children_leaves_an_edit_of_a_removed_child_unresolved() {
+        let a = child("a", Some("node-a"));
+        let a2 = child("a2", Some("node-a"));
+        let b = child("b", Some("node-b"));
+
+        let resolution = UnionOfChildren.resolve(&conflict(
+            side(&[], &[&a, &b]),
+            side(&[], &[&b]),
+            side(&[], &[&a2, &b]),
+        ));
+
+        assert!(matches!(resolution, ConflictResolution::Unresolved));
+    }
+
+    #[test]
+    fn resolve_by_field_dispatches_on_the_field_value() {
+        let resolver = ResolveByField::new("type")
+            .with("local", PreferLocal)
+            .fallback(PreferRemote);
+
+        let with_type = |value: &str| side(&[("type", value.into())], &[]);
+
+        let resolution = resolver.resolve(&conflict(
+            with_type("local"),
+            with_type("local"),
+            with_type("local"),
+        ));
+        assert!(matches!(resolution, ConflictResolution::TakeOurs));
+
+        let resolution = resolver.resolve(&conflict(
+            with_type("other"),
+            with_type("other"),
+            with_type("other"),
+        ));
+        assert!(matches!(resolution, ConflictResolution::TakeTheirs));
+    }
+}
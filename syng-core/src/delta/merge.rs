@@ -0,0 +1,591 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    blob::SyngBlob,
+    conflict::{Conflict, ConflictObject, ConflictResolution, ConflictResolver, NoResolution},
+    error::SyngError,
+    objects::SyngObjectDef,
+    tree_ops::descendent_object_ids,
+};
+
+use super::SyngDelta;
+
+/// An object that was changed on both sides of a merge in a way that cannot be merged
+/// automatically
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MergeConflict {
+    /// Index path of the object from the root of the trees
+    pub path: Vec<usize>,
+
+    /// ID of the object in the common ancestor tree
+    pub base: String,
+
+    /// ID of the object in our (local) tree
+    pub ours: String,
+
+    /// ID of the object in their (remote) tree
+    pub theirs: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum MergeError {
+    /// An object referred to by one of the trees is not present in the backend
+    MissingObject(String),
+
+    /// The backend failed while reading the trees
+    BackendReadFailed(SyngError),
+
+    /// A merged object could not be hashed
+    ObjectHashFailed,
+
+    /// Both sides changed the same objects, nothing was merged
+    Conflicts(Vec<MergeConflict>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SyngMerge {
+    /// ID of the root object of the merged tree
+    pub root: String,
+
+    /// Delta that takes our tree to the merged tree
+    pub delta: SyngDelta,
+}
+
+impl From<SyngError> for MergeError {
+    fn from(value: SyngError) -> Self {
+        match value {
+            SyngError::ObjectNotFound(id) => MergeError::MissingObject(id),
+            e => MergeError::BackendReadFailed(e),
+        }
+    }
+}
+
+fn read_object(backend: &impl SyngBackend, id: &str) -> Result<SyngObjectDef, MergeError> {
+    backend
+        .read_object(id)?
+        .ok_or_else(|| MergeError::MissingObject(id.to_owned()))
+}
+
+struct TreeMerger<'a, B: SyngBackend> {
+    backend: &'a B,
+    resolver: &'a dyn ConflictResolver,
+    new_objects: HashMap<String, SyngObjectDef>,
+    conflicts: Vec<MergeConflict>,
+}
+
+impl<'a, B: SyngBackend> TreeMerger<'a, B> {
+    fn new(backend: &'a B, resolver: &'a dyn ConflictResolver) -> Self {
+        Self {
+            backend,
+            resolver,
+            new_objects: HashMap::new(),
+            conflicts: vec![],
+        }
+    }
+
+    fn read_object(&self, id: &str) -> Result<SyngObjectDef, MergeError> {
+        if let Some(obj) = self.new_objects.get(id) {
+            return Ok(obj.clone());
+        }
+
+        read_object(self.backend, id)
+    }
+
+    fn insert_object(&mut self, obj: SyngObjectDef) -> Result<String, MergeError> {
+        let id = self
+            .backend
+            .object_id(&obj)
+            .map_err(|_| MergeError::ObjectHashFailed)?;
+
+        self.new_objects.insert(id.clone(), obj);
+
+        Ok(id)
+    }
+
+    /// One side of a conflict, along with the node IDs of its children
+    fn conflict_object(
+        &self,
+        path: &[usize],
+        id: &str,
+        def: SyngObjectDef,
+    ) -> Result<ConflictObject, MergeError> {
+        // Chunks never have node IDs, and there can be a lot of them
+        let child_node_ids = if SyngBlob::from_object(&def).is_some() {
+            vec![None; def.children.len()]
+        } else {
+            def.children
+                .iter()
+                .map(|child| Ok(self.read_object(child)?.node_id().map(str::to_owned)))
+                .collect::<Result<_, MergeError>>()?
+        };
+
+        Ok(ConflictObject {
+            id: id.to_owned(),
+            path: path.to_vec(),
+            def,
+            child_node_ids,
+        })
+    }
+
+    /// Hands the conflict over to the resolver, recording it as a merge conflict if the resolver
+    /// can't resolve it
+    fn resolve_conflict(
+        &mut self,
+        path: &[usize],
+        (base, base_obj): (&str, SyngObjectDef),
+        (ours, ours_obj): (&str, SyngObjectDef),
+        (theirs, theirs_obj): (&str, SyngObjectDef),
+    ) -> Result<Option<String>, MergeError> {
+        let conflict = Conflict {
+            base: self.conflict_object(path, base, base_obj)?,
+            ours: self.conflict_object(path, ours, ours_obj)?,
+            theirs: self.conflict_object(path, theirs, theirs_obj)?,
+        };
+
+        match self.resolver.resolve(&conflict) {
+            ConflictResolution::TakeOurs => Ok(Some(ours.to_owned())),
+            ConflictResolution::TakeTheirs => Ok(Some(theirs.to_owned())),
+            ConflictResolution::Merged(obj) => Ok(Some(self.insert_object(obj)?)),
+            ConflictResolution::Unresolved => {
+                self.conflicts.push(MergeConflict {
+                    path: path.to_vec(),
+                    base: base.to_owned(),
+                    ours: ours.to_owned(),
+                    theirs: theirs.to_owned(),
+                });
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Merges the three versions of the object at `path`. Returns the ID of the merged object, or
+    /// `None` if the object (or something under it) conflicted.
+    fn merge_node(
+        &mut self,
+        path: &mut Vec<usize>,
+        base: &str,
+        ours: &str,
+        theirs: &str,
+    ) -> Result<Option<String>, MergeError> {
+        // Only one side (or neither) changed this subtree, so we can take it whole
+        if ours == theirs || base == theirs {
+            return Ok(Some(ours.to_owned()));
+        }
+
+        if base == ours {
+            return Ok(Some(theirs.to_owned()));
+        }
+
+        let base_obj = self.read_object(base)?;
+        let ours_obj = self.read_object(ours)?;
+        let theirs_obj = self.read_object(theirs)?;
+
+        // Merging the chunks of a blob changed on both sides one by one would splice together
+        // data neither side wrote, so the blob conflicts as a whole
+        if SyngBlob::from_object(&ours_obj).is_some()
+            || SyngBlob::from_object(&theirs_obj).is_some()
+        {
+            return self.resolve_conflict(
+                path,
+                (base, base_obj),
+                (ours, ours_obj),
+                (theirs, theirs_obj),
+            );
+        }
+
+        let fields = if ours_obj.fields == base_obj.fields || ours_obj.fields == theirs_obj.fields {
+            theirs_obj.fields.clone()
+        } else if theirs_obj.fields == base_obj.fields {
+            ours_obj.fields.clone()
+        } else {
+            return self.resolve_conflict(
+                path,
+                (base, base_obj),
+                (ours, ours_obj),
+                (theirs, theirs_obj),
+            );
+        };
+
+        let children = if ours_obj.children == base_obj.children
+            || ours_obj.children == theirs_obj.children
+        {
+            theirs_obj.children.clone()
+        } else if theirs_obj.children == base_obj.children {
+            ours_obj.children.clone()
+        } else if base_obj.children.len() == ours_obj.children.len()
+            && base_obj.children.len() == theirs_obj.children.len()
+        {
+            // Both sides changed things under this object without adding or removing children, so
+            // the children line up and can be merged one by one
+            let mut children = Vec::with_capacity(base_obj.children.len());
+            let mut conflicted = false;
+
+            for (index, ((base_child, ours_child), theirs_child)) in base_obj
+                .children
+                .iter()
+                .zip(ours_obj.children.iter())
+                .zip(theirs_obj.children.iter())
+                .enumerate()
+            {
+                path.push(index);
+                let merged_child = self.merge_node(path, base_child, ours_child, theirs_child)?;
+                path.pop();
+
+                match merged_child {
+                    Some(id) => children.push(id),
+                    None => conflicted = true,
+                }
+            }
+
+            if conflicted {
+                return Ok(None);
+            }
+
+            children
+        } else {
+            return self.resolve_conflict(
+                path,
+                (base, base_obj),
+                (ours, ours_obj),
+                (theirs, theirs_obj),
+            );
+        };
+
+        let merged_id = self.insert_object(SyngObjectDef { fields, children })?;
+
+        Ok(Some(merged_id))
+    }
+}
+
+/// Generates a delta from `start_point` to `new_root`, where the objects of the new tree are
+/// either in `new_objects` or in the backend. The new root is always in the delta, even when the
+/// start tree already has it, since [`apply_delta`](super::apply_delta) needs it there. So a merge
+/// that changes nothing still gives a delta that applies (and leaves the root where it is).
+pub(crate) fn build_delta(
+    backend: &impl SyngBackend,
+    new_objects: &HashMap<String, SyngObjectDef>,
+    start_point: &str,
+    new_root: &str,
+) -> Result<SyngDelta, MergeError> {
+    let start_tree_object_ids: BTreeSet<String> = descendent_object_ids(backend, start_point)
+        .map_err(MergeError::from)?
+        .into_iter()
+        .collect();
+
+    let mut delta_objects = HashMap::new();
+
+    // Only walk the subtrees the start point doesn't already have
+    let mut search_queue = vec![new_root.to_owned()];
+
+    while let Some(object_id) = search_queue.pop() {
+        let in_start_tree = object_id != new_root && start_tree_object_ids.contains(&object_id);
+
+        if in_start_tree || delta_objects.contains_key(&object_id) {
+            continue;
+        }
+
+        let obj = match new_objects.get(&object_id) {
+            Some(obj) => obj.clone(),
+            None => read_object(backend, &object_id)?,
+        };
+
+        search_queue.extend(obj.children.iter().cloned());
+        delta_objects.insert(object_id, obj);
+    }
+
+    Ok(SyngDelta {
+        start_point: Some(start_point.to_owned()),
+        new_root_node: new_root.to_owned(),
+        new_objects: delta_objects,
+    })
+}
+
+/// Three-way merges the trees at `ours` and `theirs`, using `base` as their common ancestor.
+///
+/// All three trees need to be readable from the backend (so the remote objects should be written
+/// in before merging). Nothing is written to the backend, the merged objects are only part of the
+/// returned delta, which goes from `ours` to the merged root and can be applied with
+/// [`apply_delta`](super::apply_delta).
+///
+/// Subtrees changed by only one side are taken from that side. Objects whose fields or children
+/// were changed by both sides are returned as [`MergeError::Conflicts`].
+pub fn merge(
+    backend: &impl SyngBackend,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+) -> Result<SyngMerge, MergeError> {
+    merge_with_resolver(backend, base, ours, theirs, &NoResolution)
+}
+
+/// Same as [`merge`], but objects changed by both sides are handed to `resolver` first. Only the
+/// conflicts it leaves unresolved are returned as [`MergeError::Conflicts`].
+pub fn merge_with_resolver(
+    backend: &impl SyngBackend,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    resolver: &dyn ConflictResolver,
+) -> Result<SyngMerge, MergeError> {
+    let (root, new_objects) = merge_trees(backend, base, ours, theirs, resolver)?;
+
+    let delta = build_delta(backend, &new_objects, ours, &root)?;
+
+    Ok(SyngMerge { root, delta })
+}
+
+/// Merges the trees, returning the merged root along with the objects created by the merge
+pub(crate) fn merge_trees(
+    backend: &impl SyngBackend,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    resolver: &dyn ConflictResolver,
+) -> Result<(String, HashMap<String, SyngObjectDef>), MergeError> {
+    let mut merger = TreeMerger::new(backend, resolver);
+
+    let merged_root = merger.merge_node(&mut vec![], base, ours, theirs)?;
+
+    let Some(root) = merged_root else {
+        return Err(MergeError::Conflicts(merger.conflicts));
+    };
+
+    Ok((root, merger.new_objects))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::{
+        conflict::UnionOfChildren,
+        delta::apply_delta,
+        objects::SyngValue,
+        testing::{named, MemoryBackend},
+    };
+
+    /// Takes our side of every conflict, remembering where they were
+    #[derive(Default)]
+    struct RecordingResolver {
+        paths: RefCell<Vec<Vec<usize>>>,
+    }
+
+    impl ConflictResolver for RecordingResolver {
+        fn resolve(&self, conflict: &Conflict) -> ConflictResolution {
+            self.paths.borrow_mut().push(conflict.ours.path.clone());
+
+            ConflictResolution::TakeOurs
+        }
+    }
+
+    /// Merges with `resolver` and describes the merged tree
+    fn merged(
+        backend: &mut MemoryBackend,
+        (base, ours, theirs): (&str, &str, &str),
+        resolver: &dyn ConflictResolver,
+    ) -> Result<String, MergeError> {
+        let base = backend.write_tree(base);
+        let ours = backend.write_tree(ours);
+        let theirs = backend.write_tree(theirs);
+
+        let merge = merge_with_resolver(backend, &base, &ours, &theirs, resolver)?;
+        backend.objects.extend(merge.delta.new_objects);
+
+        Ok(backend.describe(&merge.root))
+    }
+
+    fn conflict_paths(result: Result<String, MergeError>) -> Vec<Vec<usize>> {
+        match result {
+            Err(MergeError::Conflicts(conflicts)) => conflicts
+                .into_iter()
+                .map(|conflict| conflict.path)
+                .collect(),
+            other => panic!("expected conflicts, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn one_sided_edit_takes_the_edited_tree() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.write_tree("root(a, b)");
+        let edited = backend.write_tree("root(a2, b)");
+
+        assert_eq!(merge(&backend, &base, &edited, &base).unwrap().root, edited);
+        assert_eq!(merge(&backend, &base, &base, &edited).unwrap().root, edited);
+    }
+
+    #[test]
+    fn edits_to_different_children_are_combined() {
+        let mut backend = MemoryBackend::default();
+
+        let result = merged(
+            &mut backend,
+            ("root(a(x), b(y))", "root(a(x2), b(y))", "root(a(x), b(y2))"),
+            &NoResolution,
+        );
+
+        assert_eq!(result.unwrap(), "root(a(x2), b(y2))");
+    }
+
+    #[test]
+    fn same_edit_on_both_sides_is_taken_once() {
+        let mut backend = MemoryBackend::default();
+
+        let result = merged(
+            &mut backend,
+            ("root(a, b)", "root(a2, b)", "root(a2, b)"),
+            &NoResolution,
+        );
+        assert_eq!(result.unwrap(), "root(a2, b)");
+
+        // Both renamed the root, and only one side edited a child
+        let result = merged(
+            &mut backend,
+            ("root(a, b)", "renamed(a, b2)", "renamed(a, b)"),
+            &NoResolution,
+        );
+        assert_eq!(result.unwrap(), "renamed(a, b2)");
+    }
+
+    #[test]
+    fn different_edits_of_the_same_object_conflict() {
+        let mut backend = MemoryBackend::default();
+
+        let result = merged(
+            &mut backend,
+            ("root(a, b)", "root(a2, b)", "root(a3, b)"),
+            &NoResolution,
+        );
+
+        assert_eq!(conflict_paths(result), vec![vec![0]]);
+    }
+
+    #[test]
+    fn unequal_child_counts_go_to_the_resolver() {
+        let mut backend = MemoryBackend::default();
+        let trees = ("root(a)", "root(a, b)", "root(a, c)");
+
+        assert_eq!(
+            conflict_paths(merged(&mut backend, trees, &NoResolution)),
+            vec![vec![]]
+        );
+
+        let resolver = RecordingResolver::default();
+        let result = merged(&mut backend, trees, &resolver);
+
+        assert_eq!(result.unwrap(), "root(a, b)");
+        assert_eq!(*resolver.paths.borrow(), vec![Vec::<usize>::new()]);
+    }
+
+    #[test]
+    fn one_sided_delete_is_kept() {
+        let mut backend = MemoryBackend::default();
+
+        let result = merged(
+            &mut backend,
+            ("root(a(x, y), b)", "root(a(x), b)", "root(a(x, y), b2)"),
+            &NoResolution,
+        );
+        assert_eq!(result.unwrap(), "root(a(x), b2)");
+
+        let result = merged(
+            &mut backend,
+            ("root(a, b)", "root(a, b)", "root(b)"),
+            &NoResolution,
+        );
+        assert_eq!(result.unwrap(), "root(b)");
+    }
+
+    #[test]
+    fn delete_next_to_an_edit_of_a_sibling_goes_to_the_resolver() {
+        let mut backend = MemoryBackend::default();
+
+        let result = merged(
+            &mut backend,
+            ("root(a, b)", "root(a)", "root(a, b2)"),
+            &NoResolution,
+        );
+
+        assert_eq!(conflict_paths(result), vec![vec![]]);
+    }
+
+    #[test]
+    fn resolvers_can_match_children_by_node_id() {
+        let mut backend = MemoryBackend::default();
+
+        let a = named("a").with_new_node_id();
+        let mut a2 = a.clone();
+        a2.fields.insert("name".to_owned(), SyngValue::from("a2"));
+
+        let a = backend.write_object(&a).unwrap();
+        let a2 = backend.write_object(&a2).unwrap();
+        let b = backend.write_named("b", &[]);
+
+        let base = backend.write_named("root", &[&a]);
+        let ours = backend.write_named("root", &[&a2]);
+        let theirs = backend.write_named("root", &[&a, &b]);
+
+        let merge = merge_with_resolver(&backend, &base, &ours, &theirs, &UnionOfChildren).unwrap();
+        backend.objects.extend(merge.delta.new_objects);
+
+        assert_eq!(backend.describe(&merge.root), "root(a2, b)");
+    }
+
+    /// Merges the trees and applies the delta to a backend whose root is at `ours`, describing
+    /// the root after it
+    fn merged_and_applied((base, ours, theirs): (&str, &str, &str)) -> (SyngMerge, String) {
+        let mut backend = MemoryBackend::default();
+        let base = backend.write_tree(base);
+        let theirs = backend.write_tree(theirs);
+        let ours = backend.set_tree(ours);
+
+        let merge = merge(&backend, &base, &ours, &theirs).unwrap();
+        let (root, _) = apply_delta(&mut backend, &merge.delta).unwrap();
+
+        assert_eq!(root, merge.root);
+
+        (merge, backend.describe_root())
+    }
+
+    #[test]
+    fn merge_deltas_apply() {
+        let (merge, tree) =
+            merged_and_applied(("root(a(x), b(y))", "root(a(x2), b(y))", "root(a(x), b(y2))"));
+
+        assert_eq!(tree, "root(a(x2), b(y2))");
+
+        // Only the objects the merge changed are in the delta, `a(x2)` is already ours
+        let mut names = merge
+            .delta
+            .new_objects
+            .values()
+            .map(|obj| obj.fields["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        assert_eq!(names, ["b", "root", "y2"]);
+    }
+
+    #[test]
+    fn merges_changing_nothing_still_apply() {
+        // Only our side changed, so the merged tree is ours
+        let (merge, tree) = merged_and_applied(("root(a, b)", "root(a2, b)", "root(a, b)"));
+
+        assert_eq!(tree, "root(a2, b)");
+        assert_eq!(
+            merge.delta.start_point.as_deref(),
+            Some(merge.root.as_str())
+        );
+        assert_eq!(merge.delta.new_objects.len(), 1);
+
+        // Both sides made the same edit
+        let (_, tree) = merged_and_applied(("root(a, b)", "root(a2, b)", "root(a2, b)"));
+
+        assert_eq!(tree, "root(a2, b)");
+    }
+}
@@ -2,7 +2,19 @@ use std::collections::{BTreeSet, HashMap};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{backend::SyngBackend, objects::SyngObjectDef, tree_ops::get_descendent_object_ids};
+use crate::{
+    backend::SyngBackend,
+    commit::{record_commit, CommitInfo},
+    error::SyngError,
+    objects::SyngObjectDef,
+    tree_ops::{descendent_object_ids, read_referred_objects},
+};
+
+mod merge;
+mod rebase;
+
+pub use merge::{merge, merge_with_resolver, MergeConflict, MergeError, SyngMerge};
+pub use rebase::{rebase_delta, rebase_delta_with_resolver, RebaseError};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SyngDelta {
@@ -21,56 +33,157 @@ pub enum ApplyDeltaError {
 
     /// The root node specified in the delta is not a valid node in the object list
     DeltaNewRootNodeInvaid,
+
+    /// 1 or more objects in the delta are not stored under their own hash
+    HashMismatch(Vec<String>),
+
+    /// The backend failed reading its current state while validating the delta
+    BackendReadFailed(SyngError),
+
+    /// The backend failed writing an object of the delta
+    ObjectWriteFailed(SyngError),
+
+    /// The backend failed moving the root to the new root node
+    SetRootFailed(SyngError),
+
+    /// The backend failed starting or committing the transaction the delta is applied in
+    TransactionFailed(SyngError),
+
+    /// Recording the commit of the new root failed
+    CommitFailed(SyngError),
 }
 
-fn validate_delta(backend: &impl SyngBackend, delta: &SyngDelta) -> Result<(), ApplyDeltaError> {
-    // Check if start point is the current root tree of the backend
-    if backend.get_root_object_id() != delta.start_point {
-        return Err(ApplyDeltaError::CurrentTreeDrifted);
+#[derive(Clone, Debug)]
+pub struct ApplyDeltaOptions {
+    /// Check that every object in the delta is keyed by its own hash. Only worth turning off for
+    /// deltas that were generated locally.
+    pub verify_hashes: bool,
+
+    /// Record a commit of the new root in [`crate::commit::HISTORY_REF`], as part of the same
+    /// transaction
+    pub commit: Option<CommitInfo>,
+}
+
+impl Default for ApplyDeltaOptions {
+    fn default() -> Self {
+        Self {
+            verify_hashes: true,
+            commit: None,
+        }
     }
+}
 
+/// Checks of the delta that don't depend on the backend
+pub(crate) fn validate_delta_objects(
+    delta: &SyngDelta,
+    options: &ApplyDeltaOptions,
+) -> Result<(), ApplyDeltaError> {
     // Check if new_root_node is in the new_objects list
     if !delta.new_objects.contains_key(&delta.new_root_node) {
         return Err(ApplyDeltaError::DeltaNewRootNodeInvaid);
     }
 
-    // Check if all the objects on the tree properly resolve out
-    // into valid nodes that exist
-    let mut unresolved_nodes = vec![];
-
-    for object in delta.new_objects.values() {
-        for child_node_id in &object.children {
-            // The object should either be resolvable by the backend or the delta
-            if !backend.has_object(&child_node_id) && !delta.new_objects.contains_key(child_node_id)
-            {
-                unresolved_nodes.push(child_node_id.clone());
-            }
+    // Check if the objects are what their IDs say they are, an object under the wrong ID would
+    // corrupt the store. Every ID is checked with its own algorithm, so deltas from backends
+    // hashing with another algorithm (or a mix of them) are accepted too.
+    if options.verify_hashes {
+        let mut mismatched_ids = delta
+            .new_objects
+            .iter()
+            .filter(|(id, object)| object.get_hash_like(id).ok().as_ref() != Some(*id))
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+
+        if !mismatched_ids.is_empty() {
+            mismatched_ids.sort();
+
+            return Err(ApplyDeltaError::HashMismatch(mismatched_ids));
         }
     }
 
-    if unresolved_nodes.len() > 0 {
+    Ok(())
+}
+
+fn validate_delta(
+    backend: &impl SyngBackend,
+    delta: &SyngDelta,
+    options: &ApplyDeltaOptions,
+) -> Result<(), ApplyDeltaError> {
+    // Check if start point is the current root tree of the backend
+    let root_object_id = backend
+        .get_root_object_id()
+        .map_err(ApplyDeltaError::BackendReadFailed)?;
+
+    if root_object_id != delta.start_point {
+        return Err(ApplyDeltaError::CurrentTreeDrifted);
+    }
+
+    validate_delta_objects(delta, options)?;
+
+    // Check if all the objects on the tree properly resolve out into valid nodes that exist. A
+    // child should either be in the delta or in the backend, the ones that aren't in the delta are
+    // looked up in one batch
+    let mut backend_child_ids = delta
+        .new_objects
+        .values()
+        .flat_map(|object| object.children.iter().map(String::as_str))
+        .filter(|child_node_id| !delta.new_objects.contains_key(*child_node_id))
+        .collect::<Vec<_>>();
+
+    backend_child_ids.sort();
+    backend_child_ids.dedup();
+
+    let backend_children = backend
+        .read_objects(&backend_child_ids)
+        .map_err(ApplyDeltaError::BackendReadFailed)?;
+
+    let unresolved_nodes = backend_child_ids
+        .into_iter()
+        .zip(backend_children)
+        .filter(|(_, child)| child.is_none())
+        .map(|(child_node_id, _)| child_node_id.to_owned())
+        .collect::<Vec<_>>();
+
+    if !unresolved_nodes.is_empty() {
         return Err(ApplyDeltaError::DeltaMissingObjects(unresolved_nodes));
     }
 
     Ok(())
 }
 
+/// Applies the delta onto the backend. The writes happen in a transaction on backends that support
+/// them, on other backends the root is only moved once every object has been written.
 pub fn apply_delta(
     backend: &mut impl SyngBackend,
     delta: &SyngDelta,
+) -> Result<(String, SyngObjectDef), ApplyDeltaError> {
+    apply_delta_with_options(backend, delta, &ApplyDeltaOptions::default())
+}
+
+pub fn apply_delta_with_options(
+    backend: &mut impl SyngBackend,
+    delta: &SyngDelta,
+    options: &ApplyDeltaOptions,
 ) -> Result<(String, SyngObjectDef), ApplyDeltaError> {
     // Try validating and see if the delta actually makes sense for this backend
-    validate_delta(backend, delta)?;
+    validate_delta(backend, delta, options)?;
+
+    backend
+        .begin_transaction()
+        .map_err(ApplyDeltaError::TransactionFailed)?;
+
+    if let Err(e) = write_delta(backend, delta, options) {
+        // The write error is more useful to the caller than a failed rollback
+        let _ = backend.rollback_transaction();
 
-    for object in delta.new_objects.values() {
-        backend
-            .write_object(object)
-            .expect("Failed writing object while delta is being resolved");
+        return Err(e);
     }
 
-    backend
-        .set_root_object(&delta.new_root_node)
-        .expect("Failed setting root object while the delta is being resolved");
+    if let Err(e) = backend.commit_transaction() {
+        let _ = backend.rollback_transaction();
+
+        return Err(ApplyDeltaError::TransactionFailed(e));
+    }
 
     Ok((
         delta.new_root_node.clone(),
@@ -78,32 +191,184 @@ pub fn apply_delta(
     ))
 }
 
+fn write_delta(
+    backend: &mut impl SyngBackend,
+    delta: &SyngDelta,
+    options: &ApplyDeltaOptions,
+) -> Result<(), ApplyDeltaError> {
+    // The objects keep the IDs of the backend that generated the delta, which might hash with
+    // another algorithm
+    let objects = delta
+        .new_objects
+        .iter()
+        .map(|(id, object)| (id.as_str(), object))
+        .collect::<Vec<_>>();
+
+    backend
+        .write_objects_with_ids(&objects)
+        .map_err(ApplyDeltaError::ObjectWriteFailed)?;
+
+    // The root could have moved since the delta was validated, so only move it if it is still at
+    // the start point
+    let root_updated = backend
+        .compare_and_set_root(delta.start_point.as_deref(), &delta.new_root_node)
+        .map_err(ApplyDeltaError::SetRootFailed)?;
+
+    if !root_updated {
+        return Err(ApplyDeltaError::CurrentTreeDrifted);
+    }
+
+    if let Some(info) = &options.commit {
+        record_commit(backend, &delta.new_root_node, info)
+            .map_err(ApplyDeltaError::CommitFailed)?;
+    }
+
+    Ok(())
+}
+
 pub fn generate_delta_from_point(
     backend: &impl SyngBackend,
     past_head_object_id: &str,
-) -> Option<SyngDelta> {
+) -> Result<SyngDelta, SyngError> {
     // Storing this into a BTreeSet so that we can binary search through the objects
     let past_tree_object_ids: BTreeSet<String> =
-        get_descendent_object_ids(backend, past_head_object_id)?
+        descendent_object_ids(backend, past_head_object_id)?
             .into_iter()
             .collect();
 
-    let current_head_id = backend.get_root_object_id()?;
-    let current_tree_object_ids = get_descendent_object_ids(backend, &current_head_id)?;
+    let current_head_id = backend
+        .get_root_object_id()?
+        .ok_or(SyngError::NoRootObject)?;
 
-    let new_objects = current_tree_object_ids
-        .iter()
-        .filter(|obj_id| !past_tree_object_ids.contains(*obj_id))
-        .map(|obj_id| {
-            // This unwrap is safe because we get it already from the tree
-            let obj = backend.read_object(obj_id).unwrap();
-            (obj_id.clone(), obj)
-        })
-        .collect::<HashMap<String, SyngObjectDef>>();
-
-    Some(SyngDelta {
+    let mut new_objects = HashMap::new();
+
+    // Walk the current tree a level at a time, skipping the subtrees the past tree already has
+    let mut level = vec![current_head_id.clone()];
+
+    while !level.is_empty() {
+        let objs = read_referred_objects(backend, &level)?;
+
+        let mut next_level = vec![];
+
+        for (obj_id, obj) in level.into_iter().zip(objs) {
+            next_level.extend(
+                obj.children
+                    .iter()
+                    .filter(|child_id| !past_tree_object_ids.contains(*child_id))
+                    .cloned(),
+            );
+
+            new_objects.insert(obj_id, obj);
+        }
+
+        next_level.sort();
+        next_level.dedup();
+        next_level.retain(|child_id| !new_objects.contains_key(child_id));
+
+        level = next_level;
+    }
+
+    Ok(SyngDelta {
         start_point: Some(past_head_object_id.to_string()),
         new_root_node: current_head_id,
         new_objects,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{named, MemoryBackend};
+
+    /// The delta taking the tree at `base` to `tree`, made on a copy of the backend so that its
+    /// objects are only in the delta
+    fn local_delta(backend: &MemoryBackend, base: &str, tree: &str) -> SyngDelta {
+        let mut local = backend.clone();
+        local.set_root_object(base).unwrap();
+        local.set_tree(tree);
+
+        generate_delta_from_point(&local, base).unwrap()
+    }
+
+    #[test]
+    fn applies_deltas() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a)");
+        let delta = local_delta(&backend, &base, "root(a, b(c))");
+
+        let (root, _) = apply_delta(&mut backend, &delta).unwrap();
+
+        assert_eq!(root, delta.new_root_node);
+        assert_eq!(backend.describe_root(), "root(a, b(c))");
+    }
+
+    #[test]
+    fn failed_writes_leave_nothing_behind() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a)");
+        let delta = local_delta(&backend, &base, "root(a, b(c), d)");
+        assert!(delta.new_objects.len() > 2);
+
+        let objects = backend.objects.clone();
+        backend.writes_left = Some(1);
+
+        assert!(matches!(
+            apply_delta(&mut backend, &delta),
+            Err(ApplyDeltaError::ObjectWriteFailed(_))
+        ));
+
+        assert_eq!(backend.get_root_object_id().unwrap(), Some(base));
+        assert_eq!(
+            backend.objects.keys().collect::<BTreeSet<_>>(),
+            objects.keys().collect::<BTreeSet<_>>()
+        );
+    }
+
+    #[test]
+    fn rejects_objects_that_do_not_hash_to_their_id() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a)");
+        let mut delta = local_delta(&backend, &base, "root(a, b, c)");
+
+        let b = backend.object_id(&named("b")).unwrap();
+        let c = backend.object_id(&named("c")).unwrap();
+        delta.new_objects.insert(b.clone(), named("not b"));
+        delta.new_objects.insert(c.clone(), named("not c"));
+
+        let mut expected = vec![b.clone(), c];
+        expected.sort();
+
+        assert!(matches!(
+            apply_delta(&mut backend, &delta),
+            Err(ApplyDeltaError::HashMismatch(ids)) if ids == expected
+        ));
+        assert_eq!(backend.describe_root(), "root(a)");
+
+        // Locally generated deltas can skip the check
+        let options = ApplyDeltaOptions {
+            verify_hashes: false,
+            ..ApplyDeltaOptions::default()
+        };
+
+        apply_delta_with_options(&mut backend, &delta, &options).unwrap();
+        assert_eq!(backend.describe_root(), "root(a, not b, not c)");
+    }
+
+    #[test]
+    fn does_not_overwrite_a_root_moved_while_applying() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a)");
+        let delta = local_delta(&backend, &base, "root(a, b)");
+
+        // Another client moves the root after the delta was validated
+        let other = backend.write_tree("other");
+        backend.concurrent_root = Some(other.clone());
+
+        assert!(matches!(
+            apply_delta(&mut backend, &delta),
+            Err(ApplyDeltaError::CurrentTreeDrifted)
+        ));
+        assert_eq!(backend.get_root_object_id().unwrap(), Some(other));
+        assert!(!backend.objects.contains_key(&delta.new_root_node));
+    }
+}
@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    conflict::{ConflictResolver, NoResolution},
+    error::SyngError,
+    objects::SyngObjectDef,
+};
+
+use super::{
+    merge::{build_delta, merge_trees},
+    MergeError, SyngDelta,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum RebaseError {
+    /// The delta has no start point, so there is no base to replay its changes from
+    DeltaHasNoStartPoint,
+
+    /// Replaying the changes onto the new base failed
+    MergeFailed(MergeError),
+}
+
+impl From<MergeError> for RebaseError {
+    fn from(value: MergeError) -> Self {
+        RebaseError::MergeFailed(value)
+    }
+}
+
+fn read_only_error() -> SyngError {
+    SyngError::StorageFailed("Delta overlays are read only".to_owned())
+}
+
+/// Read only view of a backend with the objects of a delta layered on top of it
+struct DeltaOverlay<'a, B: SyngBackend> {
+    backend: &'a B,
+    delta: &'a SyngDelta,
+}
+
+impl<'a, B: SyngBackend> SyngBackend for DeltaOverlay<'a, B> {
+    fn get_root_object_id(&self) -> Result<Option<String>, SyngError> {
+        self.backend.get_root_object_id()
+    }
+
+    fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError> {
+        self.backend.get_root_object()
+    }
+
+    fn set_root_object(&mut self, _node_id: &str) -> Result<(), SyngError> {
+        Err(read_only_error())
+    }
+
+    fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError> {
+        match self.delta.new_objects.get(id) {
+            Some(obj) => Ok(Some(obj.clone())),
+            None => self.backend.read_object(id),
+        }
+    }
+
+    fn read_objects(&self, ids: &[&str]) -> Result<Vec<Option<SyngObjectDef>>, SyngError> {
+        // Only the objects missing from the delta go to the backend, still in one batch
+        let backend_ids = ids
+            .iter()
+            .copied()
+            .filter(|id| !self.delta.new_objects.contains_key(*id))
+            .collect::<Vec<_>>();
+
+        let mut backend_objs = self.backend.read_objects(&backend_ids)?.into_iter();
+
+        Ok(ids
+            .iter()
+            .map(|id| match self.delta.new_objects.get(*id) {
+                Some(obj) => Some(obj.clone()),
+                None => backend_objs.next().flatten(),
+            })
+            .collect())
+    }
+
+    fn write_object(&mut self, _def: &SyngObjectDef) -> Result<String, SyngError> {
+        Err(read_only_error())
+    }
+}
+
+/// Replays the changes in `delta` on top of the tree at `new_base`, like `git pull --rebase`.
+///
+/// The tree at the delta's start point and the tree at `new_base` should be readable from the
+/// backend, the objects of the delta itself don't need to be. The returned delta starts at
+/// `new_base`, so it can be applied with [`apply_delta`](super::apply_delta) once the backend's
+/// root is at `new_base`.
+pub fn rebase_delta(
+    backend: &impl SyngBackend,
+    delta: &SyngDelta,
+    new_base: &str,
+) -> Result<SyngDelta, RebaseError> {
+    rebase_delta_with_resolver(backend, delta, new_base, &NoResolution)
+}
+
+/// Same as [`rebase_delta`], but objects changed both by the delta and since its start point are
+/// handed to `resolver` first
+pub fn rebase_delta_with_resolver(
+    backend: &impl SyngBackend,
+    delta: &SyngDelta,
+    new_base: &str,
+    resolver: &dyn ConflictResolver,
+) -> Result<SyngDelta, RebaseError> {
+    let Some(start_point) = &delta.start_point else {
+        return Err(RebaseError::DeltaHasNoStartPoint);
+    };
+
+    // Nothing moved, the delta already applies
+    if start_point == new_base {
+        return Ok(delta.clone());
+    }
+
+    let overlay = DeltaOverlay { backend, delta };
+
+    let (root, new_objects) = merge_trees(
+        &overlay,
+        start_point,
+        &delta.new_root_node,
+        new_base,
+        resolver,
+    )?;
+
+    Ok(build_delta(&overlay, &new_objects, new_base, &root)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        conflict::{PreferLocal, PreferRemote},
+        delta::{apply_delta, generate_delta_from_point},
+        testing::MemoryBackend,
+    };
+
+    /// The delta taking the tree at `base` to `tree`, made on a copy of the backend so that its
+    /// objects are only in the delta
+    fn local_delta(backend: &MemoryBackend, base: &str, tree: &str) -> SyngDelta {
+        let mut local = backend.clone();
+        local.set_root_object(base).unwrap();
+        local.set_tree(tree);
+
+        generate_delta_from_point(&local, base).unwrap()
+    }
+
+    /// Makes a delta from `base` to `ours`, moves the backend to `upstream` and rebases the delta
+    /// onto it with `resolver`, then applies the rebased delta and describes the root
+    fn rebased_and_applied(
+        (base, ours, upstream): (&str, &str, &str),
+        resolver: &dyn ConflictResolver,
+    ) -> Result<String, RebaseError> {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree(base);
+        let delta = local_delta(&backend, &base, ours);
+        let upstream = backend.set_tree(upstream);
+
+        let rebased = rebase_delta_with_resolver(&backend, &delta, &upstream, resolver)?;
+
+        assert_eq!(rebased.start_point.as_deref(), Some(upstream.as_str()));
+
+        apply_delta(&mut backend, &rebased).unwrap();
+
+        Ok(backend.describe_root())
+    }
+
+    #[test]
+    fn replays_changes_onto_the_new_base() {
+        let result = rebased_and_applied(
+            ("root(a(x), b(y))", "root(a(x2), b(y))", "root(a(x), b(y2))"),
+            &NoResolution,
+        );
+
+        assert_eq!(result.unwrap(), "root(a(x2), b(y2))");
+    }
+
+    #[test]
+    fn conflicts_go_to_the_resolver() {
+        let trees = ("root(a, b)", "root(a2, b)", "root(a3, b)");
+
+        match rebased_and_applied(trees, &NoResolution) {
+            Err(RebaseError::MergeFailed(MergeError::Conflicts(conflicts))) => {
+                assert_eq!(conflicts.len(), 1);
+                assert_eq!(conflicts[0].path, vec![0]);
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+
+        assert_eq!(
+            rebased_and_applied(trees, &PreferLocal).unwrap(),
+            "root(a2, b)"
+        );
+        assert_eq!(
+            rebased_and_applied(trees, &PreferRemote).unwrap(),
+            "root(a3, b)"
+        );
+    }
+
+    #[test]
+    fn changes_already_upstream_give_a_delta_that_applies() {
+        // Someone else pushed the same change
+        let result =
+            rebased_and_applied(("root(a, b)", "root(a2, b)", "root(a2, b)"), &NoResolution);
+        assert_eq!(result.unwrap(), "root(a2, b)");
+
+        // And the same change along with another one
+        let result =
+            rebased_and_applied(("root(a, b)", "root(a2, b)", "root(a2, b2)"), &NoResolution);
+        assert_eq!(result.unwrap(), "root(a2, b2)");
+    }
+
+    #[test]
+    fn deltas_already_on_the_new_base_are_kept() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a, b)");
+        let delta = local_delta(&backend, &base, "root(a2, b)");
+
+        let rebased = rebase_delta(&backend, &delta, &base).unwrap();
+
+        assert_eq!(rebased.new_root_node, delta.new_root_node);
+
+        apply_delta(&mut backend, &rebased).unwrap();
+        assert_eq!(backend.describe_root(), "root(a2, b)");
+    }
+
+    #[test]
+    fn deltas_without_a_start_point_cannot_be_rebased() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a, b)");
+
+        let mut delta = local_delta(&backend, &base, "root(a2, b)");
+        delta.start_point = None;
+
+        assert!(matches!(
+            rebase_delta(&backend, &delta, &base),
+            Err(RebaseError::DeltaHasNoStartPoint)
+        ));
+    }
+}
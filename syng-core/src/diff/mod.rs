@@ -0,0 +1,661 @@
+//! Structural diff between two trees.
+//!
+//! Where [`crate::delta`] only tells which objects are new, [`diff`] tells what changed and where:
+//! subtrees added or removed, objects whose fields changed, children that were reordered and nodes
+//! that moved to another parent. Objects are compared by hash first, so identical subtrees are
+//! skipped without being read and diffing two large trees with a small change only reads the
+//! objects along the changed paths.
+//!
+//! Children that are identical in both versions are lined up first. An edited child with a node
+//! ID (see [`NODE_ID_FIELD`](crate::objects::NODE_ID_FIELD)) is then matched to the child with the
+//! same node ID, and one that ended up under another parent is reported as [`TreeChange::Moved`].
+//! Children without a node ID are matched by position: the changed ones left between the same
+//! identical neighbours are paired in order.
+//!
+//! Moves are only found for the nodes that were themselves added and removed, a node moved out of
+//! a subtree that was removed as a whole shows up as part of that removal.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    error::SyngError,
+    objects::{SyngObjectDef, SyngValue},
+    tree_ops::read_referred_objects,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub key: String,
+
+    /// `None` if the field was added
+    pub old: Option<SyngValue>,
+
+    /// `None` if the field was removed
+    pub new: Option<SyngValue>,
+}
+
+/// A child that stayed the same but changed position
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChildMove {
+    pub id: String,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Paths are index paths from the root, in the old tree for things that only exist there and in
+/// the new tree otherwise
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TreeChange {
+    /// The subtree at `path` in the new tree was added
+    Added { path: Vec<usize>, id: String },
+
+    /// The subtree at `path` in the old tree was removed
+    Removed { path: Vec<usize>, id: String },
+
+    /// The fields of the object changed. Changes further down the subtree are reported separately.
+    FieldsChanged {
+        old_path: Vec<usize>,
+        new_path: Vec<usize>,
+        changes: Vec<FieldChange>,
+    },
+
+    /// Children of the object at `path` in the new tree were moved around. Only the children
+    /// that are out of order are listed.
+    Reordered {
+        path: Vec<usize>,
+        moves: Vec<ChildMove>,
+    },
+
+    /// The node with `node_id` moved from `old_path` to `new_path`, under another parent, where
+    /// it is the object `id`. Changes to the node itself are reported separately.
+    Moved {
+        node_id: String,
+        old_path: Vec<usize>,
+        new_path: Vec<usize>,
+        id: String,
+    },
+}
+
+/// An object in the old tree matched to its version in the new tree
+struct NodePair {
+    old_path: Vec<usize>,
+    new_path: Vec<usize>,
+    old_id: String,
+    new_id: String,
+}
+
+impl NodePair {
+    /// The pair of roots to start from, `None` if the trees are the same
+    fn roots(old_root: &str, new_root: &str) -> Option<Self> {
+        (old_root != new_root).then(|| Self {
+            old_path: vec![],
+            new_path: vec![],
+            old_id: old_root.to_owned(),
+            new_id: new_root.to_owned(),
+        })
+    }
+}
+
+/// Objects read so far. The changed children are read to match them up by node ID, and then
+/// compared without being read again.
+#[derive(Default)]
+struct ObjectCache {
+    objects: HashMap<String, SyngObjectDef>,
+}
+
+impl ObjectCache {
+    /// Reads the objects that weren't read yet, in one batch
+    fn load(&mut self, backend: &impl SyngBackend, ids: Vec<String>) -> Result<(), SyngError> {
+        let mut missing = ids;
+        missing.retain(|id| !self.objects.contains_key(id));
+        missing.sort();
+        missing.dedup();
+
+        let objects = read_referred_objects(backend, &missing)?;
+        self.objects.extend(missing.into_iter().zip(objects));
+
+        Ok(())
+    }
+
+    /// The object `id`, which has to be loaded
+    fn get(&self, id: &str) -> &SyngObjectDef {
+        &self.objects[id]
+    }
+
+    fn node_id(&self, id: &str) -> Option<&str> {
+        self.get(id).node_id()
+    }
+}
+
+fn child_path(parent: &[usize], index: usize) -> Vec<usize> {
+    let mut path = parent.to_vec();
+    path.push(index);
+
+    path
+}
+
+fn diff_fields(old: &SyngObjectDef, new: &SyngObjectDef) -> Vec<FieldChange> {
+    let keys: BTreeSet<&String> = old.fields.keys().chain(new.fields.keys()).collect();
+
+    keys.into_iter()
+        .filter(|key| old.fields.get(*key) != new.fields.get(*key))
+        .map(|key| FieldChange {
+            key: key.clone(),
+            old: old.fields.get(key).cloned(),
+            new: new.fields.get(key).cloned(),
+        })
+        .collect()
+}
+
+/// Returns the indexes of a longest strictly increasing subsequence of `values`
+fn longest_increasing_subsequence(values: &[usize]) -> BTreeSet<usize> {
+    // tails[k] is the index of the smallest value ending an increasing run of length k + 1
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; values.len()];
+
+    for (i, value) in values.iter().enumerate() {
+        let len = tails.partition_point(|&tail| values[tail] < *value);
+
+        if len > 0 {
+            previous[i] = Some(tails[len - 1]);
+        }
+
+        if len == tails.len() {
+            tails.push(i);
+        } else {
+            tails[len] = i;
+        }
+    }
+
+    let mut result = BTreeSet::new();
+    let mut current = tails.last().copied();
+
+    while let Some(i) = current {
+        result.insert(i);
+        current = previous[i];
+    }
+
+    result
+}
+
+/// The children of a pair that are not identical to one in the other version, as the runs of old
+/// and new indexes between two identical children that kept their order
+type UnmatchedRuns = Vec<(Vec<usize>, Vec<usize>)>;
+
+/// Lines up the children of a pair that are identical in both versions, adding the reorders it
+/// finds and returning the children left over
+fn match_identical_children(
+    pair: &NodePair,
+    old: &SyngObjectDef,
+    new: &SyngObjectDef,
+    changes: &mut Vec<TreeChange>,
+) -> UnmatchedRuns {
+    // Match identical children first, in order when the same child appears more than once
+    let mut old_positions: HashMap<&str, VecDeque<usize>> = HashMap::new();
+
+    for (index, id) in old.children.iter().enumerate() {
+        old_positions.entry(id).or_default().push_back(index);
+    }
+
+    let mut old_matched = vec![false; old.children.len()];
+    let mut new_matched = vec![false; new.children.len()];
+    let mut matched = vec![];
+
+    for (new_index, id) in new.children.iter().enumerate() {
+        if let Some(old_index) = old_positions
+            .get_mut(id.as_str())
+            .and_then(VecDeque::pop_front)
+        {
+            old_matched[old_index] = true;
+            new_matched[new_index] = true;
+            matched.push((old_index, new_index));
+        }
+    }
+
+    // The identical children that kept their relative order anchor the rest, any others moved
+    let old_indexes = matched.iter().map(|(old, _)| *old).collect::<Vec<_>>();
+    let in_order = longest_increasing_subsequence(&old_indexes);
+
+    let moves = matched
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !in_order.contains(i))
+        .map(|(_, (from, to))| ChildMove {
+            id: new.children[*to].clone(),
+            from: *from,
+            to: *to,
+        })
+        .collect::<Vec<_>>();
+
+    if !moves.is_empty() {
+        changes.push(TreeChange::Reordered {
+            path: pair.new_path.clone(),
+            moves,
+        });
+    }
+
+    let mut anchors = in_order.iter().map(|i| matched[*i]).collect::<Vec<_>>();
+    anchors.push((old.children.len(), new.children.len()));
+
+    let (mut old_start, mut new_start) = (0, 0);
+    let mut runs = vec![];
+
+    for (old_end, new_end) in anchors {
+        runs.push((
+            (old_start..old_end).filter(|i| !old_matched[*i]).collect(),
+            (new_start..new_end).filter(|i| !new_matched[*i]).collect(),
+        ));
+
+        (old_start, new_start) = (old_end + 1, new_end + 1);
+    }
+
+    runs
+}
+
+/// Pairs up the children left over by [`match_identical_children`], which have to be loaded in
+/// `cache`. Adds the children that have no counterpart as added or removed, and returns the pairs
+/// that need to be compared further.
+fn pair_changed_children(
+    pair: &NodePair,
+    runs: UnmatchedRuns,
+    cache: &ObjectCache,
+    changes: &mut Vec<TreeChange>,
+) -> Vec<NodePair> {
+    let old = cache.get(&pair.old_id);
+    let new = cache.get(&pair.new_id);
+
+    let child_pair = |old_index: usize, new_index: usize| NodePair {
+        old_path: child_path(&pair.old_path, old_index),
+        new_path: child_path(&pair.new_path, new_index),
+        old_id: old.children[old_index].clone(),
+        new_id: new.children[new_index].clone(),
+    };
+
+    // Children with a node ID are paired with the one with the same node ID, wherever it is
+    let mut new_by_node_id: HashMap<&str, VecDeque<usize>> = HashMap::new();
+
+    for new_index in runs.iter().flat_map(|(_, new_run)| new_run) {
+        if let Some(node_id) = cache.node_id(&new.children[*new_index]) {
+            new_by_node_id
+                .entry(node_id)
+                .or_default()
+                .push_back(*new_index);
+        }
+    }
+
+    let mut old_paired = vec![false; old.children.len()];
+    let mut new_paired = vec![false; new.children.len()];
+    let mut child_pairs = vec![];
+
+    for old_index in runs.iter().flat_map(|(old_run, _)| old_run) {
+        let new_index = cache
+            .node_id(&old.children[*old_index])
+            .and_then(|node_id| new_by_node_id.get_mut(node_id)?.pop_front());
+
+        if let Some(new_index) = new_index {
+            old_paired[*old_index] = true;
+            new_paired[new_index] = true;
+            child_pairs.push(child_pair(*old_index, new_index));
+        }
+    }
+
+    // Between two anchors, pair the changed children without a node ID of both sides in order.
+    // Whatever is left over was removed or added.
+    let has_node_id = |id: &String| cache.node_id(id).is_some();
+
+    for (old_run, new_run) in runs {
+        let old_positional = old_run
+            .iter()
+            .copied()
+            .filter(|i| !has_node_id(&old.children[*i]));
+        let new_positional = new_run
+            .iter()
+            .copied()
+            .filter(|i| !has_node_id(&new.children[*i]));
+
+        for (old_index, new_index) in old_positional.zip(new_positional) {
+            old_paired[old_index] = true;
+            new_paired[new_index] = true;
+            child_pairs.push(child_pair(old_index, new_index));
+        }
+
+        for old_index in old_run.into_iter().filter(|i| !old_paired[*i]) {
+            changes.push(TreeChange::Removed {
+                path: child_path(&pair.old_path, old_index),
+                id: old.children[old_index].clone(),
+            });
+        }
+
+        for new_index in new_run.into_iter().filter(|i| !new_paired[*i]) {
+            changes.push(TreeChange::Added {
+                path: child_path(&pair.new_path, new_index),
+                id: new.children[new_index].clone(),
+            });
+        }
+    }
+
+    child_pairs
+}
+
+/// Compares the pairs of a level, adding the changes it finds. Returns the pairs of the next
+/// level.
+fn diff_level(
+    backend: &impl SyngBackend,
+    level: &[NodePair],
+    cache: &mut ObjectCache,
+    changes: &mut Vec<TreeChange>,
+) -> Result<Vec<NodePair>, SyngError> {
+    cache.load(backend, level_object_ids(level))?;
+
+    let mut pair_runs = vec![];
+
+    for pair in level {
+        let (old, new) = (cache.get(&pair.old_id), cache.get(&pair.new_id));
+
+        let field_changes = diff_fields(old, new);
+
+        if !field_changes.is_empty() {
+            changes.push(TreeChange::FieldsChanged {
+                old_path: pair.old_path.clone(),
+                new_path: pair.new_path.clone(),
+                changes: field_changes,
+            });
+        }
+
+        pair_runs.push(match_identical_children(pair, old, new, changes));
+    }
+
+    // Read the changed children of the whole level at once to match them up by node ID
+    let mut unmatched = vec![];
+
+    for (pair, runs) in level.iter().zip(&pair_runs) {
+        let (old, new) = (cache.get(&pair.old_id), cache.get(&pair.new_id));
+
+        for (old_run, new_run) in runs {
+            unmatched.extend(old_run.iter().map(|i| old.children[*i].clone()));
+            unmatched.extend(new_run.iter().map(|i| new.children[*i].clone()));
+        }
+    }
+
+    cache.load(backend, unmatched)?;
+
+    Ok(level
+        .iter()
+        .zip(pair_runs)
+        .flat_map(|(pair, runs)| pair_changed_children(pair, runs, cache, changes))
+        .collect())
+}
+
+/// Turns the removed and added nodes with the same node ID into moves, returning the pairs of
+/// moved nodes that were also changed and need to be compared further
+fn match_moves(cache: &ObjectCache, changes: &mut Vec<TreeChange>) -> Vec<NodePair> {
+    let mut added: HashMap<&str, VecDeque<usize>> = HashMap::new();
+
+    for (index, change) in changes.iter().enumerate() {
+        if let TreeChange::Added { id, .. } = change {
+            if let Some(node_id) = cache.node_id(id) {
+                added.entry(node_id).or_default().push_back(index);
+            }
+        }
+    }
+
+    let mut moves = vec![];
+
+    for (index, change) in changes.iter().enumerate() {
+        let TreeChange::Removed { id, .. } = change else {
+            continue;
+        };
+
+        let added_index = cache
+            .node_id(id)
+            .and_then(|node_id| added.get_mut(node_id)?.pop_front());
+
+        if let Some(added_index) = added_index {
+            moves.push((index, added_index));
+        }
+    }
+
+    let mut pairs = vec![];
+    let mut moved_to = vec![];
+
+    for (removed_index, added_index) in moves {
+        let (
+            TreeChange::Removed {
+                path: old_path,
+                id: old_id,
+            },
+            TreeChange::Added {
+                path: new_path,
+                id: new_id,
+            },
+        ) = (&changes[removed_index], &changes[added_index])
+        else {
+            unreachable!("moves pair a removed node with an added one");
+        };
+
+        if old_id != new_id {
+            pairs.push(NodePair {
+                old_path: old_path.clone(),
+                new_path: new_path.clone(),
+                old_id: old_id.clone(),
+                new_id: new_id.clone(),
+            });
+        }
+
+        changes[removed_index] = TreeChange::Moved {
+            node_id: cache.node_id(new_id).unwrap_or_default().to_owned(),
+            old_path: old_path.clone(),
+            new_path: new_path.clone(),
+            id: new_id.clone(),
+        };
+        moved_to.push(added_index);
+    }
+
+    // The moves replaced the removals in place, the additions they came from are dropped
+    moved_to.sort_unstable();
+
+    for index in moved_to.into_iter().rev() {
+        changes.remove(index);
+    }
+
+    pairs
+}
+
+/// The IDs to read for a level, the old and new object of each pair one after the other
+fn level_object_ids(level: &[NodePair]) -> Vec<String> {
+    level
+        .iter()
+        .flat_map(|pair| [pair.old_id.clone(), pair.new_id.clone()])
+        .collect()
+}
+
+/// Lists the changes that turn the tree at `old_root` into the tree at `new_root`. Both trees have
+/// to be readable from the backend.
+///
+/// The changes are ordered from the root down, a level of the trees at a time. Changes inside the
+/// nodes that moved come after the others.
+pub fn diff(
+    backend: &impl SyngBackend,
+    old_root: &str,
+    new_root: &str,
+) -> Result<Vec<TreeChange>, SyngError> {
+    let mut changes = vec![];
+    let mut cache = ObjectCache::default();
+    let mut level = NodePair::roots(old_root, new_root)
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    while !level.is_empty() {
+        while !level.is_empty() {
+            level = diff_level(backend, &level, &mut cache, &mut changes)?;
+        }
+
+        level = match_moves(&cache, &mut changes);
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        objects::NODE_ID_FIELD,
+        testing::{named, MemoryBackend},
+    };
+
+    /// Writes an object named `name` with the node ID `node_id`, returning its ID
+    fn write_node(
+        backend: &mut MemoryBackend,
+        name: &str,
+        node_id: &str,
+        children: &[&str],
+    ) -> String {
+        let mut obj = named(name);
+        obj.fields.insert(NODE_ID_FIELD.to_owned(), node_id.into());
+        obj.children = children.iter().map(|id| id.to_string()).collect();
+
+        backend.write_object(&obj).unwrap()
+    }
+
+    fn name_change(old: &str, new: &str) -> Vec<FieldChange> {
+        vec![FieldChange {
+            key: "name".to_owned(),
+            old: Some(old.into()),
+            new: Some(new.into()),
+        }]
+    }
+
+    #[test]
+    fn identical_trees_have_no_changes() {
+        let mut backend = MemoryBackend::default();
+        let root = backend.write_tree("root(a, b(c))");
+
+        assert_eq!(diff(&backend, &root, &root).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn skips_identical_subtrees_and_reports_field_edits() {
+        let mut backend = MemoryBackend::default();
+
+        let old_root = backend.write_tree("root(a(x, y), b)");
+        let new_root = backend.write_tree("root(a(x, y), c)");
+
+        // Reading anything in the unchanged subtree would fail
+        let unchanged = backend.write_tree("a(x, y)");
+        for id in [backend.write_tree("x"), backend.write_tree("y"), unchanged] {
+            backend.objects.remove(&id);
+        }
+
+        assert_eq!(
+            diff(&backend, &old_root, &new_root).unwrap(),
+            vec![TreeChange::FieldsChanged {
+                old_path: vec![1],
+                new_path: vec![1],
+                changes: name_change("b", "c"),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_reordered_children() {
+        let mut backend = MemoryBackend::default();
+
+        let old_root = backend.write_tree("root(a, b, c)");
+        let new_root = backend.write_tree("root(c, a, b)");
+
+        assert_eq!(
+            diff(&backend, &old_root, &new_root).unwrap(),
+            vec![TreeChange::Reordered {
+                path: vec![],
+                moves: vec![ChildMove {
+                    id: backend.write_tree("c"),
+                    from: 2,
+                    to: 0,
+                }],
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_added_and_removed_children() {
+        let mut backend = MemoryBackend::default();
+
+        let old_root = backend.write_tree("root(a, b)");
+        let new_root = backend.write_tree("root(a, b, c)");
+
+        assert_eq!(
+            diff(&backend, &old_root, &new_root).unwrap(),
+            vec![TreeChange::Added {
+                path: vec![2],
+                id: backend.write_tree("c"),
+            }]
+        );
+
+        assert_eq!(
+            diff(&backend, &new_root, &old_root).unwrap(),
+            vec![TreeChange::Removed {
+                path: vec![2],
+                id: backend.write_tree("c"),
+            }]
+        );
+    }
+
+    #[test]
+    fn matches_edited_children_by_node_id() {
+        let mut backend = MemoryBackend::default();
+
+        let a = write_node(&mut backend, "a", "node-a", &[]);
+        let b = write_node(&mut backend, "b", "node-b", &[]);
+        let edited_b = write_node(&mut backend, "edited b", "node-b", &[]);
+
+        // By position the old `b` would be removed and the edited one added
+        let old_root = backend.write_named("root", &[&a, &b]);
+        let new_root = backend.write_named("root", &[&edited_b, &a]);
+
+        assert_eq!(
+            diff(&backend, &old_root, &new_root).unwrap(),
+            vec![TreeChange::FieldsChanged {
+                old_path: vec![1],
+                new_path: vec![0],
+                changes: name_change("b", "edited b"),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_nodes_moved_to_another_parent() {
+        let mut backend = MemoryBackend::default();
+
+        let node = write_node(&mut backend, "node", "moved", &[]);
+        let edited_node = write_node(&mut backend, "edited node", "moved", &[]);
+
+        let old_p = backend.write_named("p", &[&node]);
+        let old_q = backend.write_named("q", &[]);
+        let old_root = backend.write_named("root", &[&old_p, &old_q]);
+
+        let new_p = backend.write_named("p", &[]);
+        let new_q = backend.write_named("q", &[&edited_node]);
+        let new_root = backend.write_named("root", &[&new_p, &new_q]);
+
+        assert_eq!(
+            diff(&backend, &old_root, &new_root).unwrap(),
+            vec![
+                TreeChange::Moved {
+                    node_id: "moved".to_owned(),
+                    old_path: vec![0, 0],
+                    new_path: vec![1, 0],
+                    id: edited_node,
+                },
+                TreeChange::FieldsChanged {
+                    old_path: vec![0, 0],
+                    new_path: vec![1, 0],
+                    changes: name_change("node", "edited node"),
+                },
+            ]
+        );
+    }
+}
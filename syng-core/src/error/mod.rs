@@ -0,0 +1,52 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+/// Errors returned by backends and the functions working on them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SyngError {
+    /// An object that was referred to (by a tree or the root) is not in the backend
+    ObjectNotFound(String),
+
+    /// The backend has no root object set
+    NoRootObject,
+
+    /// An object could not be encoded
+    EncodeFailed(String),
+
+    /// Stored data could not be decoded into an object
+    DecodeFailed(String),
+
+    /// The underlying storage (disk, database, network) failed
+    StorageFailed(String),
+
+    /// A transaction was used incorrectly or could not be started, committed or rolled back
+    TransactionFailed(String),
+
+    /// The name is not a valid ref name, see [`crate::backend::is_valid_ref_name`]
+    InvalidRefName(String),
+
+    /// The string is not a valid object ID, see [`crate::objects::ObjectId`]
+    InvalidObjectId(String),
+
+    /// The backend doesn't support the operation
+    Unsupported(String),
+}
+
+impl Display for SyngError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyngError::ObjectNotFound(id) => write!(f, "object not found: {}", id),
+            SyngError::NoRootObject => write!(f, "no root object set"),
+            SyngError::EncodeFailed(e) => write!(f, "encoding object failed: {}", e),
+            SyngError::DecodeFailed(e) => write!(f, "decoding object failed: {}", e),
+            SyngError::StorageFailed(e) => write!(f, "storage failed: {}", e),
+            SyngError::TransactionFailed(e) => write!(f, "transaction failed: {}", e),
+            SyngError::InvalidRefName(name) => write!(f, "invalid ref name: {}", name),
+            SyngError::InvalidObjectId(id) => write!(f, "invalid object ID: {}", id),
+            SyngError::Unsupported(op) => write!(f, "not supported by the backend: {}", op),
+        }
+    }
+}
+
+impl std::error::Error for SyngError {}
@@ -0,0 +1,539 @@
+//! Integrity checking of a backend.
+//!
+//! [`fsck`] walks every object reachable from the refs of the backend and reports what's wrong
+//! with the store instead of failing on the first problem: refs and children pointing to objects
+//! the backend doesn't have, objects that don't decode or don't hash to their ID, cycles, and
+//! objects nothing refers to. Unreachable objects are only found on backends that support
+//! [`SyngBackend::list_objects_page`], which are gone through a page at a time.
+//!
+//! Reachability is the same as for [`crate::gc`]: the trees of the commits refs point to and of
+//! their merge bases are reachable, the trees of older commits are not.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    commit::{merge_base_trees, SyngCommit},
+    error::SyngError,
+    objects::SyngObjectDef,
+};
+
+/// Number of object IDs listed at once while looking for unreachable objects
+const LIST_PAGE_SIZE: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FsckIssue {
+    /// A ref points to an object the backend doesn't have
+    MissingRefTarget { ref_name: String, id: String },
+
+    /// An object has a child the backend doesn't have
+    MissingChild { parent: String, child: String },
+
+    /// A commit a ref points to (or their merge base) has a tree the backend doesn't have
+    MissingTree { commit: String, tree: String },
+
+    /// The data stored for the object can't be decoded
+    CorruptObject { id: String, error: String },
+
+    /// The object stored under `id` hashes to something else
+    HashMismatch { id: String, actual_hash: String },
+
+    /// Following the children of the object leads back to it. Content addressing makes this
+    /// impossible for objects that hash to their ID, so it only happens in corrupted stores.
+    Cycle { id: String },
+
+    /// Nothing reachable from a ref refers to the object, see [`crate::gc`]
+    Unreachable { id: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FsckReport {
+    /// Objects that were read, reachable or not
+    pub checked_objects: usize,
+
+    /// Sorted by kind and then by ID
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// A read of one object where decoding errors are kept per object
+type CheckedRead = Result<Option<SyngObjectDef>, String>;
+
+/// State of the walk over the reachable objects
+#[derive(Default)]
+struct FsckWalk {
+    seen: HashSet<String>,
+    missing: HashSet<String>,
+
+    /// Children of every reachable object that could be read
+    graph: HashMap<String, Vec<String>>,
+
+    /// Commits the refs point to and their merge bases, with their tree
+    commit_trees: Vec<(String, String)>,
+
+    checked_objects: usize,
+    issues: Vec<FsckIssue>,
+}
+
+impl FsckWalk {
+    /// Drops the objects that were already visited (or appear twice) from the level
+    fn take_unseen(&mut self, mut level: Vec<String>) -> Vec<String> {
+        level.sort();
+        level.dedup();
+        level.retain(|id| self.seen.insert(id.clone()));
+
+        level
+    }
+
+    fn check_object(
+        &mut self,
+        id: &str,
+        read: CheckedRead,
+    ) -> Result<Option<SyngObjectDef>, SyngError> {
+        let obj = match read {
+            Ok(obj) => obj,
+            Err(error) => {
+                self.checked_objects += 1;
+                self.issues.push(FsckIssue::CorruptObject {
+                    id: id.to_owned(),
+                    error,
+                });
+
+                return Ok(None);
+            }
+        };
+
+        let Some(obj) = obj else {
+            return Ok(None);
+        };
+
+        self.checked_objects += 1;
+
+        let actual_hash = obj.get_hash_like(id)?;
+
+        if actual_hash != id {
+            self.issues.push(FsckIssue::HashMismatch {
+                id: id.to_owned(),
+                actual_hash,
+            });
+        }
+
+        Ok(Some(obj))
+    }
+
+    /// Checks the objects of a level of the walk, returning the children to visit next
+    fn visit_level(
+        &mut self,
+        level: Vec<String>,
+        reads: Vec<CheckedRead>,
+    ) -> Result<Vec<String>, SyngError> {
+        let mut next_level = vec![];
+
+        for (id, read) in level.into_iter().zip(reads) {
+            let is_missing = matches!(read, Ok(None));
+
+            match self.check_object(&id, read)? {
+                Some(obj) => {
+                    next_level.extend(
+                        obj.children
+                            .iter()
+                            .filter(|child| !self.seen.contains(*child))
+                            .cloned(),
+                    );
+
+                    self.graph.insert(id, obj.children);
+                }
+                None if is_missing => {
+                    self.missing.insert(id);
+                }
+                None => {}
+            }
+        }
+
+        Ok(next_level)
+    }
+
+    /// Checks the objects the refs point to, returning the children and the trees of the commits
+    /// among them to visit next
+    fn visit_refs(
+        &mut self,
+        level: Vec<String>,
+        reads: Vec<CheckedRead>,
+    ) -> Result<Vec<String>, SyngError> {
+        for (id, read) in level.iter().zip(&reads) {
+            if let Ok(Some(obj)) = read {
+                if let Some(commit) = SyngCommit::from_object(obj) {
+                    self.commit_trees.push((id.clone(), commit.tree));
+                }
+            }
+        }
+
+        let mut next_level = self.visit_level(level, reads)?;
+
+        next_level.extend(self.commit_trees.iter().map(|(_, tree)| tree.clone()));
+
+        Ok(next_level)
+    }
+
+    /// Of the objects the backend lists, the ones the walk didn't reach
+    fn unreachable(&self, listed: Vec<String>) -> Vec<String> {
+        listed
+            .into_iter()
+            .filter(|id| !self.seen.contains(id))
+            .collect()
+    }
+
+    fn visit_unreachable(
+        &mut self,
+        ids: Vec<String>,
+        reads: Vec<CheckedRead>,
+    ) -> Result<(), SyngError> {
+        for (id, read) in ids.into_iter().zip(reads) {
+            // Deleted since it was listed
+            if matches!(read, Ok(None)) {
+                continue;
+            }
+
+            self.check_object(&id, read)?;
+            self.issues.push(FsckIssue::Unreachable { id });
+        }
+
+        Ok(())
+    }
+
+    fn into_report(mut self, refs: &BTreeMap<String, String>) -> FsckReport {
+        for (ref_name, id) in refs {
+            if self.missing.contains(id) {
+                self.issues.push(FsckIssue::MissingRefTarget {
+                    ref_name: ref_name.clone(),
+                    id: id.clone(),
+                });
+            }
+        }
+
+        for (parent, children) in &self.graph {
+            for child in children {
+                if self.missing.contains(child) {
+                    self.issues.push(FsckIssue::MissingChild {
+                        parent: parent.clone(),
+                        child: child.clone(),
+                    });
+                }
+            }
+        }
+
+        for (commit, tree) in &self.commit_trees {
+            if self.missing.contains(tree) {
+                self.issues.push(FsckIssue::MissingTree {
+                    commit: commit.clone(),
+                    tree: tree.clone(),
+                });
+            }
+        }
+
+        for id in find_cycles(&self.graph) {
+            self.issues.push(FsckIssue::Cycle { id });
+        }
+
+        self.issues.sort();
+        self.issues.dedup();
+
+        FsckReport {
+            checked_objects: self.checked_objects,
+            issues: self.issues,
+        }
+    }
+}
+
+/// Returns the objects a depth-first walk of `graph` finds itself back at, one per cycle it runs
+/// into
+fn find_cycles(graph: &HashMap<String, Vec<String>>) -> BTreeSet<String> {
+    enum State {
+        InProgress,
+        Done,
+    }
+
+    let mut states: HashMap<&str, State> = HashMap::new();
+    let mut cycles = BTreeSet::new();
+
+    // Start from the objects in order, so the same store always reports the same objects
+    let mut starts = graph.keys().collect::<Vec<_>>();
+    starts.sort();
+
+    for start in starts {
+        if states.contains_key(start.as_str()) {
+            continue;
+        }
+
+        // Each entry is an object being visited and the index of its next child to visit. The
+        // stack is explicit since a corrupted store can make the path arbitrarily long.
+        let mut stack = vec![(start.as_str(), 0)];
+        states.insert(start, State::InProgress);
+
+        while let Some(&(id, next_child)) = stack.last() {
+            let children = graph.get(id).map(Vec::as_slice).unwrap_or_default();
+
+            let Some(child) = children.get(next_child) else {
+                states.insert(id, State::Done);
+                stack.pop();
+
+                continue;
+            };
+
+            stack.last_mut().unwrap().1 += 1;
+
+            match states.get(child.as_str()) {
+                Some(State::InProgress) => {
+                    cycles.insert(child.clone());
+                }
+                Some(State::Done) => {}
+                None => {
+                    states.insert(child, State::InProgress);
+                    stack.push((child, 0));
+                }
+            }
+        }
+    }
+
+    cycles
+}
+
+/// Reads the objects with one [`SyngBackend::read_objects`] call, falling back to reading them
+/// one by one if an object in the batch can't be decoded, to find out which one it is
+fn read_checked(backend: &impl SyngBackend, ids: &[String]) -> Result<Vec<CheckedRead>, SyngError> {
+    let id_refs = ids.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match backend.read_objects(&id_refs) {
+        Ok(objects) => Ok(objects.into_iter().map(Ok).collect()),
+        Err(SyngError::DecodeFailed(_)) => ids
+            .iter()
+            .map(|id| match backend.read_object(id) {
+                Ok(obj) => Ok(Ok(obj)),
+                Err(SyngError::DecodeFailed(e)) => Ok(Err(e)),
+                Err(e) => Err(e),
+            })
+            .collect(),
+        Err(e) => Err(e),
+    }
+}
+
+/// Lists a page of the objects of the backend, `None` if it doesn't support listing
+fn listed_objects(
+    backend: &impl SyngBackend,
+    after: Option<&str>,
+) -> Result<Option<Vec<String>>, SyngError> {
+    match backend.list_objects_page(after, LIST_PAGE_SIZE) {
+        Ok(ids) => Ok(Some(ids)),
+        Err(SyngError::Unsupported(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Checks every object in the backend. Only fails if the backend can't be read at all, problems
+/// with the stored data end up in the report.
+pub fn fsck(backend: &impl SyngBackend) -> Result<FsckReport, SyngError> {
+    let refs = backend.list_refs()?;
+
+    let mut walk = FsckWalk::default();
+
+    let ref_targets = walk.take_unseen(refs.values().cloned().collect());
+    let reads = read_checked(backend, &ref_targets)?;
+    let mut next_level = walk.visit_refs(ref_targets, reads)?;
+
+    // A broken history is reported by the walk, it only means the merge bases can't be found
+    let commits = walk
+        .commit_trees
+        .iter()
+        .map(|(commit, _)| commit.clone())
+        .collect::<Vec<_>>();
+
+    match merge_base_trees(backend, &commits) {
+        Ok(bases) => {
+            next_level.extend(bases.iter().map(|(_, tree)| tree.clone()));
+            walk.commit_trees.extend(bases);
+        }
+        Err(SyngError::ObjectNotFound(_) | SyngError::DecodeFailed(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut level = walk.take_unseen(next_level);
+
+    while !level.is_empty() {
+        let reads = read_checked(backend, &level)?;
+
+        let next_level = walk.visit_level(level, reads)?;
+
+        level = walk.take_unseen(next_level);
+    }
+
+    let mut after = None;
+
+    while let Some(page) = listed_objects(backend, after.as_deref())? {
+        let Some(last) = page.last().cloned() else {
+            break;
+        };
+
+        let unreachable = walk.unreachable(page);
+        let reads = read_checked(backend, &unreachable)?;
+
+        walk.visit_unreachable(unreachable, reads)?;
+
+        after = Some(last);
+    }
+
+    Ok(walk.into_report(&refs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::DEFAULT_REF,
+        commit::{commit_root, CommitInfo},
+        testing::{named, MemoryBackend},
+    };
+
+    #[test]
+    fn intact_stores_have_no_issues() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a, b(c))");
+
+        let report = fsck(&backend).unwrap();
+
+        assert!(report.is_ok());
+        assert_eq!(report.checked_objects, 4);
+    }
+
+    #[test]
+    fn reports_missing_ref_targets() {
+        let mut backend = MemoryBackend::default();
+        let root = backend.set_tree("root");
+        backend.objects.remove(&root);
+
+        assert_eq!(
+            fsck(&backend).unwrap().issues,
+            vec![FsckIssue::MissingRefTarget {
+                ref_name: DEFAULT_REF.to_owned(),
+                id: root,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_missing_children() {
+        let mut backend = MemoryBackend::default();
+        let root = backend.set_tree("root(a, b)");
+        let a = backend.write_tree("a");
+        backend.objects.remove(&a);
+
+        assert_eq!(
+            fsck(&backend).unwrap().issues,
+            vec![FsckIssue::MissingChild {
+                parent: root,
+                child: a,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_missing_commit_trees() {
+        let mut backend = MemoryBackend::default();
+        let tree = backend.set_tree("root");
+        let commit = commit_root(&mut backend, &CommitInfo::new("test", "first")).unwrap();
+
+        backend.refs.remove(DEFAULT_REF);
+        backend.objects.remove(&tree);
+
+        assert_eq!(
+            fsck(&backend).unwrap().issues,
+            vec![FsckIssue::MissingTree { commit, tree }]
+        );
+    }
+
+    #[test]
+    fn reports_undecodable_objects() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a, b)");
+        let a = backend.write_tree("a");
+        backend.corrupt_objects.insert(a.clone());
+
+        let report = fsck(&backend).unwrap();
+
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::CorruptObject {
+                id: a.clone(),
+                error: format!("corrupt object {}", a),
+            }]
+        );
+        assert_eq!(report.checked_objects, 3);
+    }
+
+    #[test]
+    fn reports_objects_that_do_not_hash_to_their_id() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a)");
+        let a = backend.write_tree("a");
+
+        let tampered = named("tampered");
+        backend.objects.insert(a.clone(), tampered.clone());
+
+        assert_eq!(
+            fsck(&backend).unwrap().issues,
+            vec![FsckIssue::HashMismatch {
+                id: a,
+                actual_hash: backend.object_id(&tampered).unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_cycles_in_corrupted_stores() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a(b))");
+        let a = backend.write_tree("a(b)");
+
+        // An object can only be its own descendant if it doesn't hash to its ID
+        let mut looping = backend.objects[&a].clone();
+        looping.children.push(a.clone());
+        backend.objects.insert(a.clone(), looping.clone());
+
+        assert_eq!(
+            fsck(&backend).unwrap().issues,
+            vec![
+                FsckIssue::HashMismatch {
+                    id: a.clone(),
+                    actual_hash: backend.object_id(&looping).unwrap(),
+                },
+                FsckIssue::Cycle { id: a },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unreachable_objects() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a)");
+
+        let orphan = backend.write_tree("orphan(x)");
+        let x = backend.write_tree("x");
+
+        let mut expected = vec![
+            FsckIssue::Unreachable { id: orphan },
+            FsckIssue::Unreachable { id: x },
+        ];
+        expected.sort();
+
+        let report = fsck(&backend).unwrap();
+
+        assert_eq!(report.issues, expected);
+        assert_eq!(report.checked_objects, 4);
+    }
+}
@@ -0,0 +1,232 @@
+//! Garbage collection of the objects nothing refers to anymore.
+//!
+//! Objects are never deleted while the tree is rewritten, so every update leaves the replaced
+//! objects behind. [`gc`] marks everything reachable from the refs of the backend and any extra
+//! roots the caller wants to keep, then deletes the rest. The backend has to support
+//! [`SyngBackend::list_objects_page`] and [`SyngBackend::delete_object`]. Only the IDs of the
+//! reachable objects are held in memory, the rest of the store is gone through a page at a time.
+//!
+//! A ref pointing to a commit keeps the commit, its tree and every commit before it, but not the
+//! trees of the earlier commits (see [`crate::commit`]), so the history is kept while the old
+//! versions of the tree are collected. The one exception are the trees of the
+//! [`merge_base`](crate::commit::merge_base)s of the commits refs point to, which are needed to
+//! merge them.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    commit::{merge_base_trees, SyngCommit},
+    error::SyngError,
+    tree_ops::read_referred_objects,
+};
+
+/// Number of object IDs listed at once while sweeping
+const LIST_PAGE_SIZE: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// Objects reachable from the refs or the extra roots, which were kept
+    pub reachable_objects: usize,
+
+    pub deleted_objects: usize,
+}
+
+/// Collects the IDs of every object reachable from `roots`, walking the tree a level at a time
+fn mark(backend: &impl SyngBackend, roots: Vec<String>) -> Result<HashSet<String>, SyngError> {
+    let mut reachable = HashSet::new();
+    let mut level = roots;
+
+    while !level.is_empty() {
+        level.retain(|id| reachable.insert(id.clone()));
+
+        level = read_referred_objects(backend, &level)?
+            .into_iter()
+            .flat_map(|obj| obj.children)
+            .filter(|id| !reachable.contains(id))
+            .collect();
+
+        // The same object can be a child of several objects on a level
+        level.sort();
+        level.dedup();
+    }
+
+    Ok(reachable)
+}
+
+fn mark_and_sweep(backend: &mut impl SyngBackend, roots: &[&str]) -> Result<GcStats, SyngError> {
+    let mut all_roots = backend
+        .list_refs()?
+        .into_values()
+        .chain(roots.iter().map(|id| (*id).to_owned()))
+        .collect::<Vec<_>>();
+
+    all_roots.sort();
+    all_roots.dedup();
+
+    // The tree of a commit isn't one of its children, so it is only kept for the commits the roots
+    // point to directly and for their merge bases
+    let (commits, mut trees): (Vec<_>, Vec<_>) = all_roots
+        .iter()
+        .zip(read_referred_objects(backend, &all_roots)?)
+        .filter_map(|(id, obj)| Some((id.clone(), SyngCommit::from_object(&obj)?.tree)))
+        .unzip();
+
+    trees.extend(
+        merge_base_trees(backend, &commits)?
+            .into_iter()
+            .map(|(_, tree)| tree),
+    );
+
+    all_roots.extend(trees);
+
+    let reachable = mark(backend, all_roots)?;
+
+    let mut deleted_objects = 0;
+    let mut after = None;
+
+    // Deleting the objects of a page doesn't move the ones after it, so the pages can be listed
+    // while sweeping
+    loop {
+        let page = backend.list_objects_page(after.as_deref(), LIST_PAGE_SIZE)?;
+
+        let Some(last) = page.last().cloned() else {
+            break;
+        };
+
+        for id in page {
+            if !reachable.contains(&id) && backend.delete_object(&id)? {
+                deleted_objects += 1;
+            }
+        }
+
+        after = Some(last);
+    }
+
+    Ok(GcStats {
+        reachable_objects: reachable.len(),
+        deleted_objects,
+    })
+}
+
+/// Deletes every object that isn't reachable from a ref of the backend or from one of `roots`, or
+/// the tree of a commit they point to.
+/// Runs in a transaction on backends that support them, so that objects written by a concurrent
+/// writer before it moves a ref can't be collected from under it. Fails without deleting anything
+/// if a reachable object is missing.
+pub fn gc(backend: &mut impl SyngBackend, roots: &[&str]) -> Result<GcStats, SyngError> {
+    backend.begin_transaction()?;
+
+    match mark_and_sweep(backend, roots) {
+        Ok(stats) => {
+            backend.commit_transaction()?;
+
+            Ok(stats)
+        }
+        Err(e) => {
+            // The collection error is more useful to the caller than a failed rollback
+            let _ = backend.rollback_transaction();
+
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::DEFAULT_REF,
+        commit::{build_commit, commit_root, history, merge_base, read_commit, CommitInfo},
+        delta::{apply_delta, merge},
+        fsck::{fsck, FsckIssue},
+        testing::MemoryBackend,
+    };
+
+    #[test]
+    fn keeps_the_history_but_only_the_tree_of_the_commit_a_ref_points_to() {
+        let mut backend = MemoryBackend::default();
+
+        let old_tree = backend.set_tree("root(old)");
+        commit_root(&mut backend, &CommitInfo::new("test", "first")).unwrap();
+
+        let new_tree = backend.set_tree("root(new)");
+        commit_root(&mut backend, &CommitInfo::new("test", "second")).unwrap();
+
+        // Only the history ref is left to keep the current tree
+        backend.refs.remove(DEFAULT_REF);
+
+        let issues = fsck(&backend).unwrap().issues;
+        assert!(issues.contains(&FsckIssue::Unreachable {
+            id: old_tree.clone()
+        }));
+
+        let stats = gc(&mut backend, &[]).unwrap();
+
+        assert_eq!(stats.deleted_objects, 2);
+        assert!(!backend.objects.contains_key(&old_tree));
+        assert_eq!(backend.describe(&new_tree), "root(new)");
+        assert_eq!(history(&backend).unwrap().len(), 2);
+        assert!(fsck(&backend).unwrap().is_ok());
+    }
+
+    #[test]
+    fn sweeps_every_page() {
+        let mut backend = MemoryBackend::default();
+        let root = backend.set_tree("root(a, b)");
+
+        for i in 0..LIST_PAGE_SIZE * 2 + 1 {
+            backend.write_named(&i.to_string(), &[]);
+        }
+
+        let stats = gc(&mut backend, &[]).unwrap();
+
+        assert_eq!(
+            stats,
+            GcStats {
+                reachable_objects: 3,
+                deleted_objects: LIST_PAGE_SIZE * 2 + 1,
+            }
+        );
+        assert_eq!(backend.describe(&root), "root(a, b)");
+        assert_eq!(backend.objects.len(), 3);
+    }
+
+    #[test]
+    fn keeps_the_tree_of_the_merge_base_of_the_refs() {
+        let mut backend = MemoryBackend::default();
+        let info = CommitInfo::new("test", "message");
+
+        let base_tree = backend.set_tree("root(a, b)");
+        let fork = commit_root(&mut backend, &info).unwrap();
+
+        // The remote moved on from the fork on its own
+        let theirs_tree = backend.write_tree("root(a, b2)");
+        let theirs = backend
+            .write_object(&build_commit(&theirs_tree, Some(fork.clone()), &info).to_object())
+            .unwrap();
+        backend
+            .refs
+            .insert("remote/main".to_owned(), theirs.clone());
+
+        backend.set_tree("root(a2, b)");
+        let ours = commit_root(&mut backend, &info).unwrap();
+
+        gc(&mut backend, &[]).unwrap();
+        assert!(fsck(&backend).unwrap().is_ok());
+
+        let base = merge_base(&backend, &ours, &theirs).unwrap().unwrap();
+        assert_eq!(base, fork);
+
+        let base_tree_after_gc = read_commit(&backend, &base).unwrap().tree;
+        assert_eq!(base_tree_after_gc, base_tree);
+
+        let ours_tree = backend.get_root_object_id().unwrap().unwrap();
+        let merge = merge(&backend, &base_tree_after_gc, &ours_tree, &theirs_tree).unwrap();
+        apply_delta(&mut backend, &merge.delta).unwrap();
+
+        assert_eq!(backend.describe_root(), "root(a2, b2)");
+    }
+}
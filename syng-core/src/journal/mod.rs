@@ -0,0 +1,426 @@
+//! Journal of the changes made to the root, for undo and redo.
+//!
+//! Every entry records the root before and after an operation. Since objects are never changed in
+//! place, undoing an operation is only a matter of moving the root back to the recorded root (and
+//! redoing it moving it forward again), as long as the objects weren't garbage collected.
+//!
+//! The journal is kept by the caller (it can be serialized to keep it around) rather than in the
+//! backend, so every client has its own undo stack.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    error::SyngError,
+    objects::SyngObjectDef,
+    tree_ops::{self, ChildAdditionPosition, TreeOpError},
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TreeOperation {
+    UpdateObject {
+        path: Vec<usize>,
+    },
+
+    /// `index` is `None` when the child was added to the end
+    AddChildObject {
+        path: Vec<usize>,
+        index: Option<usize>,
+    },
+
+    RemoveChildObject {
+        path: Vec<usize>,
+    },
+
+    /// Paths and `index` are the ones the move was given, in the tree before it
+    MoveChildObject {
+        from_path: Vec<usize>,
+        to_parent_path: Vec<usize>,
+        index: Option<usize>,
+    },
+
+    /// The root was moved straight to a saved root
+    RevertTo {
+        root: String,
+    },
+
+    /// A change made of several operations (or outside of [`tree_ops`]), recorded with
+    /// [`OpJournal::record`]
+    Named(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JournalEntry {
+    pub operation: TreeOperation,
+
+    /// Root before the operation
+    pub before: String,
+
+    /// Root after the operation
+    pub after: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum JournalError {
+    /// The root was moved by something other than the journal, so the entry to undo or redo
+    /// doesn't start from the current root anymore
+    RootMoved {
+        expected: String,
+        actual: Option<String>,
+    },
+
+    Backend(SyngError),
+}
+
+impl From<SyngError> for JournalError {
+    fn from(value: SyngError) -> Self {
+        JournalError::Backend(value)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OpJournal {
+    entries: Vec<JournalEntry>,
+
+    /// Number of entries that are applied, the ones after it were undone and can be redone
+    applied: usize,
+}
+
+/// Moves the root from `from` to `to`, failing if it isn't at `from`
+fn move_root(backend: &mut impl SyngBackend, from: &str, to: &str) -> Result<(), JournalError> {
+    if !backend.compare_and_set_root(Some(from), to)? {
+        return Err(JournalError::RootMoved {
+            expected: from.to_owned(),
+            actual: backend.get_root_object_id()?,
+        });
+    }
+
+    Ok(())
+}
+
+fn position_index(position: &ChildAdditionPosition) -> Option<usize> {
+    match position {
+        ChildAdditionPosition::AddToEnd => None,
+        ChildAdditionPosition::AddAt(index) => Some(*index),
+    }
+}
+
+fn current_root(backend: &impl SyngBackend) -> Result<String, SyngError> {
+    backend.get_root_object_id()?.ok_or(SyngError::NoRootObject)
+}
+
+impl OpJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every recorded entry, including the undone ones
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// The entry [`OpJournal::undo`] would undo
+    pub fn next_undo(&self) -> Option<&JournalEntry> {
+        self.applied
+            .checked_sub(1)
+            .map(|index| &self.entries[index])
+    }
+
+    /// The entry [`OpJournal::redo`] would redo
+    pub fn next_redo(&self) -> Option<&JournalEntry> {
+        self.entries.get(self.applied)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.next_undo().is_some()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.next_redo().is_some()
+    }
+
+    /// Records an operation that moved the root from `before` to `after`. The undone entries are
+    /// dropped, like in any editor. Nothing is recorded if the root didn't change.
+    pub fn record(&mut self, operation: TreeOperation, before: &str, after: &str) {
+        if before == after {
+            return;
+        }
+
+        self.entries.truncate(self.applied);
+        self.entries.push(JournalEntry {
+            operation,
+            before: before.to_owned(),
+            after: after.to_owned(),
+        });
+        self.applied = self.entries.len();
+    }
+
+    /// Marks the last applied entry as undone
+    fn step_back(&mut self) {
+        self.applied -= 1;
+    }
+
+    /// Marks the first undone entry as applied again
+    fn step_forward(&mut self) {
+        self.applied += 1;
+    }
+
+    /// Records the operation of a tree op wrapper. Ops that didn't do anything leave the root
+    /// where it was, so they aren't recorded.
+    fn record_roots(
+        &mut self,
+        operation: TreeOperation,
+        before: Option<String>,
+        after: Option<String>,
+    ) {
+        if let (Some(before), Some(after)) = (before, after) {
+            self.record(operation, &before, &after);
+        }
+    }
+
+    /// [`tree_ops::update_object`], recorded in the journal
+    pub fn update_object(
+        &mut self,
+        backend: &mut impl SyngBackend,
+        obj_path: &[usize],
+        new_def: &SyngObjectDef,
+    ) -> Result<(String, SyngObjectDef), TreeOpError> {
+        let before = backend.get_root_object_id()?;
+        let result = tree_ops::update_object(backend, obj_path, new_def)?;
+
+        let operation = TreeOperation::UpdateObject {
+            path: obj_path.to_vec(),
+        };
+
+        self.record_roots(operation, before, backend.get_root_object_id()?);
+
+        Ok(result)
+    }
+
+    /// [`tree_ops::add_child_object`], recorded in the journal
+    pub fn add_child_object(
+        &mut self,
+        backend: &mut impl SyngBackend,
+        parent_path: &[usize],
+        child_def: &SyngObjectDef,
+        position: ChildAdditionPosition,
+    ) -> Result<(String, SyngObjectDef), TreeOpError> {
+        let operation = TreeOperation::AddChildObject {
+            path: parent_path.to_vec(),
+            index: position_index(&position),
+        };
+
+        let before = backend.get_root_object_id()?;
+        let result = tree_ops::add_child_object(backend, parent_path, child_def, position)?;
+
+        self.record_roots(operation, before, backend.get_root_object_id()?);
+
+        Ok(result)
+    }
+
+    /// [`tree_ops::remove_child_object`], recorded in the journal
+    pub fn remove_child_object(
+        &mut self,
+        backend: &mut impl SyngBackend,
+        obj_path: &[usize],
+    ) -> Result<(), TreeOpError> {
+        let before = backend.get_root_object_id()?;
+        tree_ops::remove_child_object(backend, obj_path)?;
+
+        let operation = TreeOperation::RemoveChildObject {
+            path: obj_path.to_vec(),
+        };
+
+        self.record_roots(operation, before, backend.get_root_object_id()?);
+
+        Ok(())
+    }
+
+    /// [`tree_ops::move_child_object`], recorded in the journal
+    pub fn move_child_object(
+        &mut self,
+        backend: &mut impl SyngBackend,
+        from_path: &[usize],
+        to_parent_path: &[usize],
+        position: ChildAdditionPosition,
+    ) -> Result<Vec<usize>, TreeOpError> {
+        let operation = TreeOperation::MoveChildObject {
+            from_path: from_path.to_vec(),
+            to_parent_path: to_parent_path.to_vec(),
+            index: position_index(&position),
+        };
+
+        let before = backend.get_root_object_id()?;
+        let result = tree_ops::move_child_object(backend, from_path, to_parent_path, position)?;
+
+        self.record_roots(operation, before, backend.get_root_object_id()?);
+
+        Ok(result)
+    }
+
+    /// Moves the root back to where it was before the last applied entry. Returns the undone
+    /// entry, or `None` if there is nothing to undo.
+    pub fn undo(
+        &mut self,
+        backend: &mut impl SyngBackend,
+    ) -> Result<Option<JournalEntry>, JournalError> {
+        let Some(entry) = self.next_undo().cloned() else {
+            return Ok(None);
+        };
+
+        move_root(backend, &entry.after, &entry.before)?;
+        self.step_back();
+
+        Ok(Some(entry))
+    }
+
+    /// Moves the root forward to where it was after the last undone entry. Returns the redone
+    /// entry, or `None` if there is nothing to redo.
+    pub fn redo(
+        &mut self,
+        backend: &mut impl SyngBackend,
+    ) -> Result<Option<JournalEntry>, JournalError> {
+        let Some(entry) = self.next_redo().cloned() else {
+            return Ok(None);
+        };
+
+        move_root(backend, &entry.before, &entry.after)?;
+        self.step_forward();
+
+        Ok(Some(entry))
+    }
+
+    /// Moves the root straight to `root` (which has to be in the backend), recording it as an
+    /// entry that can be undone like any other
+    pub fn revert_to(
+        &mut self,
+        backend: &mut impl SyngBackend,
+        root: &str,
+    ) -> Result<(), JournalError> {
+        let before = current_root(backend)?;
+
+        move_root(backend, &before, root)?;
+
+        self.record(
+            TreeOperation::RevertTo {
+                root: root.to_owned(),
+            },
+            &before,
+            root,
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{named, MemoryBackend};
+
+    fn add_child(journal: &mut OpJournal, backend: &mut MemoryBackend, name: &str) {
+        journal
+            .add_child_object(backend, &[], &named(name), ChildAdditionPosition::AddToEnd)
+            .unwrap();
+    }
+
+    #[test]
+    fn undo_and_redo_move_the_root() {
+        let mut backend = MemoryBackend::default();
+        let mut journal = OpJournal::new();
+
+        backend.set_tree("root(a)");
+        add_child(&mut journal, &mut backend, "b");
+        assert_eq!(backend.describe_root(), "root(a, b)");
+
+        let undone = journal.undo(&mut backend).unwrap().unwrap();
+        assert_eq!(
+            undone.operation,
+            TreeOperation::AddChildObject {
+                path: vec![],
+                index: None
+            }
+        );
+        assert_eq!(backend.describe_root(), "root(a)");
+        assert!(!journal.can_undo());
+
+        assert_eq!(journal.redo(&mut backend).unwrap(), Some(undone));
+        assert_eq!(backend.describe_root(), "root(a, b)");
+        assert!(!journal.can_redo());
+    }
+
+    #[test]
+    fn recording_drops_the_undone_entries() {
+        let mut backend = MemoryBackend::default();
+        let mut journal = OpJournal::new();
+
+        backend.set_tree("root");
+        add_child(&mut journal, &mut backend, "a");
+        add_child(&mut journal, &mut backend, "b");
+
+        journal.undo(&mut backend).unwrap();
+        assert!(journal.can_redo());
+
+        add_child(&mut journal, &mut backend, "c");
+
+        assert!(!journal.can_redo());
+        assert_eq!(journal.redo(&mut backend).unwrap(), None);
+        assert_eq!(journal.entries().len(), 2);
+        assert_eq!(backend.describe_root(), "root(a, c)");
+
+        journal.undo(&mut backend).unwrap();
+        assert_eq!(backend.describe_root(), "root(a)");
+    }
+
+    #[test]
+    fn undo_fails_when_the_root_moved_since_the_entry() {
+        let mut backend = MemoryBackend::default();
+        let mut journal = OpJournal::new();
+
+        backend.set_tree("root");
+        add_child(&mut journal, &mut backend, "a");
+
+        // Someone else moves the root behind the journal's back
+        let other = backend.set_tree("other");
+
+        assert_eq!(
+            journal.undo(&mut backend),
+            Err(JournalError::RootMoved {
+                expected: journal.next_undo().unwrap().after.clone(),
+                actual: Some(other),
+            })
+        );
+        assert_eq!(backend.describe_root(), "other");
+        assert!(journal.can_undo());
+        assert!(!journal.can_redo());
+    }
+
+    #[test]
+    fn reverts_can_be_undone() {
+        let mut backend = MemoryBackend::default();
+        let mut journal = OpJournal::new();
+
+        let start = backend.set_tree("root");
+        add_child(&mut journal, &mut backend, "a");
+        add_child(&mut journal, &mut backend, "b");
+
+        journal.revert_to(&mut backend, &start).unwrap();
+        assert_eq!(backend.describe_root(), "root");
+        assert_eq!(
+            journal.next_undo().unwrap().operation,
+            TreeOperation::RevertTo {
+                root: start.clone()
+            }
+        );
+
+        journal.undo(&mut backend).unwrap();
+        assert_eq!(backend.describe_root(), "root(a, b)");
+
+        journal.undo(&mut backend).unwrap();
+        assert_eq!(backend.describe_root(), "root(a)");
+
+        journal.redo(&mut backend).unwrap();
+        journal.redo(&mut backend).unwrap();
+        assert_eq!(backend.get_root_object_id().unwrap(), Some(start));
+    }
+}
@@ -1,4 +1,16 @@
+pub mod async_backend;
 pub mod backend;
+pub mod blob;
+pub mod commit;
+pub mod conflict;
 pub mod delta;
+pub mod diff;
+pub mod error;
+pub mod fsck;
+pub mod gc;
+pub mod journal;
 pub mod objects;
 pub mod tree_ops;
+
+#[cfg(test)]
+mod testing;
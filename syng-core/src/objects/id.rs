@@ -0,0 +1,295 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::error::SyngError;
+
+/// Hash function object IDs are computed with
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum HashAlgorithm {
+    /// What every object was hashed with before IDs carried their algorithm
+    #[default]
+    Sha256,
+
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// The multihash code of the algorithm
+    pub fn code(self) -> u64 {
+        match self {
+            HashAlgorithm::Sha256 => 0x12,
+            HashAlgorithm::Blake3 => 0x1e,
+        }
+    }
+
+    pub fn from_code(code: u64) -> Option<Self> {
+        match code {
+            0x12 => Some(HashAlgorithm::Sha256),
+            0x1e => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Length of the digests in bytes
+    pub fn digest_len(self) -> usize {
+        32
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            HashAlgorithm::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+        }
+    }
+}
+
+/// ID of an object, the digest of its encoding along with the algorithm that produced it.
+///
+/// The text form (which is what the backends and trees store) is multihash-style: the hex of the
+/// varint algorithm code, the varint digest length and the digest. SHA-256 IDs are the exception
+/// and are written as the bare hex digest, so the IDs of objects written before IDs were tagged
+/// stay valid. Every ID has exactly one text form, as the same object under two IDs would be two
+/// different objects to the stores.
+///
+/// Backends, trees and deltas keep IDs as these strings rather than as `ObjectId`s, so that the IDs
+/// written before they were tagged and the stores' schemas stay as they were. Parse an ID when its
+/// algorithm is needed, like [`super::SyngObjectDef::get_hash_like`] does.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId {
+    algorithm: HashAlgorithm,
+    digest: Vec<u8>,
+}
+
+fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+/// Reads an unsigned varint from the start of `bytes`, returning it with the bytes after it
+fn read_varint(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let mut value: u64 = 0;
+
+    for (index, &byte) in bytes.iter().enumerate().take(9) {
+        value |= u64::from(byte & 0x7f) << (7 * index);
+
+        if byte & 0x80 == 0 {
+            return Some((value, &bytes[index + 1..]));
+        }
+    }
+
+    None
+}
+
+/// Decodes lowercase hex, which is the only case IDs are written in
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    let nibble = |b: u8| match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        _ => None,
+    };
+
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| Some(nibble(pair[0])? << 4 | nibble(pair[1])?))
+        .collect()
+}
+
+impl ObjectId {
+    /// Hashes `data` with `algorithm`
+    pub fn hash(algorithm: HashAlgorithm, data: &[u8]) -> Self {
+        Self {
+            algorithm,
+            digest: algorithm.digest(data),
+        }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+}
+
+impl Display for ObjectId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = vec![];
+
+        if self.algorithm != HashAlgorithm::Sha256 {
+            write_varint(self.algorithm.code(), &mut bytes);
+            write_varint(self.digest.len() as u64, &mut bytes);
+        }
+
+        bytes.extend_from_slice(&self.digest);
+
+        for byte in bytes {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = SyngError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || SyngError::InvalidObjectId(s.to_owned());
+
+        let bytes = decode_hex(s).ok_or_else(invalid)?;
+
+        if bytes.len() == HashAlgorithm::Sha256.digest_len() {
+            return Ok(Self {
+                algorithm: HashAlgorithm::Sha256,
+                digest: bytes,
+            });
+        }
+
+        let (code, rest) = read_varint(&bytes).ok_or_else(invalid)?;
+        let (len, digest) = read_varint(rest).ok_or_else(invalid)?;
+
+        let algorithm = HashAlgorithm::from_code(code)
+            .filter(|algorithm| *algorithm != HashAlgorithm::Sha256)
+            .ok_or_else(invalid)?;
+
+        if len != algorithm.digest_len() as u64 || digest.len() != algorithm.digest_len() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            algorithm,
+            digest: digest.to_vec(),
+        })
+    }
+}
+
+impl Serialize for ObjectId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::SyngBackend,
+        testing::{named, MemoryBackend},
+    };
+
+    const SHA256_ID: &str = "25e0c56a367b0e7b0f87da298103b781a95a9bf4e7a2e4693ce6b61e339bb8c9";
+
+    #[test]
+    fn sha256_ids_are_bare_hex() {
+        let id = SHA256_ID.parse::<ObjectId>().unwrap();
+
+        assert_eq!(id.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(id.digest().len(), 32);
+        assert_eq!(id.to_string(), SHA256_ID);
+    }
+
+    #[test]
+    fn blake3_ids_are_prefixed() {
+        let id = ObjectId::hash(HashAlgorithm::Blake3, b"data");
+        let text = id.to_string();
+
+        assert_eq!(&text[..4], "1e20");
+        assert_eq!(&text[4..], blake3::hash(b"data").to_hex().as_str());
+        assert_eq!(text.parse::<ObjectId>().unwrap(), id);
+    }
+
+    #[test]
+    fn sha256_ids_round_trip() {
+        let id = ObjectId::hash(HashAlgorithm::Sha256, b"data");
+
+        assert_eq!(id.to_string().len(), 64);
+        assert_eq!(id.to_string().parse::<ObjectId>().unwrap(), id);
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        let blake3_id = ObjectId::hash(HashAlgorithm::Blake3, b"data").to_string();
+
+        let invalid = [
+            // Odd length
+            SHA256_ID[1..].to_owned(),
+            format!("{}0", SHA256_ID),
+            // Unknown algorithm code
+            format!("1f20{}", &blake3_id[4..]),
+            // SHA-256 only has the bare form
+            format!("1220{}", SHA256_ID),
+            // Digest length not matching the algorithm
+            format!("1e1f{}", &blake3_id[6..]),
+            blake3_id[..blake3_id.len() - 2].to_owned(),
+            // Only lowercase hex is valid
+            SHA256_ID.to_uppercase(),
+            String::new(),
+        ];
+
+        for id in invalid {
+            assert_eq!(
+                id.parse::<ObjectId>(),
+                Err(SyngError::InvalidObjectId(id.clone())),
+                "{:?} should be rejected",
+                id
+            );
+        }
+    }
+
+    #[test]
+    fn get_hash_like_matches_the_algorithm_of_the_id() {
+        let mut sha256_backend = MemoryBackend::default();
+        let mut blake3_backend = MemoryBackend {
+            algorithm: HashAlgorithm::Blake3,
+            ..Default::default()
+        };
+
+        // A store holding objects written by both
+        let mut ids = vec![];
+
+        for name in ["a", "b"] {
+            ids.push(sha256_backend.write_named(name, &[]));
+            ids.push(blake3_backend.write_named(name, &[]));
+        }
+
+        let mut store = sha256_backend;
+        store.objects.extend(blake3_backend.objects);
+
+        for id in ids {
+            let obj = store.read_object(&id).unwrap().unwrap();
+
+            assert_eq!(obj.get_hash_like(&id).unwrap(), id);
+        }
+
+        let obj = named("a");
+
+        assert_eq!(
+            obj.get_hash_like("not an ID").unwrap(),
+            obj.get_hash().unwrap()
+        );
+    }
+}
@@ -1,22 +1,86 @@
-use anyhow::Result;
 use ciborium::ser::into_writer;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::SyngError;
+
+mod id;
+mod value;
+
+pub use id::{HashAlgorithm, ObjectId};
+pub use value::SyngValue;
+
+/// Reserved field holding the stable identity of a node. The ID of an object changes with every
+/// edit since it is the hash of the content, the node ID stays the same so the node can still be
+/// found after it was edited or moved (see the `*_by_node_id` functions in [`crate::tree_ops`]).
+pub const NODE_ID_FIELD: &str = "syng.id";
+
+/// Makes the node IDs generated within the process unique even when the clock doesn't move
+static NODE_ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Generates a node ID that is unique with overwhelming probability, to be set in
+/// [`NODE_ID_FIELD`]
+pub fn new_node_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+
+    let seed = format!(
+        "{}-{}-{}",
+        nanos,
+        std::process::id(),
+        NODE_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+
+    ObjectId::hash(HashAlgorithm::Sha256, seed.as_bytes()).to_string()[..32].to_owned()
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SyngObjectDef {
-    pub fields: BTreeMap<String, String>,
+    pub fields: BTreeMap<String, SyngValue>,
     pub children: Vec<String>,
 }
 
 impl SyngObjectDef {
-    pub fn get_hash(&self) -> Result<String> {
+    /// The SHA-256 ID of the object. Backends can hash with another algorithm, see
+    /// [`crate::backend::SyngBackend::object_id`] for the ID an object gets in a backend.
+    pub fn get_hash(&self) -> Result<String, SyngError> {
+        Ok(self.get_id(HashAlgorithm::Sha256)?.to_string())
+    }
+
+    pub fn get_id(&self, algorithm: HashAlgorithm) -> Result<ObjectId, SyngError> {
         let mut data_sink = Vec::<u8>::new();
 
-        into_writer(&self, &mut data_sink)?;
+        into_writer(&self, &mut data_sink).map_err(|e| SyngError::EncodeFailed(e.to_string()))?;
+
+        Ok(ObjectId::hash(algorithm, data_sink.as_slice()))
+    }
+
+    /// The ID the object has with the algorithm `id` was hashed with, which is what `id` should be
+    /// for an object stored under it. Falls back to SHA-256 if `id` isn't a valid ID.
+    pub fn get_hash_like(&self, id: &str) -> Result<String, SyngError> {
+        let algorithm = id
+            .parse::<ObjectId>()
+            .map(|id| id.algorithm())
+            .unwrap_or_default();
+
+        Ok(self.get_id(algorithm)?.to_string())
+    }
 
-        let hash = sha256::digest(data_sink.as_slice());
+    /// The stable identity of the node, if it has one
+    pub fn node_id(&self) -> Option<&str> {
+        self.fields.get(NODE_ID_FIELD)?.as_str()
+    }
 
-        Ok(hash)
+    /// Gives the object a newly generated node ID, replacing any it had
+    pub fn with_new_node_id(mut self) -> Self {
+        self.fields
+            .insert(NODE_ID_FIELD.to_owned(), new_node_id().into());
+        self
     }
 }
@@ -0,0 +1,493 @@
+use std::{collections::BTreeMap, fmt::Formatter};
+
+use serde::{
+    de::{Error, MapAccess, SeqAccess, Unexpected, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// Value of a field of a [`super::SyngObjectDef`].
+///
+/// In binary formats (like the CBOR the objects are hashed and stored with) every variant maps to
+/// the matching native type, so a string value encodes as a plain CBOR string and objects with
+/// only string fields keep the IDs they had when fields were plain strings. Human-readable formats
+/// like JSON can't tell bytes from a list of numbers or encode NaN, so values are externally
+/// tagged there (`{"String": "..."}`) to make them survive a round trip unchanged.
+///
+/// Floats compare by their bits with every NaN being the same value, matching how they are hashed.
+#[derive(Clone, Debug)]
+pub enum SyngValue {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<SyngValue>),
+    Map(BTreeMap<String, SyngValue>),
+}
+
+/// Gives every NaN the same bits, so equal values always encode (and hash) the same
+fn canonical_float(value: f64) -> f64 {
+    if value.is_nan() {
+        f64::NAN
+    } else {
+        value
+    }
+}
+
+impl SyngValue {
+    pub fn is_null(&self) -> bool {
+        matches!(self, SyngValue::Null)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            SyngValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            SyngValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            SyngValue::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            SyngValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            SyngValue::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[SyngValue]> {
+        match self {
+            SyngValue::List(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&BTreeMap<String, SyngValue>> {
+        match self {
+            SyngValue::Map(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for SyngValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (SyngValue::Null, SyngValue::Null) => true,
+            (SyngValue::Bool(a), SyngValue::Bool(b)) => a == b,
+            (SyngValue::Integer(a), SyngValue::Integer(b)) => a == b,
+            (SyngValue::Float(a), SyngValue::Float(b)) => {
+                canonical_float(*a).to_bits() == canonical_float(*b).to_bits()
+            }
+            (SyngValue::String(a), SyngValue::String(b)) => a == b,
+            (SyngValue::Bytes(a), SyngValue::Bytes(b)) => a == b,
+            (SyngValue::List(a), SyngValue::List(b)) => a == b,
+            (SyngValue::Map(a), SyngValue::Map(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for SyngValue {}
+
+impl From<&str> for SyngValue {
+    fn from(value: &str) -> Self {
+        SyngValue::String(value.to_owned())
+    }
+}
+
+impl From<String> for SyngValue {
+    fn from(value: String) -> Self {
+        SyngValue::String(value)
+    }
+}
+
+impl From<bool> for SyngValue {
+    fn from(value: bool) -> Self {
+        SyngValue::Bool(value)
+    }
+}
+
+impl From<i64> for SyngValue {
+    fn from(value: i64) -> Self {
+        SyngValue::Integer(value)
+    }
+}
+
+impl From<f64> for SyngValue {
+    fn from(value: f64) -> Self {
+        SyngValue::Float(value)
+    }
+}
+
+impl From<Vec<u8>> for SyngValue {
+    fn from(value: Vec<u8>) -> Self {
+        SyngValue::Bytes(value)
+    }
+}
+
+impl From<Vec<SyngValue>> for SyngValue {
+    fn from(value: Vec<SyngValue>) -> Self {
+        SyngValue::List(value)
+    }
+}
+
+impl From<BTreeMap<String, SyngValue>> for SyngValue {
+    fn from(value: BTreeMap<String, SyngValue>) -> Self {
+        SyngValue::Map(value)
+    }
+}
+
+/// Float of the human-readable form, with the values JSON has no numbers for written as strings
+struct TaggedFloat(f64);
+
+impl Serialize for TaggedFloat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            value if value.is_nan() => serializer.serialize_str("NaN"),
+            f64::INFINITY => serializer.serialize_str("inf"),
+            f64::NEG_INFINITY => serializer.serialize_str("-inf"),
+            value => serializer.serialize_f64(value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TaggedFloat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FloatVisitor;
+
+        impl Visitor<'_> for FloatVisitor {
+            type Value = TaggedFloat;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                formatter.write_str("a number, \"NaN\", \"inf\" or \"-inf\"")
+            }
+
+            fn visit_f64<E: Error>(self, value: f64) -> Result<Self::Value, E> {
+                Ok(TaggedFloat(value))
+            }
+
+            fn visit_i64<E: Error>(self, value: i64) -> Result<Self::Value, E> {
+                Ok(TaggedFloat(value as f64))
+            }
+
+            fn visit_u64<E: Error>(self, value: u64) -> Result<Self::Value, E> {
+                Ok(TaggedFloat(value as f64))
+            }
+
+            fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+                match value {
+                    "NaN" => Ok(TaggedFloat(f64::NAN)),
+                    "inf" => Ok(TaggedFloat(f64::INFINITY)),
+                    "-inf" => Ok(TaggedFloat(f64::NEG_INFINITY)),
+                    _ => Err(E::invalid_value(Unexpected::Str(value), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(FloatVisitor)
+    }
+}
+
+#[derive(Serialize)]
+enum TaggedRef<'a> {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(TaggedFloat),
+    String(&'a str),
+    Bytes(&'a [u8]),
+    List(&'a [SyngValue]),
+    Map(&'a BTreeMap<String, SyngValue>),
+}
+
+#[derive(Deserialize)]
+enum Tagged {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(TaggedFloat),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<SyngValue>),
+    Map(BTreeMap<String, SyngValue>),
+}
+
+impl Serialize for SyngValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            let tagged = match self {
+                SyngValue::Null => TaggedRef::Null,
+                SyngValue::Bool(value) => TaggedRef::Bool(*value),
+                SyngValue::Integer(value) => TaggedRef::Integer(*value),
+                SyngValue::Float(value) => TaggedRef::Float(TaggedFloat(canonical_float(*value))),
+                SyngValue::String(value) => TaggedRef::String(value),
+                SyngValue::Bytes(value) => TaggedRef::Bytes(value),
+                SyngValue::List(value) => TaggedRef::List(value),
+                SyngValue::Map(value) => TaggedRef::Map(value),
+            };
+
+            return tagged.serialize(serializer);
+        }
+
+        match self {
+            SyngValue::Null => serializer.serialize_unit(),
+            SyngValue::Bool(value) => serializer.serialize_bool(*value),
+            SyngValue::Integer(value) => serializer.serialize_i64(*value),
+            SyngValue::Float(value) => serializer.serialize_f64(canonical_float(*value)),
+            SyngValue::String(value) => serializer.serialize_str(value),
+            SyngValue::Bytes(value) => serializer.serialize_bytes(value),
+            SyngValue::List(value) => serializer.collect_seq(value),
+            SyngValue::Map(value) => serializer.collect_map(value),
+        }
+    }
+}
+
+/// Reads the native form of binary formats
+struct NativeVisitor;
+
+impl<'de> Visitor<'de> for NativeVisitor {
+    type Value = SyngValue;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("a field value")
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Self::Value, E> {
+        Ok(SyngValue::Null)
+    }
+
+    fn visit_none<E: Error>(self) -> Result<Self::Value, E> {
+        Ok(SyngValue::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        SyngValue::deserialize(deserializer)
+    }
+
+    fn visit_bool<E: Error>(self, value: bool) -> Result<Self::Value, E> {
+        Ok(SyngValue::Bool(value))
+    }
+
+    fn visit_i64<E: Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(SyngValue::Integer(value))
+    }
+
+    fn visit_u64<E: Error>(self, value: u64) -> Result<Self::Value, E> {
+        i64::try_from(value)
+            .map(SyngValue::Integer)
+            .map_err(|_| E::invalid_value(Unexpected::Unsigned(value), &"a 64-bit signed integer"))
+    }
+
+    fn visit_f64<E: Error>(self, value: f64) -> Result<Self::Value, E> {
+        Ok(SyngValue::Float(value))
+    }
+
+    fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(SyngValue::String(value.to_owned()))
+    }
+
+    fn visit_string<E: Error>(self, value: String) -> Result<Self::Value, E> {
+        Ok(SyngValue::String(value))
+    }
+
+    fn visit_bytes<E: Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        Ok(SyngValue::Bytes(value.to_vec()))
+    }
+
+    fn visit_byte_buf<E: Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(SyngValue::Bytes(value))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut list = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+        while let Some(value) = seq.next_element()? {
+            list.push(value);
+        }
+
+        Ok(SyngValue::List(list))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut result = BTreeMap::new();
+
+        while let Some((key, value)) = map.next_entry()? {
+            result.insert(key, value);
+        }
+
+        Ok(SyngValue::Map(result))
+    }
+}
+
+impl<'de> Deserialize<'de> for SyngValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return deserializer.deserialize_any(NativeVisitor);
+        }
+
+        Ok(match Tagged::deserialize(deserializer)? {
+            Tagged::Null => SyngValue::Null,
+            Tagged::Bool(value) => SyngValue::Bool(value),
+            Tagged::Integer(value) => SyngValue::Integer(value),
+            Tagged::Float(TaggedFloat(value)) => SyngValue::Float(value),
+            Tagged::String(value) => SyngValue::String(value),
+            Tagged::Bytes(value) => SyngValue::Bytes(value),
+            Tagged::List(value) => SyngValue::List(value),
+            Tagged::Map(value) => SyngValue::Map(value),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::SyngObjectDef;
+
+    fn cbor(value: &impl Serialize) -> Vec<u8> {
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(value, &mut bytes).unwrap();
+
+        bytes
+    }
+
+    /// An object with one field of every kind, which pins the encoding of all of them at once
+    fn every_kind() -> SyngObjectDef {
+        let values: [(&str, SyngValue); 9] = [
+            ("bool", true.into()),
+            ("bytes", vec![1u8, 2].into()),
+            ("float", 1.5.into()),
+            ("integer", (-1i64).into()),
+            ("list", vec![SyngValue::from(1i64), "a".into()].into()),
+            (
+                "map",
+                BTreeMap::from([("a".to_owned(), SyngValue::from(1i64))]).into(),
+            ),
+            ("nan", f64::NAN.into()),
+            ("null", SyngValue::Null),
+            ("string", "a".into()),
+        ];
+
+        SyngObjectDef {
+            fields: values
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect(),
+            children: vec![],
+        }
+    }
+
+    #[test]
+    fn values_encode_as_native_cbor() {
+        let cases: [(SyngValue, &[u8]); 11] = [
+            (SyngValue::Null, &[0xf6]),
+            (true.into(), &[0xf5]),
+            (1i64.into(), &[0x01]),
+            ((-1i64).into(), &[0x20]),
+            (1.5.into(), &[0xf9, 0x3e, 0x00]),
+            (
+                0.1.into(),
+                &[0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a],
+            ),
+            (f64::NAN.into(), &[0xf9, 0x7e, 0x00]),
+            ("a".into(), &[0x61, 0x61]),
+            (vec![1u8, 2].into(), &[0x42, 0x01, 0x02]),
+            (
+                vec![SyngValue::from(1i64), "a".into()].into(),
+                &[0x82, 0x01, 0x61, 0x61],
+            ),
+            (
+                BTreeMap::from([("a".to_owned(), SyngValue::from(1i64))]).into(),
+                &[0xa1, 0x61, 0x61, 0x01],
+            ),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(cbor(&value), expected, "encoding of {:?}", value);
+        }
+    }
+
+    #[test]
+    fn hashes_are_stable() {
+        assert_eq!(
+            every_kind().get_hash().unwrap(),
+            "32811203f6a24588e0faf391be7b40bda1291304b4002e3e80f48aad11ccffd3"
+        );
+    }
+
+    #[test]
+    fn string_fields_hash_like_plain_strings() {
+        #[derive(Serialize)]
+        struct PlainObjectDef {
+            fields: BTreeMap<String, String>,
+            children: Vec<String>,
+        }
+
+        let plain = PlainObjectDef {
+            fields: BTreeMap::from([("name".to_owned(), "root".to_owned())]),
+            children: vec!["child".to_owned()],
+        };
+        let obj = SyngObjectDef {
+            fields: BTreeMap::from([("name".to_owned(), "root".into())]),
+            children: vec!["child".to_owned()],
+        };
+
+        assert_eq!(cbor(&obj), cbor(&plain));
+        assert_eq!(
+            obj.get_hash().unwrap(),
+            "70c2d91ea3e30e459c26215a337b324773d6b86cd64981ba918dc952c6a60fe5"
+        );
+    }
+
+    #[test]
+    fn every_nan_encodes_the_same() {
+        let nans = [
+            f64::NAN,
+            -f64::NAN,
+            f64::from_bits(0x7ff8_0000_0000_0001),
+            f64::from_bits(0xfff0_0000_0000_0001),
+            f64::INFINITY - f64::INFINITY,
+        ];
+
+        let mut obj = every_kind();
+        let expected_hash = obj.get_hash().unwrap();
+
+        for nan in nans {
+            assert!(nan.is_nan());
+            assert_eq!(SyngValue::Float(nan), SyngValue::Float(f64::NAN));
+            assert_eq!(
+                cbor(&SyngValue::Float(nan)),
+                cbor(&SyngValue::Float(f64::NAN))
+            );
+
+            obj.fields.insert("nan".to_owned(), nan.into());
+            assert_eq!(obj.get_hash().unwrap(), expected_hash);
+        }
+    }
+
+    #[test]
+    fn zeroes_keep_their_sign() {
+        assert_ne!(SyngValue::Float(0.0), SyngValue::Float(-0.0));
+        assert_ne!(cbor(&SyngValue::Float(0.0)), cbor(&SyngValue::Float(-0.0)));
+    }
+}
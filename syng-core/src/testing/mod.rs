@@ -0,0 +1,300 @@
+//! In-memory backend and tree helpers shared by the unit tests
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use crate::{
+    backend::{check_ref_name, SyngBackend, DEFAULT_REF},
+    error::SyngError,
+    objects::{HashAlgorithm, SyngObjectDef},
+};
+
+/// Backend keeping everything in maps, with named refs and a configurable hash algorithm
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MemoryBackend {
+    pub algorithm: HashAlgorithm,
+    pub objects: HashMap<String, SyngObjectDef>,
+    pub refs: BTreeMap<String, String>,
+
+    /// Number of times the root was moved
+    pub root_changes: usize,
+
+    /// Objects whose stored data reads as undecodable
+    pub corrupt_objects: HashSet<String>,
+
+    /// Number of objects that can still be written before every write fails, `None` for no limit
+    pub writes_left: Option<usize>,
+
+    /// Root another client moves the root to during the next write of objects
+    pub concurrent_root: Option<String>,
+
+    /// Objects and refs when the transaction began
+    pub snapshot: Option<(HashMap<String, SyngObjectDef>, BTreeMap<String, String>)>,
+}
+
+impl MemoryBackend {
+    /// Writes the object named `name` with the given children, returning its ID
+    pub fn write_named(&mut self, name: &str, children: &[&str]) -> String {
+        let mut obj = named(name);
+        obj.children = children.iter().map(|id| id.to_string()).collect();
+
+        self.write_object(&obj).unwrap()
+    }
+
+    /// Writes a tree written in the same form [`MemoryBackend::describe`] returns, like
+    /// `root(a, b(c))`, returning the ID of its root
+    pub fn write_tree(&mut self, tree: &str) -> String {
+        let (id, rest) = self.write_subtree(tree.trim());
+        assert!(rest.trim().is_empty(), "trailing input {:?}", rest);
+
+        id
+    }
+
+    fn write_subtree<'a>(&mut self, tree: &'a str) -> (String, &'a str) {
+        let name_end = tree.find(['(', ',', ')']).unwrap_or(tree.len());
+        let name = tree[..name_end].trim();
+        let mut rest = &tree[name_end..];
+        let mut children = vec![];
+
+        if let Some(inner) = rest.strip_prefix('(') {
+            rest = inner;
+
+            loop {
+                let (child, after) = self.write_subtree(rest.trim_start());
+                children.push(child);
+                rest = after.trim_start();
+
+                match rest.strip_prefix(',') {
+                    Some(after) => rest = after,
+                    None => break,
+                }
+            }
+
+            rest = rest.strip_prefix(')').expect("unclosed child list");
+        }
+
+        let children = children.iter().map(String::as_str).collect::<Vec<_>>();
+
+        (self.write_named(name, &children), rest)
+    }
+
+    /// Writes the tree and makes it the root, returning its ID
+    pub fn set_tree(&mut self, tree: &str) -> String {
+        let root = self.write_tree(tree);
+        self.set_root_object(&root).unwrap();
+
+        root
+    }
+
+    /// The tree under `id` as the names of its objects, like `root(a, b(c))`
+    pub fn describe(&self, id: &str) -> String {
+        let obj = self.objects.get(id).expect("object not in the backend");
+        let name = obj.fields["name"].as_str().unwrap().to_owned();
+
+        if obj.children.is_empty() {
+            return name;
+        }
+
+        let children = obj
+            .children
+            .iter()
+            .map(|child| self.describe(child))
+            .collect::<Vec<_>>();
+
+        format!("{}({})", name, children.join(", "))
+    }
+
+    /// [`MemoryBackend::describe`] of the root
+    pub fn describe_root(&self) -> String {
+        self.describe(&self.get_root_object_id().unwrap().unwrap())
+    }
+}
+
+impl MemoryBackend {
+    /// Counts down [`MemoryBackend::writes_left`], failing once there are none left
+    fn take_write(&mut self) -> Result<(), SyngError> {
+        match &mut self.writes_left {
+            Some(0) => Err(SyngError::StorageFailed("out of writes".to_owned())),
+            Some(left) => {
+                *left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the root to [`MemoryBackend::concurrent_root`] outside of any transaction, like
+    /// another client would
+    fn move_root_concurrently(&mut self) {
+        let Some(root) = self.concurrent_root.take() else {
+            return;
+        };
+
+        if let Some((_, refs)) = &mut self.snapshot {
+            refs.insert(DEFAULT_REF.to_owned(), root.clone());
+        }
+
+        self.refs.insert(DEFAULT_REF.to_owned(), root);
+    }
+}
+
+/// Runs a future that never waits, like the async functions called on a
+/// [`crate::async_backend::SyncBackendAdapter`]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+
+        fn noop(_: *const ()) {}
+
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    // Safety: the waker does nothing with its data pointer
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+
+    match pin!(future).poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("the future waited"),
+    }
+}
+
+/// An object with just a `name` field
+pub(crate) fn named(name: &str) -> SyngObjectDef {
+    SyngObjectDef {
+        fields: BTreeMap::from([("name".to_owned(), name.into())]),
+        children: vec![],
+    }
+}
+
+impl SyngBackend for MemoryBackend {
+    fn get_root_object_id(&self) -> Result<Option<String>, SyngError> {
+        Ok(self.refs.get(DEFAULT_REF).cloned())
+    }
+
+    fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError> {
+        match self.get_root_object_id()? {
+            Some(root_id) => self.read_object(&root_id),
+            None => Ok(None),
+        }
+    }
+
+    fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError> {
+        if !self.has_object(node_id)? {
+            return Err(SyngError::ObjectNotFound(node_id.to_owned()));
+        }
+
+        self.refs.insert(DEFAULT_REF.to_owned(), node_id.to_owned());
+        self.root_changes += 1;
+
+        Ok(())
+    }
+
+    fn list_refs(&self) -> Result<BTreeMap<String, String>, SyngError> {
+        Ok(self.refs.clone())
+    }
+
+    fn read_ref(&self, name: &str) -> Result<Option<String>, SyngError> {
+        check_ref_name(name)?;
+
+        Ok(self.refs.get(name).cloned())
+    }
+
+    fn compare_and_set_ref(
+        &mut self,
+        name: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        if self.refs.get(name).map(String::as_str) != expected {
+            return Ok(false);
+        }
+
+        self.refs.insert(name.to_owned(), new.to_owned());
+
+        if name == DEFAULT_REF {
+            self.root_changes += 1;
+        }
+
+        Ok(true)
+    }
+
+    fn delete_ref(&mut self, name: &str) -> Result<bool, SyngError> {
+        check_ref_name(name)?;
+
+        Ok(self.refs.remove(name).is_some())
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError> {
+        if self.corrupt_objects.contains(id) {
+            return Err(SyngError::DecodeFailed(format!("corrupt object {}", id)));
+        }
+
+        Ok(self.objects.get(id).cloned())
+    }
+
+    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError> {
+        self.take_write()?;
+
+        let id = self.object_id(def)?;
+        self.objects.insert(id.clone(), def.clone());
+
+        Ok(id)
+    }
+
+    fn write_objects_with_ids(
+        &mut self,
+        objects: &[(&str, &SyngObjectDef)],
+    ) -> Result<(), SyngError> {
+        self.move_root_concurrently();
+
+        for (id, def) in objects {
+            self.take_write()?;
+            self.objects.insert(id.to_string(), (*def).clone());
+        }
+
+        Ok(())
+    }
+
+    fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        Ok(self.objects.keys().cloned().collect())
+    }
+
+    fn delete_object(&mut self, id: &str) -> Result<bool, SyngError> {
+        Ok(self.objects.remove(id).is_some())
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), SyngError> {
+        self.snapshot = Some((self.objects.clone(), self.refs.clone()));
+
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), SyngError> {
+        self.snapshot = None;
+
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), SyngError> {
+        if let Some((objects, refs)) = self.snapshot.take() {
+            self.objects = objects;
+            self.refs = refs;
+        }
+
+        Ok(())
+    }
+}
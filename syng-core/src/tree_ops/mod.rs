@@ -1,98 +1,388 @@
-use crate::{backend::SyngBackend, objects::SyngObjectDef};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::{Display, Formatter},
+};
 
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::SyngBackend,
+    commit::{record_commit, CommitInfo},
+    error::SyngError,
+    objects::{HashAlgorithm, SyngObjectDef, NODE_ID_FIELD},
+};
+
+pub(crate) mod transaction;
+
+pub use transaction::TreeTransaction;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ChildAdditionPosition {
     AddToEnd,
     AddAt(usize),
 }
 
+/// Why a tree operation failed. Paths are index paths from the root of the tree the operation was
+/// applied to.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TreeOpError {
+    /// The object at `parent_path` has no child at `index`, so the path can't be followed further
+    ChildNotFound {
+        parent_path: Vec<usize>,
+        index: usize,
+    },
+
+    /// `AddAt(index)` is past the `len` children of the object at `parent_path`
+    PositionOutOfRange {
+        parent_path: Vec<usize>,
+        index: usize,
+        len: usize,
+    },
+
+    /// The operation works on the parent of the object, but the path is the one of the root
+    RootHasNoParent,
+
+    /// The object at `from_path` would be moved into its own subtree
+    MoveIntoOwnSubtree {
+        from_path: Vec<usize>,
+        to_parent_path: Vec<usize>,
+    },
+
+    /// No node in the tree has the node ID
+    NodeNotFound(String),
+
+    /// The root moved away from the base root of a [`TreeTransaction`] before it was committed
+    RootMoved {
+        expected: String,
+        actual: Option<String>,
+    },
+
+    /// Reading or writing the backend failed
+    Backend(SyngError),
+}
+
+impl From<SyngError> for TreeOpError {
+    fn from(value: SyngError) -> Self {
+        TreeOpError::Backend(value)
+    }
+}
+
+impl Display for TreeOpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeOpError::ChildNotFound { parent_path, index } => write!(
+                f,
+                "object at {:?} has no child at index {}",
+                parent_path, index
+            ),
+            TreeOpError::PositionOutOfRange {
+                parent_path,
+                index,
+                len,
+            } => write!(
+                f,
+                "position {} is out of range for the {} children of the object at {:?}",
+                index, len, parent_path
+            ),
+            TreeOpError::RootHasNoParent => write!(f, "the root has no parent"),
+            TreeOpError::MoveIntoOwnSubtree {
+                from_path,
+                to_parent_path,
+            } => write!(
+                f,
+                "object at {:?} can't be moved into its own subtree at {:?}",
+                from_path, to_parent_path
+            ),
+            TreeOpError::NodeNotFound(node_id) => write!(f, "no node with ID {}", node_id),
+            TreeOpError::RootMoved { expected, actual } => {
+                write!(f, "root moved away from {} (now {:?})", expected, actual)
+            }
+            TreeOpError::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TreeOpError {}
+
+#[derive(Clone, Debug, Default)]
+pub struct TreeOpOptions {
+    /// Record a commit of the new root in [`crate::commit::HISTORY_REF`]
+    pub commit: Option<CommitInfo>,
+}
+
+/// Moves the root to the rewritten tree, recording a commit of it if the options ask for one
+fn set_new_root(
+    backend: &mut impl SyngBackend,
+    root_id: &str,
+    options: &TreeOpOptions,
+) -> Result<(), SyngError> {
+    backend.set_root_object(root_id)?;
+
+    if let Some(info) = &options.commit {
+        record_commit(backend, root_id, info)?;
+    }
+
+    Ok(())
+}
+
+/// Reads an object that is expected to exist (because something refers to it), treating a missing
+/// object as an error
+pub(crate) fn read_referred_object(
+    backend: &impl SyngBackend,
+    id: &str,
+) -> Result<SyngObjectDef, SyngError> {
+    backend
+        .read_object(id)?
+        .ok_or_else(|| SyngError::ObjectNotFound(id.to_owned()))
+}
+
+/// Batch version of [`read_referred_object`], reading all the objects with one
+/// [`SyngBackend::read_objects`] call
+pub(crate) fn read_referred_objects(
+    backend: &impl SyngBackend,
+    ids: &[String],
+) -> Result<Vec<SyngObjectDef>, SyngError> {
+    let id_refs = ids.iter().map(String::as_str).collect::<Vec<_>>();
+
+    backend
+        .read_objects(&id_refs)?
+        .into_iter()
+        .zip(ids)
+        .map(|(obj, id)| obj.ok_or_else(|| SyngError::ObjectNotFound(id.clone())))
+        .collect()
+}
+
+fn read_root_object(backend: &impl SyngBackend) -> Result<(String, SyngObjectDef), SyngError> {
+    let root_id = backend
+        .get_root_object_id()?
+        .ok_or(SyngError::NoRootObject)?;
+
+    let root_obj = read_referred_object(backend, &root_id)?;
+
+    Ok((root_id, root_obj))
+}
+
+/// The child of `obj` that the path continues with after `depth` steps
+pub(crate) fn next_child_id<'a>(
+    obj: &'a SyngObjectDef,
+    obj_path: &[usize],
+    depth: usize,
+) -> Result<&'a String, TreeOpError> {
+    let index = obj_path[depth];
+
+    obj.children
+        .get(index)
+        .ok_or_else(|| TreeOpError::ChildNotFound {
+            parent_path: obj_path[..depth].to_vec(),
+            index,
+        })
+}
+
+/// Index a new child gets in the `len` children of the object at `parent_path`
+pub(crate) fn addition_index(
+    parent_path: &[usize],
+    len: usize,
+    position: ChildAdditionPosition,
+) -> Result<usize, TreeOpError> {
+    match position {
+        ChildAdditionPosition::AddToEnd => Ok(len),
+        ChildAdditionPosition::AddAt(index) if index < len => Ok(index),
+        ChildAdditionPosition::AddAt(index) => Err(TreeOpError::PositionOutOfRange {
+            parent_path: parent_path.to_vec(),
+            index,
+            len,
+        }),
+    }
+}
+
+/// Returns the object at the given index path
 pub fn get_object_at_path(
     backend: &impl SyngBackend,
     obj_path: &[usize],
-) -> Option<(String, SyngObjectDef)> {
-    if obj_path.is_empty() {
-        Some((backend.get_root_object_id()?, backend.get_root_object()?))
-    } else {
-        let mut last_obj = (backend.get_root_object_id()?, backend.get_root_object()?);
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let mut last_obj = read_root_object(backend)?;
+
+    for depth in 0..obj_path.len() {
+        let index_obj_id = next_child_id(&last_obj.1, obj_path, depth)?.clone();
+        let index_obj = read_referred_object(backend, &index_obj_id)?;
+
+        last_obj = (index_obj_id, index_obj);
+    }
 
-        for index in obj_path {
-            let curr_obj = &last_obj.1;
+    Ok(last_obj)
+}
+
+/// Finds the index path of the node with the given stable ID (see
+/// [`crate::objects::NODE_ID_FIELD`]), walking the tree a level at a time so the nodes closest to
+/// the root are found first. Returns `None` if no node in the tree has the ID.
+///
+/// There is no index of the node IDs, so this reads every object of the tree in the worst case
+/// (deep nodes, or IDs that aren't there), and so does every `*_by_node_id` function since they
+/// start with this search. Callers doing several operations on the same nodes can find their
+/// paths once and use the path based functions instead.
+pub fn find_node_path(
+    backend: &impl SyngBackend,
+    node_id: &str,
+) -> Result<Option<Vec<usize>>, TreeOpError> {
+    let Some(root_id) = backend.get_root_object_id()? else {
+        return Ok(None);
+    };
+
+    // An object can appear in several places of the tree, but its subtree only has to be searched
+    // once
+    let mut visited = HashSet::from([root_id.clone()]);
+    let mut level = vec![(vec![], root_id)];
+
+    while !level.is_empty() {
+        let ids = level.iter().map(|(_, id)| id.clone()).collect::<Vec<_>>();
+        let objs = read_referred_objects(backend, &ids)?;
+
+        let mut next_level = vec![];
 
-            let index_obj_id = curr_obj.children.get(index.clone())?;
+        for ((path, _), obj) in level.into_iter().zip(objs) {
+            if obj.node_id() == Some(node_id) {
+                return Ok(Some(path));
+            }
 
-            last_obj = (index_obj_id.clone(), backend.read_object(index_obj_id)?);
+            for (index, child_id) in obj.children.into_iter().enumerate() {
+                if visited.insert(child_id.clone()) {
+                    next_level.push(([&path[..], &[index]].concat(), child_id));
+                }
+            }
         }
 
-        Some(last_obj)
+        level = next_level;
     }
+
+    Ok(None)
+}
+
+/// Like [`find_node_path`], failing with [`TreeOpError::NodeNotFound`] if no node has the ID
+fn node_path(backend: &impl SyngBackend, node_id: &str) -> Result<Vec<usize>, TreeOpError> {
+    find_node_path(backend, node_id)?.ok_or_else(|| TreeOpError::NodeNotFound(node_id.to_owned()))
+}
+
+/// Same as [`get_object_at_path`], with the object addressed by its node ID
+pub fn get_object_by_node_id(
+    backend: &impl SyngBackend,
+    node_id: &str,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let obj_path = node_path(backend, node_id)?;
+
+    get_object_at_path(backend, &obj_path)
 }
 
 fn get_objects_along_index_path(
     backend: &impl SyngBackend,
     obj_path: &[usize],
-) -> Option<Vec<(String, SyngObjectDef)>> {
-    if obj_path.is_empty() {
-        Some(vec![(
-            backend.get_root_object_id()?,
-            backend.get_root_object()?,
-        )])
-    } else {
-        let mut objs = Vec::with_capacity(obj_path.len() + 1);
+) -> Result<Vec<(String, SyngObjectDef)>, TreeOpError> {
+    let mut objs = Vec::with_capacity(obj_path.len() + 1);
 
-        objs.push((backend.get_root_object_id()?, backend.get_root_object()?));
+    objs.push(read_root_object(backend)?);
 
-        for index in obj_path {
-            let curr_obj = &objs.last()?.1;
+    for depth in 0..obj_path.len() {
+        let curr_obj = &objs.last().unwrap().1;
 
-            let index_obj_id = curr_obj.children.get(index.clone())?;
+        let index_obj_id = next_child_id(curr_obj, obj_path, depth)?.clone();
+        let index_obj = read_referred_object(backend, &index_obj_id)?;
 
-            objs.push((index_obj_id.clone(), backend.read_object(index_obj_id)?));
-        }
-
-        Some(objs)
+        objs.push((index_obj_id, index_obj));
     }
+
+    Ok(objs)
 }
 
-pub fn get_descendent_object_ids(backend: &impl SyngBackend, id: &str) -> Option<Vec<String>> {
+/// Walks the tree under `id` one level at a time, so that each level is fetched with a single
+/// batch read
+pub(crate) fn get_descendent_id_object_pairs(
+    backend: &impl SyngBackend,
+    id: &str,
+) -> Result<Vec<(String, SyngObjectDef)>, SyngError> {
     let mut result = vec![];
 
-    // Iterate through the tree using a queue in place of recursion
-    let mut search_queue = vec![id.to_owned()];
+    let mut level = vec![id.to_owned()];
 
-    while let Some(object_id) = search_queue.pop() {
-        let obj = backend.read_object(&object_id)?;
-        result.push(object_id);
+    while !level.is_empty() {
+        let objs = read_referred_objects(backend, &level)?;
 
-        search_queue.extend(obj.children);
+        let next_level = objs
+            .iter()
+            .flat_map(|obj| obj.children.iter().cloned())
+            .collect();
+
+        result.extend(level.into_iter().zip(objs));
+
+        level = next_level;
     }
 
-    Some(result)
+    Ok(result)
 }
 
-pub fn get_descendent_objects(backend: &impl SyngBackend, id: &str) -> Option<Vec<SyngObjectDef>> {
-    let mut result = vec![];
+/// [`get_descendent_object_ids`] for the callers reporting [`SyngError`]s
+pub(crate) fn descendent_object_ids(
+    backend: &impl SyngBackend,
+    id: &str,
+) -> Result<Vec<String>, SyngError> {
+    Ok(get_descendent_id_object_pairs(backend, id)?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect())
+}
 
-    // Iterate through the tree using a queue in place of recursion
-    let mut search_queue = vec![id.to_owned()];
+pub fn get_descendent_object_ids(
+    backend: &impl SyngBackend,
+    id: &str,
+) -> Result<Vec<String>, TreeOpError> {
+    Ok(descendent_object_ids(backend, id)?)
+}
+
+pub fn get_descendent_objects(
+    backend: &impl SyngBackend,
+    id: &str,
+) -> Result<Vec<SyngObjectDef>, TreeOpError> {
+    Ok(get_descendent_id_object_pairs(backend, id)?
+        .into_iter()
+        .map(|(_, obj)| obj)
+        .collect())
+}
 
-    while let Some(object_id) = search_queue.pop() {
-        let obj = backend.read_object(&object_id)?;
-        result.push(obj.clone());
+/// Carries the node ID of the replaced object over to its new version, unless the new version sets
+/// one itself
+pub(crate) fn keep_node_id(old_obj: &SyngObjectDef, new_def: &SyngObjectDef) -> SyngObjectDef {
+    let mut new_def = new_def.clone();
 
-        search_queue.extend(obj.children);
+    if let (Some(node_id), None) = (old_obj.node_id(), new_def.node_id()) {
+        new_def
+            .fields
+            .insert(NODE_ID_FIELD.to_owned(), node_id.into());
     }
 
-    Some(result)
+    new_def
 }
 
+/// Replaces the object at the path, keeping its node ID if the new object has none. Returns the ID
+/// and the written version of the new object.
 pub fn update_object(
     backend: &mut impl SyngBackend,
     obj_path: &[usize],
     new_def: &SyngObjectDef,
-) -> Option<(String, SyngObjectDef)> {
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    update_object_with_options(backend, obj_path, new_def, &TreeOpOptions::default())
+}
+
+pub fn update_object_with_options(
+    backend: &mut impl SyngBackend,
+    obj_path: &[usize],
+    new_def: &SyngObjectDef,
+    options: &TreeOpOptions,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
     let ancestor_objs = get_objects_along_index_path(backend, obj_path)?;
 
+    let (_, old_obj) = ancestor_objs.last().unwrap();
+    let new_def = &keep_node_id(old_obj, new_def);
+
     // Write the new object into the backend
-    let hash = backend.write_object(&new_def).ok()?;
+    let hash = backend.write_object(new_def)?;
 
     // Go in reverse through all the parent objects and update the tree
     let mut last_obj_id = hash.clone();
@@ -103,41 +393,55 @@ pub fn update_object(
 
         new_obj.children[obj_path[index]] = last_obj_id;
 
-        let new_hash = backend.write_object(&new_obj).ok()?;
+        let new_hash = backend.write_object(&new_obj)?;
 
         last_obj_id = new_hash;
     }
 
     // The last value of `last_obj_id` will be the root id of the updated root obj, so update the
     // root obj for the backend
-    backend.set_root_object(&last_obj_id).ok()?;
+    set_new_root(backend, &last_obj_id, options)?;
 
-    Some((hash, new_def.clone()))
+    Ok((hash, new_def.clone()))
 }
 
+/// Adds a child to the object at the path. Returns the ID of the new child.
 pub fn add_child_object(
     backend: &mut impl SyngBackend,
     parent_obj_path: &[usize],
     new_def: &SyngObjectDef,
     position: ChildAdditionPosition,
-) -> Option<(String, SyngObjectDef)> {
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    add_child_object_with_options(
+        backend,
+        parent_obj_path,
+        new_def,
+        position,
+        &TreeOpOptions::default(),
+    )
+}
+
+pub fn add_child_object_with_options(
+    backend: &mut impl SyngBackend,
+    parent_obj_path: &[usize],
+    new_def: &SyngObjectDef,
+    position: ChildAdditionPosition,
+    options: &TreeOpOptions,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
     let ancestor_objs = get_objects_along_index_path(backend, parent_obj_path)?;
 
-    let hash = backend.write_object(&new_def).ok()?;
+    let (_, direct_parent_obj) = ancestor_objs.last().unwrap();
+
+    // Check the position before writing anything
+    let index = addition_index(parent_obj_path, direct_parent_obj.children.len(), position)?;
 
-    let (_, direct_parent_obj) = ancestor_objs.last()?;
+    let hash = backend.write_object(new_def)?;
 
     let mut new_parent = direct_parent_obj.clone();
 
-    match position {
-        ChildAdditionPosition::AddToEnd => new_parent.children.push(hash.clone()),
-        ChildAdditionPosition::AddAt(index) if index < new_parent.children.len() => {
-            new_parent.children.insert(index, hash.clone());
-        }
-        _ => return None, // AddAt with index > children length, that operation is not allowed
-    };
+    new_parent.children.insert(index, hash.clone());
 
-    let mut last_parent_obj_id = backend.write_object(&new_parent).ok()?;
+    let mut last_parent_obj_id = backend.write_object(&new_parent)?;
 
     // Applying the changes to the entire tree
     for (index, (_, obj)) in ancestor_objs.iter().enumerate().rev().skip(1) {
@@ -147,18 +451,30 @@ pub fn add_child_object(
 
         new_obj.children[obj_index] = last_parent_obj_id;
 
-        let new_hash = backend.write_object(&new_obj).ok()?;
+        let new_hash = backend.write_object(&new_obj)?;
 
         last_parent_obj_id = new_hash;
     }
 
     // The last remaining value of `last_parent_obj_id` will be the root id
-    backend.set_root_object(&last_parent_obj_id).ok()?;
+    set_new_root(backend, &last_parent_obj_id, options)?;
 
-    Some((hash, new_def.clone()))
+    Ok((hash, new_def.clone()))
 }
 
-pub fn remove_child_object(backend: &mut impl SyngBackend, obj_path: &[usize]) -> Option<()> {
+/// Removes the object at the path from its parent
+pub fn remove_child_object(
+    backend: &mut impl SyngBackend,
+    obj_path: &[usize],
+) -> Result<(), TreeOpError> {
+    remove_child_object_with_options(backend, obj_path, &TreeOpOptions::default())
+}
+
+pub fn remove_child_object_with_options(
+    backend: &mut impl SyngBackend,
+    obj_path: &[usize],
+    options: &TreeOpOptions,
+) -> Result<(), TreeOpError> {
     let ancestor_objs = get_objects_along_index_path(backend, obj_path)?;
 
     // Since we are removing a child object, we have to make sure atleast 2 objects are there in the
@@ -168,16 +484,16 @@ pub fn remove_child_object(backend: &mut impl SyngBackend, obj_path: &[usize]) -
         (_, parent_obj),
         _
     ] = ancestor_objs.as_slice() else {
-        return None;
+        return Err(TreeOpError::RootHasNoParent);
     };
 
     let mut new_parent_obj = parent_obj.clone();
 
-    let delete_index = obj_path.last().unwrap().clone();
+    let delete_index = *obj_path.last().unwrap();
 
     new_parent_obj.children.remove(delete_index);
 
-    let mut last_parent_obj_id = backend.write_object(&new_parent_obj).ok()?;
+    let mut last_parent_obj_id = backend.write_object(&new_parent_obj)?;
 
     // Applying the changes to the entire tree
     for (index, (_, obj)) in remaining_ancestors.iter().enumerate().rev() {
@@ -187,13 +503,481 @@ pub fn remove_child_object(backend: &mut impl SyngBackend, obj_path: &[usize]) -
 
         new_obj.children[update_index] = last_parent_obj_id;
 
-        let new_hash = backend.write_object(&new_obj).ok()?;
+        let new_hash = backend.write_object(&new_obj)?;
 
         last_parent_obj_id = new_hash;
     }
 
     // The last remaining value of `last_parent_obj_id` will be the root id
-    backend.set_root_object(&last_parent_obj_id).ok()?;
+    set_new_root(backend, &last_parent_obj_id, options)?;
+
+    Ok(())
+}
+
+/// Checks that the object at `from_path` has a parent and isn't moved into its own subtree
+pub(crate) fn check_move(from_path: &[usize], to_parent_path: &[usize]) -> Result<(), TreeOpError> {
+    if from_path.is_empty() {
+        return Err(TreeOpError::RootHasNoParent);
+    }
+
+    if to_parent_path.starts_with(from_path) {
+        return Err(TreeOpError::MoveIntoOwnSubtree {
+            from_path: from_path.to_vec(),
+            to_parent_path: to_parent_path.to_vec(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Index the moved object gets in the children of its new parent (of `to_len` children before the
+/// move). Positions are given in the children of the new parent before the move, so when moving
+/// within the same parent, taking the object out shifts the later ones.
+pub(crate) fn move_insert_index(
+    from_path: &[usize],
+    to_parent_path: &[usize],
+    to_len: usize,
+    position: ChildAdditionPosition,
+) -> Result<usize, TreeOpError> {
+    let (&from_index, from_parent_path) =
+        from_path.split_last().ok_or(TreeOpError::RootHasNoParent)?;
+
+    let index = addition_index(to_parent_path, to_len, position)?;
+
+    if from_parent_path != to_parent_path {
+        return Ok(index);
+    }
+
+    match position {
+        ChildAdditionPosition::AddAt(_) if index <= from_index => Ok(index),
+        _ => Ok(index - 1),
+    }
+}
+
+/// Where the object at `path` ends up once the object at `removed_path` is taken out of the tree,
+/// `path` not being inside the removed subtree
+pub(crate) fn path_after_removal(path: &[usize], removed_path: &[usize]) -> Vec<usize> {
+    let mut new_path = path.to_vec();
+
+    if let Some((&removed_index, removed_parent_path)) = removed_path.split_last() {
+        let depth = removed_parent_path.len();
+
+        if new_path.len() > depth
+            && new_path.starts_with(removed_parent_path)
+            && new_path[depth] > removed_index
+        {
+            new_path[depth] -= 1;
+        }
+    }
+
+    new_path
+}
 
-    Some(())
+/// Objects rewritten by a move, ready to be written
+pub(crate) struct MovePlan {
+    /// Every rewritten object, the new root being the last one
+    pub(crate) objects: Vec<SyngObjectDef>,
+    pub(crate) root_id: String,
+
+    /// Path of the moved object in the new tree
+    pub(crate) new_path: Vec<usize>,
+}
+
+/// Works out the objects a move rewrites, from the objects along the path of the moved object
+/// (`from_objs`, root first) and along the path of its new parent (`to_objs`). The rewritten
+/// objects are hashed with `algorithm`, which should be the one of the backend they are written to.
+pub(crate) fn plan_move(
+    from_objs: Vec<(String, SyngObjectDef)>,
+    to_objs: Vec<(String, SyngObjectDef)>,
+    from_path: &[usize],
+    to_parent_path: &[usize],
+    position: ChildAdditionPosition,
+    algorithm: HashAlgorithm,
+) -> Result<MovePlan, TreeOpError> {
+    let (&from_index, from_parent_path) = from_path.split_last().unwrap();
+    let (moved_id, _) = from_objs.last().unwrap().clone();
+
+    // The objects along both paths (up to the old and new parent), keyed by their path in the tree
+    // before the move. Their common ancestors are only rewritten once.
+    let mut nodes: BTreeMap<Vec<usize>, SyngObjectDef> = BTreeMap::new();
+
+    for (depth, (_, obj)) in from_objs.into_iter().enumerate().take(from_path.len()) {
+        nodes.insert(from_path[..depth].to_vec(), obj);
+    }
+
+    for (depth, (_, obj)) in to_objs.into_iter().enumerate() {
+        nodes.entry(to_parent_path[..depth].to_vec()).or_insert(obj);
+    }
+
+    let to_len = nodes[to_parent_path].children.len();
+
+    let insert_index = move_insert_index(from_path, to_parent_path, to_len, position)?;
+
+    nodes
+        .get_mut(from_parent_path)
+        .unwrap()
+        .children
+        .remove(from_index);
+    nodes
+        .get_mut(to_parent_path)
+        .unwrap()
+        .children
+        .insert(insert_index, moved_id);
+
+    // Index of a child in its parent once the object was taken out and put in its new place
+    let index_after_move = |parent_path: &[usize], index: usize| {
+        let index = if parent_path == from_parent_path && index > from_index {
+            index - 1
+        } else {
+            index
+        };
+
+        if parent_path == to_parent_path && index >= insert_index {
+            index + 1
+        } else {
+            index
+        }
+    };
+
+    // Rewrite the deepest objects first, so every object is hashed after its children
+    let mut paths = nodes.keys().cloned().collect::<Vec<_>>();
+    paths.sort_by_key(|path| std::cmp::Reverse(path.len()));
+
+    let mut objects = Vec::with_capacity(paths.len());
+    let mut root_id = None;
+
+    for path in paths {
+        let obj = nodes.remove(&path).unwrap();
+        let id = obj.get_id(algorithm)?.to_string();
+
+        objects.push(obj);
+
+        match path.split_last() {
+            Some((&index, parent_path)) => {
+                let index = index_after_move(parent_path, index);
+
+                nodes.get_mut(parent_path).unwrap().children[index] = id;
+            }
+            None => root_id = Some(id),
+        }
+    }
+
+    let mut new_path = path_after_removal(to_parent_path, from_path);
+    new_path.push(insert_index);
+
+    Ok(MovePlan {
+        objects,
+        root_id: root_id.expect("the root is on both paths"),
+        new_path,
+    })
+}
+
+/// Moves the object at `from_path` into the children of the object at `to_parent_path`. The
+/// ancestors of both places are rewritten together, so the move is a single change of the root
+/// and the object can't get lost halfway.
+///
+/// Both paths and the position refer to the tree before the move, `AddAt(index)` puts the object
+/// in front of the child currently at `index`. Returns the path of the object in the new tree.
+pub fn move_child_object(
+    backend: &mut impl SyngBackend,
+    from_path: &[usize],
+    to_parent_path: &[usize],
+    position: ChildAdditionPosition,
+) -> Result<Vec<usize>, TreeOpError> {
+    move_child_object_with_options(
+        backend,
+        from_path,
+        to_parent_path,
+        position,
+        &TreeOpOptions::default(),
+    )
+}
+
+pub fn move_child_object_with_options(
+    backend: &mut impl SyngBackend,
+    from_path: &[usize],
+    to_parent_path: &[usize],
+    position: ChildAdditionPosition,
+    options: &TreeOpOptions,
+) -> Result<Vec<usize>, TreeOpError> {
+    check_move(from_path, to_parent_path)?;
+
+    let from_objs = get_objects_along_index_path(backend, from_path)?;
+    let to_objs = get_objects_along_index_path(backend, to_parent_path)?;
+
+    let plan = plan_move(
+        from_objs,
+        to_objs,
+        from_path,
+        to_parent_path,
+        position,
+        backend.hash_algorithm(),
+    )?;
+
+    backend.write_objects(&plan.objects)?;
+
+    set_new_root(backend, &plan.root_id, options)?;
+
+    Ok(plan.new_path)
+}
+
+/// Same as [`update_object`], with the object addressed by its node ID
+pub fn update_object_by_node_id(
+    backend: &mut impl SyngBackend,
+    node_id: &str,
+    new_def: &SyngObjectDef,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    update_object_by_node_id_with_options(backend, node_id, new_def, &TreeOpOptions::default())
+}
+
+pub fn update_object_by_node_id_with_options(
+    backend: &mut impl SyngBackend,
+    node_id: &str,
+    new_def: &SyngObjectDef,
+    options: &TreeOpOptions,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let obj_path = node_path(backend, node_id)?;
+
+    update_object_with_options(backend, &obj_path, new_def, options)
+}
+
+/// Same as [`add_child_object`], with the parent addressed by its node ID
+pub fn add_child_object_by_node_id(
+    backend: &mut impl SyngBackend,
+    parent_node_id: &str,
+    new_def: &SyngObjectDef,
+    position: ChildAdditionPosition,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    add_child_object_by_node_id_with_options(
+        backend,
+        parent_node_id,
+        new_def,
+        position,
+        &TreeOpOptions::default(),
+    )
+}
+
+pub fn add_child_object_by_node_id_with_options(
+    backend: &mut impl SyngBackend,
+    parent_node_id: &str,
+    new_def: &SyngObjectDef,
+    position: ChildAdditionPosition,
+    options: &TreeOpOptions,
+) -> Result<(String, SyngObjectDef), TreeOpError> {
+    let parent_obj_path = node_path(backend, parent_node_id)?;
+
+    add_child_object_with_options(backend, &parent_obj_path, new_def, position, options)
+}
+
+/// Same as [`remove_child_object`], with the object addressed by its node ID
+pub fn remove_child_object_by_node_id(
+    backend: &mut impl SyngBackend,
+    node_id: &str,
+) -> Result<(), TreeOpError> {
+    remove_child_object_by_node_id_with_options(backend, node_id, &TreeOpOptions::default())
+}
+
+pub fn remove_child_object_by_node_id_with_options(
+    backend: &mut impl SyngBackend,
+    node_id: &str,
+    options: &TreeOpOptions,
+) -> Result<(), TreeOpError> {
+    let obj_path = node_path(backend, node_id)?;
+
+    remove_child_object_with_options(backend, &obj_path, options)
+}
+
+/// Same as [`move_child_object`], with the object and its new parent addressed by their node IDs
+pub fn move_child_object_by_node_id(
+    backend: &mut impl SyngBackend,
+    node_id: &str,
+    to_parent_node_id: &str,
+    position: ChildAdditionPosition,
+) -> Result<Vec<usize>, TreeOpError> {
+    move_child_object_by_node_id_with_options(
+        backend,
+        node_id,
+        to_parent_node_id,
+        position,
+        &TreeOpOptions::default(),
+    )
+}
+
+pub fn move_child_object_by_node_id_with_options(
+    backend: &mut impl SyngBackend,
+    node_id: &str,
+    to_parent_node_id: &str,
+    position: ChildAdditionPosition,
+    options: &TreeOpOptions,
+) -> Result<Vec<usize>, TreeOpError> {
+    let from_path = node_path(backend, node_id)?;
+    let to_parent_path = node_path(backend, to_parent_node_id)?;
+
+    move_child_object_with_options(backend, &from_path, &to_parent_path, position, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commit::history,
+        testing::{named, MemoryBackend},
+    };
+
+    fn move_in(
+        tree: &str,
+        from_path: &[usize],
+        to_parent_path: &[usize],
+        position: ChildAdditionPosition,
+    ) -> (Result<Vec<usize>, TreeOpError>, String) {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree(tree);
+
+        let result = move_child_object(&mut backend, from_path, to_parent_path, position);
+
+        (result, backend.describe_root())
+    }
+
+    #[test]
+    fn moves_forward_within_the_same_parent() {
+        let (path, tree) = move_in(
+            "root(a, b, c, d)",
+            &[0],
+            &[],
+            ChildAdditionPosition::AddAt(3),
+        );
+
+        assert_eq!(path, Ok(vec![2]));
+        assert_eq!(tree, "root(b, c, a, d)");
+    }
+
+    #[test]
+    fn moves_backward_within_the_same_parent() {
+        let (path, tree) = move_in(
+            "root(a, b, c, d)",
+            &[3],
+            &[],
+            ChildAdditionPosition::AddAt(1),
+        );
+
+        assert_eq!(path, Ok(vec![1]));
+        assert_eq!(tree, "root(a, d, b, c)");
+    }
+
+    #[test]
+    fn moving_next_to_itself_keeps_the_order() {
+        for index in [1, 2] {
+            let (path, tree) = move_in(
+                "root(a, b, c)",
+                &[1],
+                &[],
+                ChildAdditionPosition::AddAt(index),
+            );
+
+            assert_eq!(path, Ok(vec![1]));
+            assert_eq!(tree, "root(a, b, c)");
+        }
+    }
+
+    #[test]
+    fn moves_to_the_end() {
+        let (path, tree) = move_in("root(a, b, c)", &[0], &[], ChildAdditionPosition::AddToEnd);
+
+        assert_eq!(path, Ok(vec![2]));
+        assert_eq!(tree, "root(b, c, a)");
+
+        let (path, tree) = move_in(
+            "root(a(x), b(y))",
+            &[0, 0],
+            &[1],
+            ChildAdditionPosition::AddToEnd,
+        );
+
+        assert_eq!(path, Ok(vec![1, 1]));
+        assert_eq!(tree, "root(a, b(y, x))");
+    }
+
+    #[test]
+    fn moves_into_a_later_sibling() {
+        let (path, tree) = move_in("root(a, b(c))", &[0], &[1], ChildAdditionPosition::AddAt(0));
+
+        assert_eq!(path, Ok(vec![0, 0]));
+        assert_eq!(tree, "root(b(a, c))");
+    }
+
+    #[test]
+    fn moving_into_its_own_subtree_fails() {
+        for to_parent_path in [&[0][..], &[0, 0], &[0, 0, 0]] {
+            let (result, tree) = move_in(
+                "root(a(b(c)), d)",
+                &[0],
+                to_parent_path,
+                ChildAdditionPosition::AddToEnd,
+            );
+
+            assert_eq!(
+                result,
+                Err(TreeOpError::MoveIntoOwnSubtree {
+                    from_path: vec![0],
+                    to_parent_path: to_parent_path.to_vec(),
+                })
+            );
+            assert_eq!(tree, "root(a(b(c)), d)");
+        }
+    }
+
+    #[test]
+    fn moving_past_the_end_fails() {
+        let (result, tree) = move_in("root(a, b(c))", &[0], &[1], ChildAdditionPosition::AddAt(2));
+
+        assert_eq!(
+            result,
+            Err(TreeOpError::PositionOutOfRange {
+                parent_path: vec![1],
+                index: 2,
+                len: 1,
+            })
+        );
+        assert_eq!(tree, "root(a, b(c))");
+    }
+
+    #[test]
+    fn missing_objects_are_backend_errors() {
+        let mut backend = MemoryBackend::default();
+        let root = backend.set_tree("root(a, b(c))");
+        let b = backend.objects[&root].children[1].clone();
+        backend.objects.remove(&b);
+
+        let expected = TreeOpError::Backend(SyngError::ObjectNotFound(b.clone()));
+
+        assert_eq!(find_node_path(&backend, "missing"), Err(expected.clone()));
+        assert_eq!(get_descendent_object_ids(&backend, &root), Err(expected));
+    }
+
+    #[test]
+    fn node_id_operations_can_record_commits() {
+        let mut backend = MemoryBackend::default();
+        backend.set_tree("root(a)");
+
+        let node = named("node").with_new_node_id();
+        let node_id = node.node_id().unwrap().to_owned();
+        add_child_object(&mut backend, &[0], &node, ChildAdditionPosition::AddToEnd).unwrap();
+
+        let options = TreeOpOptions {
+            commit: Some(CommitInfo::new("test", "rename")),
+        };
+
+        update_object_by_node_id_with_options(&mut backend, &node_id, &named("renamed"), &options)
+            .unwrap();
+
+        let commits = history(&backend).unwrap();
+
+        assert_eq!(backend.describe_root(), "root(a(renamed))");
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].1.message, "rename");
+        assert_eq!(
+            Some(commits[0].1.tree.clone()),
+            backend.get_root_object_id().unwrap()
+        );
+    }
 }
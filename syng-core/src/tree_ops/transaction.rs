@@ -0,0 +1,542 @@
+//! Several tree operations applied together.
+//!
+//! Every function in [`crate::tree_ops`] rewrites the ancestors of the object it changes and moves
+//! the root, so a batch of changes leaves an intermediate root (and a copy of every ancestor)
+//! behind for each operation. A [`TreeTransaction`] collects the operations instead and applies
+//! them to an in-memory copy of the touched part of the tree, then writes each changed object once
+//! and moves the root a single time.
+
+use crate::{
+    backend::SyngBackend,
+    commit::record_commit,
+    error::SyngError,
+    objects::{HashAlgorithm, SyngObjectDef},
+};
+
+use super::{
+    addition_index, check_move, keep_node_id, move_insert_index, path_after_removal,
+    read_referred_object, ChildAdditionPosition, TreeOpError, TreeOpOptions,
+};
+
+#[derive(Clone, Debug)]
+pub(crate) enum TreeTxOp {
+    Update {
+        path: Vec<usize>,
+        def: SyngObjectDef,
+    },
+    Add {
+        parent_path: Vec<usize>,
+        def: SyngObjectDef,
+        position: ChildAdditionPosition,
+    },
+    Remove {
+        path: Vec<usize>,
+    },
+    Move {
+        from_path: Vec<usize>,
+        to_parent_path: Vec<usize>,
+        position: ChildAdditionPosition,
+    },
+}
+
+/// Builder of a batch of tree operations against a base root.
+///
+/// The operations work like their [`crate::tree_ops`] counterparts and are applied in order, so
+/// the paths of an operation refer to the tree as the previous operations left it. Committing
+/// fails if the root moved away from the base root in the meantime.
+#[derive(Clone, Debug)]
+pub struct TreeTransaction {
+    pub(crate) base_root: String,
+    pub(crate) operations: Vec<TreeTxOp>,
+}
+
+impl TreeTransaction {
+    pub fn new(base_root: &str) -> Self {
+        Self {
+            base_root: base_root.to_owned(),
+            operations: vec![],
+        }
+    }
+
+    pub fn base_root(&self) -> &str {
+        &self.base_root
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// See [`super::update_object`]
+    pub fn update_object(mut self, obj_path: &[usize], new_def: &SyngObjectDef) -> Self {
+        self.operations.push(TreeTxOp::Update {
+            path: obj_path.to_vec(),
+            def: new_def.clone(),
+        });
+
+        self
+    }
+
+    /// See [`super::add_child_object`]
+    pub fn add_child_object(
+        mut self,
+        parent_obj_path: &[usize],
+        new_def: &SyngObjectDef,
+        position: ChildAdditionPosition,
+    ) -> Self {
+        self.operations.push(TreeTxOp::Add {
+            parent_path: parent_obj_path.to_vec(),
+            def: new_def.clone(),
+            position,
+        });
+
+        self
+    }
+
+    /// See [`super::remove_child_object`]
+    pub fn remove_child_object(mut self, obj_path: &[usize]) -> Self {
+        self.operations.push(TreeTxOp::Remove {
+            path: obj_path.to_vec(),
+        });
+
+        self
+    }
+
+    /// See [`super::move_child_object`], the paths and position refer to the tree before this move
+    pub fn move_child_object(
+        mut self,
+        from_path: &[usize],
+        to_parent_path: &[usize],
+        position: ChildAdditionPosition,
+    ) -> Self {
+        self.operations.push(TreeTxOp::Move {
+            from_path: from_path.to_vec(),
+            to_parent_path: to_parent_path.to_vec(),
+            position,
+        });
+
+        self
+    }
+
+    /// Applies the operations and moves the root to the result, returning the new root. Nothing is
+    /// written if an operation fails, with the error its [`crate::tree_ops`] counterpart would
+    /// return.
+    pub fn commit(self, backend: &mut impl SyngBackend) -> Result<String, TreeOpError> {
+        self.commit_with_options(backend, &TreeOpOptions::default())
+    }
+
+    pub fn commit_with_options(
+        self,
+        backend: &mut impl SyngBackend,
+        options: &TreeOpOptions,
+    ) -> Result<String, TreeOpError> {
+        let mut tree = TxTree::new(&self.base_root);
+
+        for op in &self.operations {
+            loop {
+                match tree.apply(op) {
+                    ApplyStep::Read { path, id } => {
+                        let obj = read_referred_object(backend, &id)?;
+
+                        tree.load(&path, obj);
+                    }
+                    ApplyStep::Done => break,
+                    ApplyStep::Failed(e) => return Err(e),
+                }
+            }
+        }
+
+        let (root_id, objects) = tree.into_objects(backend.hash_algorithm())?;
+
+        backend.write_objects(&objects)?;
+
+        if !backend.compare_and_set_root(Some(&self.base_root), &root_id)? {
+            return Err(TreeOpError::RootMoved {
+                expected: self.base_root,
+                actual: backend.get_root_object_id()?,
+            });
+        }
+
+        if let Some(info) = &options.commit {
+            record_commit(backend, &root_id, info)?;
+        }
+
+        Ok(root_id)
+    }
+}
+
+/// What applying an operation needs next
+pub(crate) enum ApplyStep {
+    /// The object at `path` has to be read and given to [`TxTree::load`] first
+    Read {
+        path: Vec<usize>,
+        id: String,
+    },
+
+    Done,
+
+    /// The operation can't be applied to the tree
+    Failed(TreeOpError),
+}
+
+enum TxNode {
+    /// A subtree that wasn't touched, only known by its ID
+    Stored(String),
+
+    Loaded(Box<LoadedNode>),
+}
+
+struct LoadedNode {
+    /// The object without its children, which are in `children`
+    obj: SyngObjectDef,
+    children: Vec<TxNode>,
+
+    /// ID of the object as long as it wasn't changed
+    id: Option<String>,
+}
+
+impl LoadedNode {
+    fn new(mut obj: SyngObjectDef, id: Option<String>) -> Self {
+        let children = std::mem::take(&mut obj.children)
+            .into_iter()
+            .map(TxNode::Stored)
+            .collect();
+
+        Self { obj, children, id }
+    }
+}
+
+impl TxNode {
+    fn loaded_mut(&mut self) -> &mut LoadedNode {
+        match self {
+            TxNode::Loaded(node) => node,
+            TxNode::Stored(id) => unreachable!("object {} was not loaded", id),
+        }
+    }
+}
+
+/// The part of the tree the operations of a transaction touched, read from the backend as needed
+pub(crate) struct TxTree {
+    root: TxNode,
+}
+
+impl TxTree {
+    pub(crate) fn new(base_root: &str) -> Self {
+        Self {
+            root: TxNode::Stored(base_root.to_owned()),
+        }
+    }
+
+    /// Checks that the objects along `path` (including the last one) are loaded
+    fn resolve(&self, path: &[usize]) -> ApplyStep {
+        let mut node = &self.root;
+
+        for depth in 0..=path.len() {
+            let loaded = match node {
+                TxNode::Stored(id) => {
+                    return ApplyStep::Read {
+                        path: path[..depth].to_vec(),
+                        id: id.clone(),
+                    }
+                }
+                TxNode::Loaded(loaded) => loaded,
+            };
+
+            let Some(&index) = path.get(depth) else {
+                break;
+            };
+
+            let Some(child) = loaded.children.get(index) else {
+                return ApplyStep::Failed(TreeOpError::ChildNotFound {
+                    parent_path: path[..depth].to_vec(),
+                    index,
+                });
+            };
+
+            node = child;
+        }
+
+        ApplyStep::Done
+    }
+
+    fn resolve_all(&self, paths: &[&[usize]]) -> ApplyStep {
+        for path in paths {
+            match self.resolve(path) {
+                ApplyStep::Done => {}
+                step => return step,
+            }
+        }
+
+        ApplyStep::Done
+    }
+
+    /// Replaces the stored object at `path` (whose ancestors are loaded) with its loaded version
+    pub(crate) fn load(&mut self, path: &[usize], obj: SyngObjectDef) {
+        let mut node = &mut self.root;
+
+        for &index in path {
+            node = &mut node.loaded_mut().children[index];
+        }
+
+        if let TxNode::Stored(id) = node {
+            let id = id.clone();
+
+            *node = TxNode::Loaded(Box::new(LoadedNode::new(obj, Some(id))));
+        }
+    }
+
+    /// The loaded object at `path`, marking it and its ancestors as changed
+    fn changed_node(&mut self, path: &[usize]) -> &mut LoadedNode {
+        let mut node = self.root.loaded_mut();
+        node.id = None;
+
+        for &index in path {
+            node = node.children[index].loaded_mut();
+            node.id = None;
+        }
+
+        node
+    }
+
+    fn children_len(&mut self, path: &[usize]) -> usize {
+        let mut node = self.root.loaded_mut();
+
+        for &index in path {
+            node = node.children[index].loaded_mut();
+        }
+
+        node.children.len()
+    }
+
+    /// Applies the operation if everything it needs is loaded, otherwise returns what to read
+    /// first. The tree is only changed when [`ApplyStep::Done`] is returned.
+    pub(crate) fn apply(&mut self, op: &TreeTxOp) -> ApplyStep {
+        match op {
+            TreeTxOp::Update { path, def } => {
+                let step = self.resolve(path);
+
+                if !matches!(step, ApplyStep::Done) {
+                    return step;
+                }
+
+                let node = self.changed_node(path);
+                let def = keep_node_id(&node.obj, def);
+
+                *node = LoadedNode::new(def, None);
+            }
+            TreeTxOp::Add {
+                parent_path,
+                def,
+                position,
+            } => {
+                let step = self.resolve(parent_path);
+
+                if !matches!(step, ApplyStep::Done) {
+                    return step;
+                }
+
+                let len = self.children_len(parent_path);
+
+                let index = match addition_index(parent_path, len, *position) {
+                    Ok(index) => index,
+                    Err(e) => return ApplyStep::Failed(e),
+                };
+
+                let child = TxNode::Loaded(Box::new(LoadedNode::new(def.clone(), None)));
+
+                self.changed_node(parent_path).children.insert(index, child);
+            }
+            TreeTxOp::Remove { path } => {
+                let Some((&index, parent_path)) = path.split_last() else {
+                    return ApplyStep::Failed(TreeOpError::RootHasNoParent);
+                };
+
+                let step = self.resolve(path);
+
+                if !matches!(step, ApplyStep::Done) {
+                    return step;
+                }
+
+                self.changed_node(parent_path).children.remove(index);
+            }
+            TreeTxOp::Move {
+                from_path,
+                to_parent_path,
+                position,
+            } => {
+                if let Err(e) = check_move(from_path, to_parent_path) {
+                    return ApplyStep::Failed(e);
+                }
+
+                let (&from_index, from_parent_path) = from_path.split_last().unwrap();
+
+                let step = self.resolve_all(&[from_path, to_parent_path]);
+
+                if !matches!(step, ApplyStep::Done) {
+                    return step;
+                }
+
+                let to_len = self.children_len(to_parent_path);
+
+                let insert_index =
+                    match move_insert_index(from_path, to_parent_path, to_len, *position) {
+                        Ok(index) => index,
+                        Err(e) => return ApplyStep::Failed(e),
+                    };
+
+                let moved = self
+                    .changed_node(from_parent_path)
+                    .children
+                    .remove(from_index);
+
+                let new_parent_path = path_after_removal(to_parent_path, from_path);
+
+                self.changed_node(&new_parent_path)
+                    .children
+                    .insert(insert_index, moved);
+            }
+        }
+
+        ApplyStep::Done
+    }
+
+    /// The ID of the new root and the changed objects to write, children before their parents.
+    /// The changed objects are hashed with `algorithm`.
+    pub(crate) fn into_objects(
+        self,
+        algorithm: HashAlgorithm,
+    ) -> Result<(String, Vec<SyngObjectDef>), SyngError> {
+        let mut objects = vec![];
+        let root_id = collect_changed(self.root, algorithm, &mut objects)?;
+
+        Ok((root_id, objects))
+    }
+}
+
+fn collect_changed(
+    node: TxNode,
+    algorithm: HashAlgorithm,
+    objects: &mut Vec<SyngObjectDef>,
+) -> Result<String, SyngError> {
+    let node = match node {
+        TxNode::Stored(id) => return Ok(id),
+        TxNode::Loaded(node) => *node,
+    };
+
+    if let Some(id) = node.id {
+        return Ok(id);
+    }
+
+    let mut obj = node.obj;
+
+    obj.children = node
+        .children
+        .into_iter()
+        .map(|child| collect_changed(child, algorithm, objects))
+        .collect::<Result<_, _>>()?;
+
+    let id = obj.get_id(algorithm)?.to_string();
+
+    objects.push(obj);
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commit::{history, CommitInfo},
+        testing::{named, MemoryBackend},
+        tree_ops::update_object,
+    };
+
+    #[test]
+    fn commit_writes_each_changed_object_once_and_moves_the_root_once() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a(x), b, c)");
+        let objects_before = backend.objects.len();
+        let root_changes_before = backend.root_changes;
+
+        let new_root = TreeTransaction::new(&base)
+            .update_object(&[0, 0], &named("x2"))
+            .add_child_object(&[0], &named("y"), ChildAdditionPosition::AddToEnd)
+            .update_object(&[1], &named("b2"))
+            .remove_child_object(&[2])
+            .move_child_object(&[0, 1], &[], ChildAdditionPosition::AddAt(0))
+            .commit(&mut backend)
+            .unwrap();
+
+        assert_eq!(
+            backend.get_root_object_id().unwrap(),
+            Some(new_root.clone())
+        );
+        assert_eq!(backend.describe(&new_root), "root(y, a(x2), b2)");
+        assert_eq!(backend.root_changes, root_changes_before + 1);
+
+        // x2, y, b2 and the rewritten a and root, without any intermediate version of them
+        assert_eq!(backend.objects.len(), objects_before + 5);
+    }
+
+    #[test]
+    fn commit_fails_if_the_root_moved_since_the_base() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a, b)");
+
+        let transaction = TreeTransaction::new(&base).update_object(&[0], &named("ours"));
+
+        // Another writer changes the tree in the meantime
+        update_object(&mut backend, &[1], &named("theirs")).unwrap();
+        let concurrent_root = backend.get_root_object_id().unwrap();
+
+        assert_eq!(
+            transaction.commit(&mut backend),
+            Err(TreeOpError::RootMoved {
+                expected: base,
+                actual: concurrent_root.clone(),
+            })
+        );
+        assert_eq!(backend.get_root_object_id().unwrap(), concurrent_root);
+        assert_eq!(backend.describe_root(), "root(a, theirs)");
+    }
+
+    #[test]
+    fn failed_operation_leaves_the_root_alone() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a, b)");
+
+        let result = TreeTransaction::new(&base)
+            .update_object(&[0], &named("a2"))
+            .remove_child_object(&[5])
+            .commit(&mut backend);
+
+        assert_eq!(
+            result,
+            Err(TreeOpError::ChildNotFound {
+                parent_path: vec![],
+                index: 5,
+            })
+        );
+        assert_eq!(backend.get_root_object_id().unwrap(), Some(base));
+    }
+
+    #[test]
+    fn commit_with_options_records_a_single_commit() {
+        let mut backend = MemoryBackend::default();
+        let base = backend.set_tree("root(a, b)");
+
+        let options = TreeOpOptions {
+            commit: Some(CommitInfo::new("test", "batch")),
+        };
+
+        let new_root = TreeTransaction::new(&base)
+            .update_object(&[0], &named("a2"))
+            .update_object(&[1], &named("b2"))
+            .commit_with_options(&mut backend, &options)
+            .unwrap();
+
+        let history = history(&backend).unwrap();
+
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].1.tree, new_root);
+    }
+}
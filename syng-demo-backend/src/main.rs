@@ -1,18 +1,30 @@
-use anyhow::{bail, Result};
-use std::{collections::{HashMap, BTreeMap}, sync::RwLock, time::SystemTime};
+use std::{collections::{HashMap, BTreeMap}, fmt::{Display, Formatter}, sync::{PoisonError, RwLock}, time::SystemTime};
 
-use actix_web::{get, middleware::Logger, web, App, HttpServer, Responder, post};
+use actix_web::{
+    error::BlockingError, get, http::{header, StatusCode}, middleware::Logger, post, web, App, HttpRequest,
+    HttpResponse, HttpServer, Responder, ResponseError,
+};
 use syng::{
-    backend::SyngBackend, delta::{generate_delta_from_point, SyngDelta, apply_delta}, objects::SyngObjectDef,
-    tree_ops::get_descendent_objects,
+    backend::{sqlite::SqliteBackend, SyngBackend},
+    delta::{apply_delta, generate_delta_from_point, SyngDelta},
+    error::SyngError, fsck::fsck, gc::gc, objects::{HashAlgorithm, SyngObjectDef},
+    tree_ops::{get_descendent_objects, TreeOpError},
 };
 use syng_demo_common::backend::{
-    BackendCurrRootResult, BackendFullPullResult, BackendPullFromResult, BackendPullFromError,
-    BackendPushResult, BackendPushError
+    BackendCurrRootResult, BackendFsckResult, BackendFullPullResult, BackendGcResult,
+    BackendPullFromResult, BackendPullFromError, BackendPushResult, BackendPushError
 };
 
 struct DataBackend {
     objects: HashMap<String, SyngObjectDef>,
+    root_object_id: Option<String>,
+    transaction: Option<DataTransaction>
+}
+
+/// Changes made since a transaction began, so that they can be rolled back
+struct DataTransaction {
+    written_object_ids: Vec<String>,
+    deleted_objects: HashMap<String, SyngObjectDef>,
     root_object_id: Option<String>
 }
 
@@ -31,34 +43,52 @@ impl Default for DataBackend {
 
         Self {
             objects,
-            root_object_id: Some(root_hash)
+            root_object_id: Some(root_hash),
+            transaction: None
         }
     }
 }
 
+/// Set to the path of an SQLite database to store the data there instead of in memory
+const DB_PATH_ENV: &str = "SYNG_DB";
+
+/// Environment variable holding the token the `/admin` routes are called with, as an
+/// `Authorization: Bearer <token>` header. The admin routes refuse every call when it isn't set.
+const ADMIN_TOKEN_ENV: &str = "SYNG_ADMIN_TOKEN";
+
+/// The in-memory backend is handy for trying things out, the SQLite one keeps the data across
+/// restarts
+enum ServerBackend {
+    Memory(DataBackend),
+    Sqlite(SqliteBackend)
+}
+
 struct BackendState {
-    data: RwLock<DataBackend>
+    // Only locked on the blocking thread pool, see `with_backend`
+    data: RwLock<ServerBackend>,
+
+    admin_token: Option<String>
 }
 
 impl SyngBackend for DataBackend {
-    fn get_root_object_id(&self) -> Option<String> {
-        return self.root_object_id.clone();
+    fn get_root_object_id(&self) -> Result<Option<String>, SyngError> {
+        Ok(self.root_object_id.clone())
     }
 
-    fn get_root_object(&self) -> Option<SyngObjectDef> {
-        let root_id = self.get_root_object_id()?;
+    fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError> {
+        let Some(root_id) = &self.root_object_id else {
+            return Ok(None);
+        };
 
-        Some(
-            self.objects
-                .get(&root_id)
-                .expect("Root ID is set to a value not in the object list")
-                .clone(),
-        )
+        match self.objects.get(root_id) {
+            Some(obj) => Ok(Some(obj.clone())),
+            None => Err(SyngError::ObjectNotFound(root_id.clone())),
+        }
     }
 
-    fn set_root_object(&mut self, node_id: &str) -> Result<()> {
-        if !self.has_object(node_id) {
-            bail!("INVALID_OBJ_ID");
+    fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError> {
+        if !self.objects.contains_key(node_id) {
+            return Err(SyngError::ObjectNotFound(node_id.to_owned()));
         }
 
         self.root_object_id = Some(node_id.to_owned());
@@ -68,111 +98,510 @@ impl SyngBackend for DataBackend {
         Ok(())
     }
 
-    fn read_object(&self, id: &str) -> Option<SyngObjectDef> {
+    fn compare_and_set_root(&mut self, expected: Option<&str>, new: &str) -> Result<bool, SyngError> {
+        if !self.objects.contains_key(new) {
+            return Err(SyngError::ObjectNotFound(new.to_owned()));
+        }
+
+        if self.root_object_id.as_deref() != expected {
+            println!("Root Object CAS failed, expected {:?} but was {:?}", expected, self.root_object_id);
+
+            return Ok(false);
+        }
+
+        self.root_object_id = Some(new.to_owned());
+
+        println!("Root Object set to {:?}", self.root_object_id);
+
+        Ok(true)
+    }
+
+    fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError> {
         println!("Object Read: {}", id);
 
-        Some(self.objects.get(id)?.clone())
+        Ok(self.objects.get(id).cloned())
     }
 
-    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String> {
+    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError> {
         let hash = def.get_hash()?;
         println!("Object Write: [Hash: {}] {:?}", hash, def);
 
+        if let Some(transaction) = &mut self.transaction {
+            if !self.objects.contains_key(&hash) {
+                transaction.written_object_ids.push(hash.clone());
+            }
+        }
+
         self.objects.insert(hash.clone(), def.clone());
 
         Ok(hash)
     }
+
+    fn write_objects_with_ids(&mut self, objects: &[(&str, &SyngObjectDef)]) -> Result<(), SyngError> {
+        for (id, def) in objects {
+            println!("Object Write: [Hash: {}] {:?}", id, def);
+
+            if let Some(transaction) = &mut self.transaction {
+                if !self.objects.contains_key(*id) {
+                    transaction.written_object_ids.push(id.to_string());
+                }
+            }
+
+            self.objects.insert(id.to_string(), (*def).clone());
+        }
+
+        Ok(())
+    }
+
+    fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        Ok(self.objects.keys().cloned().collect())
+    }
+
+    fn delete_object(&mut self, id: &str) -> Result<bool, SyngError> {
+        let Some(obj) = self.objects.remove(id) else {
+            return Ok(false);
+        };
+
+        println!("Object Delete: {}", id);
+
+        if let Some(transaction) = &mut self.transaction {
+            transaction.deleted_objects.insert(id.to_owned(), obj);
+        }
+
+        Ok(true)
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), SyngError> {
+        if self.transaction.is_some() {
+            return Err(SyngError::TransactionFailed("TRANSACTION_IN_PROGRESS".to_owned()));
+        }
+
+        self.transaction = Some(DataTransaction {
+            written_object_ids: vec![],
+            deleted_objects: HashMap::new(),
+            root_object_id: self.root_object_id.clone()
+        });
+
+        Ok(())
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), SyngError> {
+        if self.transaction.take().is_none() {
+            return Err(SyngError::TransactionFailed("NO_TRANSACTION".to_owned()));
+        }
+
+        Ok(())
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), SyngError> {
+        let Some(transaction) = self.transaction.take() else {
+            return Err(SyngError::TransactionFailed("NO_TRANSACTION".to_owned()));
+        };
+
+        for id in transaction.written_object_ids {
+            self.objects.remove(&id);
+        }
+
+        self.objects.extend(transaction.deleted_objects);
+
+        self.root_object_id = transaction.root_object_id;
+
+        println!("Transaction rolled back, root object set to {:?}", self.root_object_id);
+
+        Ok(())
+    }
 }
 
-impl DataBackend {
-    fn get_accesible_objects(&self) -> Option<Vec<SyngObjectDef>> {
+impl SyngBackend for ServerBackend {
+    fn has_object(&self, object_id: &str) -> Result<bool, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.has_object(object_id),
+            ServerBackend::Sqlite(b) => b.has_object(object_id),
+        }
+    }
+
+    fn get_root_object_id(&self) -> Result<Option<String>, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.get_root_object_id(),
+            ServerBackend::Sqlite(b) => b.get_root_object_id(),
+        }
+    }
+
+    fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.get_root_object(),
+            ServerBackend::Sqlite(b) => b.get_root_object(),
+        }
+    }
+
+    fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.set_root_object(node_id),
+            ServerBackend::Sqlite(b) => b.set_root_object(node_id),
+        }
+    }
+
+    fn compare_and_set_root(&mut self, expected: Option<&str>, new: &str) -> Result<bool, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.compare_and_set_root(expected, new),
+            ServerBackend::Sqlite(b) => b.compare_and_set_root(expected, new),
+        }
+    }
+
+    fn list_refs(&self) -> Result<BTreeMap<String, String>, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.list_refs(),
+            ServerBackend::Sqlite(b) => b.list_refs(),
+        }
+    }
 
-        Some(match &self.root_object_id {
-            None => vec![],
-            Some(id) => get_descendent_objects(self, id)?,
-        })
+    fn read_ref(&self, name: &str) -> Result<Option<String>, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.read_ref(name),
+            ServerBackend::Sqlite(b) => b.read_ref(name),
+        }
+    }
+
+    fn compare_and_set_ref(&mut self, name: &str, expected: Option<&str>, new: &str) -> Result<bool, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.compare_and_set_ref(name, expected, new),
+            ServerBackend::Sqlite(b) => b.compare_and_set_ref(name, expected, new),
+        }
+    }
+
+    fn delete_ref(&mut self, name: &str) -> Result<bool, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.delete_ref(name),
+            ServerBackend::Sqlite(b) => b.delete_ref(name),
+        }
+    }
+
+    fn hash_algorithm(&self) -> HashAlgorithm {
+        match self {
+            ServerBackend::Memory(b) => b.hash_algorithm(),
+            ServerBackend::Sqlite(b) => b.hash_algorithm(),
+        }
+    }
+
+    fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.read_object(id),
+            ServerBackend::Sqlite(b) => b.read_object(id),
+        }
+    }
+
+    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.write_object(def),
+            ServerBackend::Sqlite(b) => b.write_object(def),
+        }
+    }
+
+    fn read_objects(&self, ids: &[&str]) -> Result<Vec<Option<SyngObjectDef>>, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.read_objects(ids),
+            ServerBackend::Sqlite(b) => b.read_objects(ids),
+        }
+    }
+
+    fn write_objects(&mut self, defs: &[SyngObjectDef]) -> Result<Vec<String>, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.write_objects(defs),
+            ServerBackend::Sqlite(b) => b.write_objects(defs),
+        }
+    }
+
+    fn write_objects_with_ids(&mut self, objects: &[(&str, &SyngObjectDef)]) -> Result<(), SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.write_objects_with_ids(objects),
+            ServerBackend::Sqlite(b) => b.write_objects_with_ids(objects),
+        }
+    }
+
+    fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.list_objects(),
+            ServerBackend::Sqlite(b) => b.list_objects(),
+        }
+    }
+
+    fn delete_object(&mut self, id: &str) -> Result<bool, SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.delete_object(id),
+            ServerBackend::Sqlite(b) => b.delete_object(id),
+        }
+    }
+
+    fn begin_transaction(&mut self) -> Result<(), SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.begin_transaction(),
+            ServerBackend::Sqlite(b) => b.begin_transaction(),
+        }
+    }
+
+    fn commit_transaction(&mut self) -> Result<(), SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.commit_transaction(),
+            ServerBackend::Sqlite(b) => b.commit_transaction(),
+        }
+    }
+
+    fn rollback_transaction(&mut self) -> Result<(), SyngError> {
+        match self {
+            ServerBackend::Memory(b) => b.rollback_transaction(),
+            ServerBackend::Sqlite(b) => b.rollback_transaction(),
+        }
+    }
+}
+
+/// Opens the SQLite database if one is configured, giving it the same empty root as the
+/// in-memory backend when it is new
+fn open_backend() -> Result<ServerBackend, SyngError> {
+    let Ok(db_path) = std::env::var(DB_PATH_ENV) else {
+        return Ok(ServerBackend::Memory(DataBackend::default()));
+    };
+
+    let mut backend = SqliteBackend::open(&db_path)?;
+
+    if backend.get_root_object_id()?.is_none() {
+        let root_hash = backend.write_object(&SyngObjectDef {
+            fields: BTreeMap::new(),
+            children: vec![],
+        })?;
+
+        backend.set_root_object(&root_hash)?;
+    }
+
+    println!("Using SQLite database at {}", db_path);
+
+    Ok(ServerBackend::Sqlite(backend))
+}
+
+/// Errors returned from the handlers as HTTP errors
+#[derive(Debug)]
+enum ApiError {
+    Backend(SyngError),
+    TreeOp(TreeOpError),
+
+    /// The blocking thread pool couldn't run the request, or it panicked there
+    Blocking,
+
+    /// An admin route was called without the admin token
+    Unauthorized
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Backend(e) => e.fmt(f),
+            ApiError::TreeOp(e) => e.fmt(f),
+            ApiError::Blocking => write!(f, "the request could not be run"),
+            ApiError::Unauthorized => write!(f, "missing or wrong admin token"),
+        }
     }
 }
 
+impl From<SyngError> for ApiError {
+    fn from(value: SyngError) -> Self {
+        ApiError::Backend(value)
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(_: BlockingError) -> Self {
+        ApiError::Blocking
+    }
+}
+
+impl From<TreeOpError> for ApiError {
+    fn from(value: TreeOpError) -> Self {
+        match value {
+            TreeOpError::Backend(e) => ApiError::Backend(e),
+            e => ApiError::TreeOp(e),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::Backend(SyngError::ObjectNotFound(_) | SyngError::NoRootObject) => StatusCode::NOT_FOUND,
+            ApiError::Backend(_) | ApiError::TreeOp(_) | ApiError::Blocking => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ApiError::Backend(e) => HttpResponse::build(self.status_code()).json(e),
+            ApiError::TreeOp(e) => HttpResponse::build(self.status_code()).json(e),
+            ApiError::Blocking | ApiError::Unauthorized => HttpResponse::build(self.status_code()).body(self.to_string()),
+        }
+    }
+}
+
+/// Runs `f` with the backend on the blocking thread pool, since the SQLite backend waits on disk I/O
+/// and would stall the async workers otherwise
+async fn with_backend<T, F>(state: &web::Data<BackendState>, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&ServerBackend) -> Result<T, ApiError> + Send + 'static,
+{
+    let state = state.clone();
+
+    web::block(move || f(&state.data.read().unwrap_or_else(PoisonError::into_inner))).await?
+}
+
+/// Same as [`with_backend`], with the write lock held
+async fn with_backend_mut<T, F>(state: &web::Data<BackendState>, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut ServerBackend) -> Result<T, ApiError> + Send + 'static,
+{
+    let state = state.clone();
+
+    web::block(move || f(&mut state.data.write().unwrap_or_else(PoisonError::into_inner))).await?
+}
+
+fn get_accesible_objects(backend: &impl SyngBackend) -> Result<Vec<SyngObjectDef>, TreeOpError> {
+    Ok(match backend.get_root_object_id()? {
+        None => vec![],
+        Some(id) => get_descendent_objects(backend, &id)?,
+    })
+}
+
 #[get("/curr_root")]
-async fn curr_root(state: web::Data<BackendState>) -> impl Responder {
-    let result = state.data.read().unwrap().get_root_object_id();
+async fn curr_root(state: web::Data<BackendState>) -> Result<impl Responder, ApiError> {
+    let result = with_backend(&state, |backend| Ok(backend.get_root_object_id()?)).await?;
 
-    web::Json(BackendCurrRootResult { data: result })
+    Ok(web::Json(BackendCurrRootResult { data: result }))
 }
 
 #[get("/pull")]
-async fn pull(state: web::Data<BackendState>) -> Option<impl Responder> {
-    let backend = state.data.read().unwrap();
-
+async fn pull(state: web::Data<BackendState>) -> Result<impl Responder, ApiError> {
     let time_start = SystemTime::now();
 
-    let root_id = backend.get_root_object_id();
-    let accessible_objects = backend.get_accesible_objects()?;
+    let (root_id, accessible_objects) = with_backend(&state, |backend| {
+        Ok((backend.get_root_object_id()?, get_accesible_objects(backend)?))
+    }).await?;
 
     let time_end = SystemTime::now();
     let duration = time_end.duration_since(time_start).unwrap().as_millis();
 
     println!("Full pull took {}ms", duration);
 
-    Some(web::Json(BackendFullPullResult {
+    Ok(web::Json(BackendFullPullResult {
         root_obj_id: root_id,
         objects: accessible_objects,
     }))
 }
 
 #[get("/pull_from/{hash}")]
-async fn pull_from(hash: web::Path<String>, state: web::Data<BackendState>) -> impl Responder {
-    let backend = state.data.read().unwrap();
+async fn pull_from(hash: web::Path<String>, state: web::Data<BackendState>) -> Result<impl Responder, ApiError> {
+    let hash = hash.into_inner();
 
     let time_start = SystemTime::now();
 
-    if backend.get_root_object_id().is_none() {
-        return web::Json(BackendPullFromResult {
-            data: Err(BackendPullFromError::BackendHasNoRoot),
-        });
-    } else if !backend.has_object(&hash) {
-        return web::Json(BackendPullFromResult {
-            data: Err(BackendPullFromError::InvalidFromPoint),
-        });
-    }
+    let data = with_backend(&state, {
+        let hash = hash.clone();
+
+        move |backend| {
+            if backend.get_root_object_id()?.is_none() {
+                return Ok(Err(BackendPullFromError::BackendHasNoRoot));
+            } else if !backend.has_object(&hash)? {
+                return Ok(Err(BackendPullFromError::InvalidFromPoint));
+            }
+
+            Ok(generate_delta_from_point(backend, &hash).map_err(BackendPullFromError::DeltaGenError))
+        }
+    }).await?;
 
     let time_end = SystemTime::now();
     let duration = time_end.duration_since(time_start).unwrap().as_millis();
 
     println!("Pull from {} took {}ms", hash, duration);
 
-    let Some(delta) = generate_delta_from_point(&*backend, &hash) else { 
-        return web::Json(BackendPullFromResult { 
-            data: Err(BackendPullFromError::DeltaGenError)
-        })
-    };
-
-    web::Json(BackendPullFromResult {
-        data: Ok(delta)
-    })
+    Ok(web::Json(BackendPullFromResult { data }))
 }
 
 #[post("/push")]
-async fn push(delta: web::Json<SyngDelta>, state: web::Data<BackendState>) -> impl Responder {
-    let mut backend = state.data.write().unwrap();
+async fn push(delta: web::Json<SyngDelta>, state: web::Data<BackendState>) -> Result<impl Responder, ApiError> {
+    let delta = delta.into_inner();
 
-    let delta = apply_delta(&mut *backend, &delta);
+    let result = with_backend_mut(&state, move |backend| Ok(apply_delta(backend, &delta))).await?;
 
-    web::Json(
+    Ok(web::Json(
         BackendPushResult {
-            data: match delta {
+            data: match result {
                 Err(e) => Err(BackendPushError::DeltaApplyFailed(e)),
                 Ok(_) => Ok(())
             }
         }
-    )
+    ))
+}
+
+/// Compares without bailing out at the first differing byte, so the time taken doesn't tell how
+/// much of a guessed token was right
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Lets the request through if it carries the admin token, see [`ADMIN_TOKEN_ENV`]
+fn check_admin_token(state: &BackendState, req: &HttpRequest) -> Result<(), ApiError> {
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match (&state.admin_token, given) {
+        (Some(token), Some(given)) if tokens_match(token, given) => Ok(()),
+        _ => Err(ApiError::Unauthorized),
+    }
+}
+
+/// Deletes the objects no ref points to anymore, like the ones replaced by pushes
+#[post("/admin/gc")]
+async fn collect_garbage(req: HttpRequest, state: web::Data<BackendState>) -> Result<impl Responder, ApiError> {
+    check_admin_token(&state, &req)?;
+
+    let time_start = SystemTime::now();
+
+    let stats = with_backend_mut(&state, |backend| Ok(gc(backend, &[])?)).await?;
+
+    let time_end = SystemTime::now();
+    let duration = time_end.duration_since(time_start).unwrap().as_millis();
+
+    println!("GC took {}ms, deleted {} objects", duration, stats.deleted_objects);
+
+    Ok(web::Json(BackendGcResult { data: stats }))
+}
+
+/// Checks the store for missing, corrupted and unreachable objects
+#[get("/admin/fsck")]
+async fn check_store(req: HttpRequest, state: web::Data<BackendState>) -> Result<impl Responder, ApiError> {
+    check_admin_token(&state, &req)?;
+
+    let report = with_backend(&state, |backend| Ok(fsck(backend)?)).await?;
+
+    println!("Fsck checked {} objects, found {} issues", report.checked_objects, report.issues.len());
+
+    Ok(web::Json(BackendFsckResult { data: report }))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let backend = open_backend().map_err(std::io::Error::other)?;
+
+    let admin_token = std::env::var(ADMIN_TOKEN_ENV).ok().filter(|token| !token.is_empty());
+
+    if admin_token.is_none() {
+        println!("{} is not set, the admin routes are disabled", ADMIN_TOKEN_ENV);
+    }
+
     let app_state = web::Data::new(BackendState {
-        data: RwLock::new(DataBackend::default())
+        data: RwLock::new(backend),
+        admin_token
     });
 
     HttpServer::new(move || {
@@ -183,6 +612,8 @@ async fn main() -> std::io::Result<()> {
             .service(pull)
             .service(pull_from)
             .service(push)
+            .service(collect_garbage)
+            .service(check_store)
     })
     .bind(("127.0.0.1", 8080))?
     .run()
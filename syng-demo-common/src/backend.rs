@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 use syng::delta::{ApplyDeltaError, SyngDelta};
+use syng::error::SyngError;
+use syng::fsck::FsckReport;
+use syng::gc::GcStats;
 use syng::objects::SyngObjectDef;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -17,7 +20,7 @@ pub struct BackendFullPullResult {
 pub enum BackendPullFromError {
     BackendHasNoRoot,
     InvalidFromPoint,
-    DeltaGenError,
+    DeltaGenError(SyngError),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -34,3 +37,13 @@ pub enum BackendPushError {
 pub struct BackendPushResult {
     pub data: Result<(), BackendPushError>,
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackendGcResult {
+    pub data: GcStats,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BackendFsckResult {
+    pub data: FsckReport,
+}
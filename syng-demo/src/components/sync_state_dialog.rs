@@ -10,9 +10,6 @@ pub struct SyncStateDialogProps<'a> {
     show: bool,
     backend: DemoFEBackend,
 
-    #[props(!optional)]
-    last_synced_point: &'a Option<String>,
-
     on_close: EventHandler<'a>,
 }
 
@@ -34,7 +31,7 @@ pub fn SyncStateDialog<'a>(cx: Scope<'a, SyncStateDialogProps<'a>>) -> Element<'
 
                 button {
                     onclick: move |_| {
-                        let last_sync_point = cx.props.last_synced_point.to_owned().unwrap();
+                        let last_sync_point = cx.props.backend.get_last_synced_remote_root_id().unwrap();
                         let backend = cx.props.backend.to_owned();
 
                         cx.spawn(async move {
@@ -38,7 +38,6 @@ struct RemoteSyncLogItem {
 
 fn App(cx: Scope) -> Element {
     let last_known_remote_root_id = use_state(cx, || -> Option<String> { None });
-    let last_synced_remote_root_id = use_state(cx, || -> Option<String> { None });
 
     let remote_sync_log = use_ref(cx, || Vec::<RemoteSyncLogItem>::new());
 
@@ -57,6 +56,8 @@ fn App(cx: Scope) -> Element {
         .unwrap()
         .as_millis();
 
+    let last_synced_remote_root_id = backend.read().get_last_synced_remote_root_id();
+
     let tree_info = backend
         .read()
         .generate_gen_info()
@@ -67,8 +68,10 @@ fn App(cx: Scope) -> Element {
 
     let root_colls_len = root_colls.len();
 
-    let move_folder_from_path = use_state(cx, || -> Option<Vec<usize>> { None });
-    let move_req_from_path = use_state(cx, || -> Option<(Vec<usize>, usize)> { None });
+    // Node IDs rather than paths, so that changes to the tree while the dialogs are open don't
+    // move the wrong node
+    let move_folder_node_id = use_state(cx, || -> Option<String> { None });
+    let move_req_node_id = use_state(cx, || -> Option<String> { None });
 
     let show_sync_state_dialog = use_state(cx, || { false });
 
@@ -99,11 +102,11 @@ fn App(cx: Scope) -> Element {
                 on_delete_request: move |(path, index): (Vec<usize>, usize)| {
                     backend.write().delete_request(&path, index).expect("Delete request failed");
                 },
-                on_move_folder: move |path| {
-                    move_folder_from_path.set(Some(path));
+                on_move_folder: move |path: Vec<usize>| {
+                    move_folder_node_id.set(backend.read().get_node_id(&path));
                 },
-                on_move_request: move |path| {
-                    move_req_from_path.set(Some(path));
+                on_move_request: move |(path, index): (Vec<usize>, usize)| {
+                    move_req_node_id.set(backend.read().get_request_node_id(&path, index));
                 }
             }
         }
@@ -116,51 +119,50 @@ fn App(cx: Scope) -> Element {
             SyncStateDialog {
                 show: **show_sync_state_dialog,
                 backend: backend.read().clone(),
-                last_synced_point: &last_synced_remote_root_id,
                 on_close: move |()| {
                     show_sync_state_dialog.set(false)
                 }
             }
 
             PromptDialog {
-                show: move_folder_from_path.is_some(),
+                show: move_folder_node_id.is_some(),
                 dialog_title: "Move Folder",
                 message: "Use slash separated path indices",
                 placeholder: "Path",
 
                 on_ok: move |to_path_str: String| {
                     let to_obj_path = to_path_str.split('/').map(|s| s.parse::<usize>().unwrap()).collect::<Vec<_>>();
-                    let from_folder_path = move_folder_from_path.get().clone().unwrap();
+                    let folder_node_id = move_folder_node_id.get().clone().unwrap();
 
                     backend.with_mut(move |bk| {
-                        bk.move_folder(from_folder_path.as_slice(), to_obj_path.as_slice()).expect("Move failed");
+                        bk.move_folder_by_node_id(&folder_node_id, to_obj_path.as_slice()).expect("Move failed");
                     });
 
-                    move_folder_from_path.set(None);
+                    move_folder_node_id.set(None);
                 },
                 on_cancel: move |_| {
-                    move_folder_from_path.set(None);
+                    move_folder_node_id.set(None);
                 }
             }
 
             PromptDialog {
-                show: move_req_from_path.is_some(),
+                show: move_req_node_id.is_some(),
                 dialog_title: "Move Request",
                 message: "Use slash separated path indices",
                 placeholder: "Path",
 
                 on_ok: move |to_path_str: String| {
                     let to_obj_path = to_path_str.split('/').map(|s| s.parse::<usize>().unwrap()).collect::<Vec<_>>();
-                    let from_req_path = move_req_from_path.get().clone().unwrap();
+                    let req_node_id = move_req_node_id.get().clone().unwrap();
 
                     backend.with_mut(move |bk| {
-                        bk.move_request((from_req_path.0.as_slice(), from_req_path.1), to_obj_path.as_slice()).expect("Move failed");
+                        bk.move_request_by_node_id(&req_node_id, to_obj_path.as_slice()).expect("Move failed");
                     });
 
-                    move_req_from_path.set(None);
+                    move_req_node_id.set(None);
                 },
                 on_cancel: move |_| {
-                    move_req_from_path.set(None);
+                    move_req_node_id.set(None);
                 }
             }
 
@@ -176,14 +178,41 @@ fn App(cx: Scope) -> Element {
 
                     br {}
 
+                    button {
+                        onclick: move |_| {
+                            let stats = backend.with_mut(|bk| {
+                                bk.drop_unreachable_objects(&*last_known_remote_root_id).expect("Drop failed")
+                            });
+
+                            remote_sync_log.with_mut(|log| {
+                                log.push(RemoteSyncLogItem {
+                                    op: "Drop Unreachable".to_owned(),
+                                    result: serde_json::to_string_pretty(&stats).unwrap()
+                                });
+                            });
+                        },
+
+                        "Drop Unreachable"
+                    }
+
                     button {
                         onclick: move |_| {
                             backend.with_mut(|bk| {
-                                bk.drop_unreachable_objects(&*last_known_remote_root_id).expect("Drop failed");
+                                bk.undo().expect("Undo failed");
                             })
                         },
 
-                        "Drop Unreachable"
+                        "Undo"
+                    }
+
+                    button {
+                        onclick: move |_| {
+                            backend.with_mut(|bk| {
+                                bk.redo().expect("Redo failed");
+                            })
+                        },
+
+                        "Redo"
                     }
 
                     br {}
@@ -207,7 +236,7 @@ fn App(cx: Scope) -> Element {
 
                     br {}
 
-                    "Last Synced Remote ID: {*last_synced_remote_root_id:?}"
+                    "Last Synced Remote ID: {last_synced_remote_root_id:?}"
 
                     br {}
 
@@ -238,8 +267,7 @@ fn App(cx: Scope) -> Element {
 
                     button {
                         onclick: move |_| {
-                            let ls_remote_root_id = last_synced_remote_root_id.clone();
-                            let lk_remote_root_id = last_synced_remote_root_id.clone();
+                            let lk_remote_root_id = last_known_remote_root_id.clone();
 
                             cx.spawn({
                                 let back = backend.to_owned();
@@ -249,11 +277,10 @@ fn App(cx: Scope) -> Element {
                                     let result = pull_full_from_remote().await.expect("Pull failed");
 
                                     back.with_mut(|bk| {
+                                        // Also moves the last synced remote ref to the pulled root
                                         bk.apply_full_pull(&result).expect("Pull write failed");
 
-                                        let root_id = bk.get_root_object_id().clone();
-                                        lk_remote_root_id.set(root_id.clone());
-                                        ls_remote_root_id.set(root_id);
+                                        lk_remote_root_id.set(result.root_obj_id.clone());
                                     });
 
                                     log.with_mut(|log| {
@@ -272,11 +299,11 @@ fn App(cx: Scope) -> Element {
                     button {
                         onclick: move |_| {
                             let back = backend.clone();
-                            let last_sync_point = last_synced_remote_root_id.clone();
                             let last_known_bk_point = last_known_remote_root_id.clone();
                             let log = remote_sync_log.to_owned();
 
-                            let delta = backend.read().get_delta_for_pushing(&last_sync_point.get().clone().unwrap()).unwrap();
+                            let last_sync_point = backend.read().get_last_synced_remote_root_id().unwrap();
+                            let delta = backend.read().get_delta_for_pushing(&last_sync_point).unwrap();
 
                             cx.spawn({
                                 async move {
@@ -289,9 +316,11 @@ fn App(cx: Scope) -> Element {
                                         });
                                     });
 
-                                    let curr_root = back.read().get_root_object_id();
-                                    last_sync_point.set(curr_root.clone());
-                                    last_known_bk_point.set(curr_root);
+                                    // The remote is at the pushed root now
+                                    back.with_mut(|bk| {
+                                        bk.mark_synced(&delta.new_root_node).expect("Sync point update failed");
+                                    });
+                                    last_known_bk_point.set(Some(delta.new_root_node.clone()));
                                 }
                             })
                         },
@@ -301,7 +330,9 @@ fn App(cx: Scope) -> Element {
 
                     button {
                         onclick: move |_| {
-                            // Revert to last pull
+                            backend.with_mut(|bk| {
+                                bk.revert_to_last_sync().expect("Revert failed");
+                            })
                         },
 
                         "Revert to last pull"
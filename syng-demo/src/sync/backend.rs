@@ -1,58 +1,133 @@
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use syng_demo_common::{backend::BackendFullPullResult, CollectionData, RequestData};
 
-use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
 use syng::{
-    backend::SyngBackend,
+    backend::{is_valid_ref_name, SyngBackend, DEFAULT_REF},
+    commit::{commit_root, log, read_commit, CommitInfo, HISTORY_REF},
     delta::{generate_delta_from_point, SyngDelta},
-    objects::SyngObjectDef,
+    error::SyngError,
+    gc::{gc, GcStats},
+    journal::{OpJournal, TreeOperation},
+    objects::{SyngObjectDef, SyngValue},
     tree_ops::{
-        add_child_object, get_descendent_object_ids, get_object_at_path, remove_child_object,
-        update_object, ChildAdditionPosition,
+        add_child_object, find_node_path, get_object_at_path, move_child_object,
+        remove_child_object, ChildAdditionPosition,
     },
 };
 
 use super::treegen::{generate_object_for_coll, generate_object_for_req, ObjectGen};
 
+/// Ref holding the local commit the remote was last synced with
+pub const REMOTE_REF: &str = "remote/origin";
+
+/// Author of the commits recorded for local changes
+const COMMIT_AUTHOR: &str = "syng-demo";
+
 #[derive(Debug, Clone)]
 pub struct DemoFEBackend {
-    root_id: Option<String>,
+    refs: BTreeMap<String, String>,
     objects: HashMap<String, SyngObjectDef>,
+    journal: OpJournal,
 }
 
 impl SyngBackend for DemoFEBackend {
-    fn get_root_object_id(&self) -> Option<String> {
-        self.root_id.clone()
+    fn get_root_object_id(&self) -> Result<Option<String>, SyngError> {
+        self.read_ref(DEFAULT_REF)
     }
 
-    fn get_root_object(&self) -> Option<SyngObjectDef> {
-        let root_id = self.root_id.clone()?;
+    fn get_root_object(&self) -> Result<Option<SyngObjectDef>, SyngError> {
+        let Some(root_id) = self.refs.get(DEFAULT_REF) else {
+            return Ok(None);
+        };
 
-        self.read_object(&root_id)
+        self.read_object(root_id)
     }
 
-    fn set_root_object(&mut self, node_id: &str) -> Result<()> {
-        if !self.has_object(node_id) {
-            bail!("Tried to set root object to non-existent hash")
+    fn set_root_object(&mut self, node_id: &str) -> Result<(), SyngError> {
+        if !self.objects.contains_key(node_id) {
+            return Err(SyngError::ObjectNotFound(node_id.to_owned()));
         }
 
-        self.root_id = Some(node_id.to_owned());
+        self.refs.insert(DEFAULT_REF.to_owned(), node_id.to_owned());
 
         Ok(())
     }
 
-    fn read_object(&self, id: &str) -> Option<SyngObjectDef> {
-        Some(self.objects.get(id)?.clone())
+    fn compare_and_set_root(
+        &mut self,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        self.compare_and_set_ref(DEFAULT_REF, expected, new)
+    }
+
+    fn list_refs(&self) -> Result<BTreeMap<String, String>, SyngError> {
+        Ok(self.refs.clone())
+    }
+
+    fn read_ref(&self, name: &str) -> Result<Option<String>, SyngError> {
+        Ok(self.refs.get(name).cloned())
+    }
+
+    fn compare_and_set_ref(
+        &mut self,
+        name: &str,
+        expected: Option<&str>,
+        new: &str,
+    ) -> Result<bool, SyngError> {
+        if !is_valid_ref_name(name) {
+            return Err(SyngError::InvalidRefName(name.to_owned()));
+        }
+
+        if !self.objects.contains_key(new) {
+            return Err(SyngError::ObjectNotFound(new.to_owned()));
+        }
+
+        if self.refs.get(name).map(String::as_str) != expected {
+            return Ok(false);
+        }
+
+        self.refs.insert(name.to_owned(), new.to_owned());
+
+        Ok(true)
+    }
+
+    fn delete_ref(&mut self, name: &str) -> Result<bool, SyngError> {
+        Ok(self.refs.remove(name).is_some())
+    }
+
+    fn read_object(&self, id: &str) -> Result<Option<SyngObjectDef>, SyngError> {
+        Ok(self.objects.get(id).cloned())
     }
 
-    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String> {
+    fn write_object(&mut self, def: &SyngObjectDef) -> Result<String, SyngError> {
         let hash = def.get_hash()?;
 
         self.objects.insert(hash.clone(), def.clone());
 
         Ok(hash)
     }
+
+    fn write_objects_with_ids(
+        &mut self,
+        objects: &[(&str, &SyngObjectDef)],
+    ) -> Result<(), SyngError> {
+        for (id, def) in objects {
+            self.objects.insert(id.to_string(), (*def).clone());
+        }
+
+        Ok(())
+    }
+
+    fn list_objects(&self) -> Result<Vec<String>, SyngError> {
+        Ok(self.objects.keys().cloned().collect())
+    }
+
+    fn delete_object(&mut self, id: &str) -> Result<bool, SyngError> {
+        Ok(self.objects.remove(id).is_some())
+    }
 }
 
 pub enum NodeTranslation {
@@ -74,7 +149,8 @@ impl Default for DemoFEBackend {
 
         Self {
             objects: object_store,
-            root_id: Some(hash),
+            refs: BTreeMap::from([(DEFAULT_REF.to_owned(), hash)]),
+            journal: OpJournal::new(),
         }
     }
 }
@@ -83,7 +159,7 @@ impl DemoFEBackend {
     pub fn get_full_delta(&self) -> SyngDelta {
         SyngDelta {
             start_point: None,
-            new_root_node: self.root_id.clone().unwrap(),
+            new_root_node: self.refs[DEFAULT_REF].clone(),
             new_objects: self.objects.clone(),
         }
     }
@@ -92,62 +168,190 @@ impl DemoFEBackend {
             self.write_object(&obj).expect("Pull object write failed");
         }
 
-        self.root_id = data.root_obj_id.clone();
+        match &data.root_obj_id {
+            Some(root_id) => {
+                self.set_root_object(root_id)?;
+                self.commit_operation("Pull from remote")?;
+                self.mark_synced(root_id)?;
+            }
+            None => {
+                self.refs.remove(DEFAULT_REF);
+                self.refs.remove(REMOTE_REF);
+            }
+        }
 
         Ok(())
     }
 
-    pub fn get_delta_for_pushing(&self, past_point: &str) -> Result<SyngDelta> {
-        let delta = generate_delta_from_point(self, &past_point).expect("Delta gen failed");
+    fn commit(&mut self, message: &str) -> Result<()> {
+        commit_root(self, &CommitInfo::new(COMMIT_AUTHOR, message))?;
 
-        Ok(delta)
+        Ok(())
+    }
+
+    /// Commits the change made by an operation and records it in the journal so it can be undone.
+    /// Every change of the root gets committed, so the root before the operation is the tree of
+    /// the previous commit.
+    fn commit_operation(&mut self, message: &str) -> Result<()> {
+        let before = match self.read_ref(HISTORY_REF)? {
+            Some(head) => Some(read_commit(self, &head)?.tree),
+            None => None,
+        };
+
+        if let (Some(before), Some(after)) = (before, self.get_root_object_id()?) {
+            self.journal
+                .record(TreeOperation::Named(message.to_owned()), &before, &after);
+        }
+
+        self.commit(message)
+    }
+
+    fn describe_operation(operation: &TreeOperation) -> String {
+        match operation {
+            TreeOperation::Named(name) => name.clone(),
+            TreeOperation::RevertTo { .. } => "revert".to_owned(),
+            operation => format!("{:?}", operation),
+        }
     }
 
-    pub fn drop_unreachable_objects(&mut self, last_sync_point: &Option<String>) -> Result<()> {
-        let active_objects =
-            get_descendent_object_ids(self, &self.get_root_object_id().unwrap()).unwrap();
+    /// Undoes the last operation, returning false if there was nothing to undo
+    pub fn undo(&mut self) -> Result<bool> {
+        let mut journal = std::mem::take(&mut self.journal);
+        let undone = journal.undo(self);
+        self.journal = journal;
 
-        let sync_point_active_objects = match last_sync_point {
-            Some(past_point) => get_descendent_object_ids(self, past_point).unwrap(),
-            None => vec![],
+        let Some(entry) = undone.map_err(|e| anyhow!("Undo failed: {:?}", e))? else {
+            return Ok(false);
         };
 
-        self.objects.retain(|hash, _| {
-            active_objects.contains(hash) || sync_point_active_objects.contains(hash)
-        });
+        let message = format!("Undo {}", Self::describe_operation(&entry.operation));
+
+        self.commit(&message)?;
+
+        Ok(true)
+    }
+
+    /// Redoes the last undone operation, returning false if there was nothing to redo
+    pub fn redo(&mut self) -> Result<bool> {
+        let mut journal = std::mem::take(&mut self.journal);
+        let redone = journal.redo(self);
+        self.journal = journal;
+
+        let Some(entry) = redone.map_err(|e| anyhow!("Redo failed: {:?}", e))? else {
+            return Ok(false);
+        };
+
+        let message = format!("Redo {}", Self::describe_operation(&entry.operation));
+
+        self.commit(&message)?;
+
+        Ok(true)
+    }
+
+    /// Throws away the local changes made since the last sync with the remote. The revert can be
+    /// undone like any other operation.
+    pub fn revert_to_last_sync(&mut self) -> Result<()> {
+        let last_synced_root = self
+            .get_last_synced_remote_root_id()
+            .ok_or_else(|| anyhow!("Not synced with the remote yet"))?;
+
+        let mut journal = std::mem::take(&mut self.journal);
+        let reverted = journal.revert_to(self, &last_synced_root);
+        self.journal = journal;
+
+        reverted.map_err(|e| anyhow!("Revert failed: {:?}", e))?;
+
+        self.commit("Revert to last sync")
+    }
+
+    /// Root the remote was at when it was last synced with. This only reads the tree of the local
+    /// commit [`DemoFEBackend::mark_synced`] recorded in [`REMOTE_REF`], the remote's own history
+    /// isn't fetched.
+    pub fn get_last_synced_remote_root_id(&self) -> Option<String> {
+        let synced_commit = self.refs.get(REMOTE_REF)?;
+
+        Some(read_commit(self, synced_commit).ok()?.tree)
+    }
+
+    /// Marks the latest local commit of `root_id` as the point the remote was last synced at
+    pub fn mark_synced(&mut self, root_id: &str) -> Result<()> {
+        let head = self
+            .read_ref(HISTORY_REF)?
+            .ok_or_else(|| anyhow!("No local history to mark as synced"))?;
+
+        let Some((commit_id, _)) = log(self, &head)?
+            .into_iter()
+            .find(|(_, commit)| commit.tree == root_id)
+        else {
+            bail!("No local commit of the synced root {}", root_id);
+        };
+
+        let current = self.read_ref(REMOTE_REF)?;
+
+        self.compare_and_set_ref(REMOTE_REF, current.as_deref(), &commit_id)?;
 
         Ok(())
     }
 
+    pub fn get_delta_for_pushing(&self, past_point: &str) -> Result<SyngDelta> {
+        let delta = generate_delta_from_point(self, past_point)?;
+
+        Ok(delta)
+    }
+
+    /// Deletes the objects that aren't reachable from a ref, the last known remote root or a root
+    /// the journal can undo or redo to. The trees of older commits are dropped.
+    pub fn drop_unreachable_objects(
+        &mut self,
+        last_sync_point: &Option<String>,
+    ) -> Result<GcStats> {
+        let journal_roots = self
+            .journal
+            .entries()
+            .iter()
+            .flat_map(|entry| [entry.before.clone(), entry.after.clone()]);
+
+        let roots = last_sync_point
+            .iter()
+            .cloned()
+            .chain(journal_roots)
+            .collect::<Vec<_>>();
+
+        let roots = roots.iter().map(String::as_str).collect::<Vec<_>>();
+
+        Ok(gc(self, &roots)?)
+    }
+
     pub fn generate_gen_info(&self) -> Option<ObjectGen> {
         Some(ObjectGen {
-            root_id: self.root_id.clone()?,
+            root_id: self.refs.get(DEFAULT_REF)?.clone(),
             objects: self.objects.clone(),
         })
     }
 
     pub fn get_collection(&self, path: &[usize]) -> Option<CollectionData> {
-        let coll_obj = get_object_at_path(self, path)?.1;
+        let coll_obj = get_object_at_path(self, path).ok()?.1;
 
         self.parse_collection_from_obj(&coll_obj)
     }
 
     pub fn parse_request_from_obj(&self, obj: &SyngObjectDef) -> Option<RequestData> {
-        if obj.fields.get("type")? != "request" {
+        if obj.fields.get("type")?.as_str()? != "request" {
             return None;
         }
 
-        let req_data = serde_json::from_str(obj.fields.get("data")?).ok()?;
-
-        Some(req_data)
+        Some(RequestData {
+            title: obj.fields.get("title")?.as_str()?.to_owned(),
+            content: obj.fields.get("content")?.as_str()?.to_owned(),
+        })
     }
 
     pub fn parse_collection_from_obj(&self, obj: &SyngObjectDef) -> Option<CollectionData> {
-        if obj.fields.get("type")? != "collection" {
+        if obj.fields.get("type")?.as_str()? != "collection" {
             return None;
         }
 
-        let Some(coll_title) = obj.fields.get("title") else { return None };
+        let Some(coll_title) = obj.fields.get("title").and_then(SyngValue::as_str) else { return None };
 
         let mut requests = vec![];
         let mut collections = vec![];
@@ -162,14 +366,14 @@ impl DemoFEBackend {
         }
 
         Some(CollectionData {
-            title: coll_title.clone(),
+            title: coll_title.to_owned(),
             requests,
             folders: collections,
         })
     }
 
     pub fn translate_node(&self, node_id: &str) -> Option<NodeTranslation> {
-        let obj = self.read_object(node_id)?;
+        let obj = self.read_object(node_id).ok()??;
 
         if let Some(req_data) = self.parse_request_from_obj(&obj) {
             return Some(NodeTranslation::Request(req_data));
@@ -183,13 +387,13 @@ impl DemoFEBackend {
     }
 
     pub fn get_collection_tree(&self) -> Option<Vec<CollectionData>> {
-        let root_obj = self.get_root_object()?;
+        let root_obj = self.get_root_object().ok()??;
 
         let colls = root_obj
             .children
             .iter()
             .map(|child_hash| {
-                let obj = self.read_object(child_hash)?;
+                let obj = self.read_object(child_hash).ok()??;
 
                 self.parse_collection_from_obj(&obj)
             })
@@ -211,15 +415,15 @@ impl DemoFEBackend {
             &[],
             &coll_obj,
             syng::tree_ops::ChildAdditionPosition::AddToEnd,
-        )
-        .expect("Write root collection failed");
+        )?;
+
+        self.commit_operation("Add collection")?;
 
         Ok(())
     }
 
     pub fn add_folder(&mut self, coll_path: &[usize], def: CollectionData) -> Result<()> {
-        let (_, coll_obj_at_path) =
-            get_object_at_path(self, coll_path).expect("Node at path extract failed");
+        let (_, coll_obj_at_path) = get_object_at_path(self, coll_path)?;
 
         let add_pos = coll_obj_at_path
             .children
@@ -228,9 +432,11 @@ impl DemoFEBackend {
             .find_map(|(index, hash)| {
                 let obj = self
                     .read_object(hash)
+                    .ok()
+                    .flatten()
                     .expect("Folder point search hash failed");
 
-                if obj.fields.get("type") == Some(&"request".to_owned()) {
+                if obj.fields.get("type").and_then(SyngValue::as_str) == Some("request") {
                     Some(ChildAdditionPosition::AddAt(index))
                 } else {
                     None
@@ -245,7 +451,9 @@ impl DemoFEBackend {
             self.write_object(obj)?;
         }
 
-        add_child_object(self, coll_path, &coll_obj, add_pos).expect("Write folder failed");
+        add_child_object(self, coll_path, &coll_obj, add_pos)?;
+
+        self.commit_operation("Add folder")?;
 
         Ok(())
     }
@@ -253,21 +461,23 @@ impl DemoFEBackend {
     pub fn add_request(&mut self, path: &[usize], def: RequestData) -> Result<()> {
         let req_obj = generate_object_for_req(&def);
 
-        add_child_object(self, path, &req_obj, ChildAdditionPosition::AddToEnd)
-            .expect("Write request failed");
+        add_child_object(self, path, &req_obj, ChildAdditionPosition::AddToEnd)?;
+
+        self.commit_operation("Add request")?;
 
         Ok(())
     }
 
     pub fn delete_folder(&mut self, path: &[usize]) -> Result<()> {
-        remove_child_object(self, path).expect("Delete folder failed");
+        remove_child_object(self, path)?;
+
+        self.commit_operation("Delete folder")?;
 
         Ok(())
     }
 
     pub fn delete_request(&mut self, path: &[usize], req_index: usize) -> Result<()> {
-        let (_, coll_obj_at_path) =
-            get_object_at_path(self, path).expect("Node at path extract failed");
+        let (_, coll_obj_at_path) = get_object_at_path(self, path)?;
 
         let req_pos = coll_obj_at_path
             .children
@@ -276,9 +486,11 @@ impl DemoFEBackend {
             .find_map(|(index, hash)| {
                 let obj = self
                     .read_object(hash)
+                    .ok()
+                    .flatten()
                     .expect("Folder point search hash failed");
 
-                if obj.fields.get("type") == Some(&"request".to_owned()) {
+                if obj.fields.get("type").and_then(SyngValue::as_str) == Some("request") {
                     Some(index)
                 } else {
                     None
@@ -288,27 +500,16 @@ impl DemoFEBackend {
 
         let remove_index = req_pos + req_index;
 
-        remove_child_object(self, &[&path[..], &[remove_index]].concat())
-            .expect("Remove request failed");
+        remove_child_object(self, &[&path[..], &[remove_index]].concat())?;
+
+        self.commit_operation("Delete request")?;
 
         Ok(())
     }
 
     pub fn move_folder(&mut self, path: &[usize], new_path: &[usize]) -> Result<()> {
-        let parent_path = &path[..path.len() - 1];
-        // Get the folder object of the parent
-        let (_, parent_obj) =
-            get_object_at_path(self, parent_path).expect("Parent object not found");
-
-        // Get index of the folder in the parent
-        let folder_index = path.last().unwrap();
-
-        // Get the folder object
-        let (_, folder_obj) = get_object_at_path(self, path).expect("Folder object not found");
-
         // Get the folder object at the new path
-        let (_, new_folder_obj) =
-            get_object_at_path(self, new_path).expect("New folder object not found");
+        let (_, new_folder_obj) = get_object_at_path(self, new_path)?;
 
         // Find last folder position so we can find the location to add to
         let add_pos = new_folder_obj
@@ -318,9 +519,11 @@ impl DemoFEBackend {
             .find_map(|(index, hash)| {
                 let obj = self
                     .read_object(hash)
+                    .ok()
+                    .flatten()
                     .expect("Folder point search hash failed");
 
-                if obj.fields.get("type") == Some(&"request".to_owned()) {
+                if obj.fields.get("type").and_then(SyngValue::as_str) == Some("request") {
                     Some(ChildAdditionPosition::AddAt(index))
                 } else {
                     None
@@ -328,26 +531,71 @@ impl DemoFEBackend {
             })
             .unwrap_or(ChildAdditionPosition::AddToEnd);
 
-        // Update the current parent object to remove the folder
-        let mut new_parent_obj = parent_obj.clone();
-        new_parent_obj.children.remove(*folder_index);
-
-        update_object(self, parent_path, &new_parent_obj).expect("Update parent object failed");
+        // Move the folder in front of the requests of the new parent, in a single root update
+        move_child_object(self, path, new_path, add_pos)?;
 
-        // Add folder to the new parent
-        add_child_object(self, new_path, &folder_obj, add_pos);
+        self.commit_operation("Move folder")?;
 
         Ok(())
     }
 
+    /// Stable ID of the node at the path, which keeps pointing to the node while the tree around it
+    /// changes
+    pub fn get_node_id(&self, path: &[usize]) -> Option<String> {
+        let (_, obj) = get_object_at_path(self, path).ok()?;
+
+        obj.node_id().map(str::to_owned)
+    }
+
+    pub fn get_request_node_id(&self, folder_path: &[usize], req_index: usize) -> Option<String> {
+        // Folders come before the requests in a collection
+        let folder = self.get_collection(folder_path)?;
+
+        self.get_node_id(&[folder_path, &[folder.folders.len() + req_index]].concat())
+    }
+
+    fn get_node_path(&self, node_id: &str) -> Result<Vec<usize>> {
+        find_node_path(self, node_id)?.ok_or_else(|| anyhow!("Node {} not found", node_id))
+    }
+
+    pub fn move_folder_by_node_id(&mut self, node_id: &str, new_path: &[usize]) -> Result<()> {
+        let path = self.get_node_path(node_id)?;
+
+        self.move_folder(&path, new_path)
+    }
+
+    pub fn move_request_by_node_id(&mut self, node_id: &str, new_path: &[usize]) -> Result<()> {
+        let path = self.get_node_path(node_id)?;
+
+        let Some((index_in_folder, folder_path)) = path.split_last() else {
+            bail!("Node {} is the root, not a request", node_id);
+        };
+
+        let (_, obj) = get_object_at_path(self, &path)?;
+
+        if self.parse_request_from_obj(&obj).is_none() {
+            bail!("Node {} is not a request", node_id);
+        }
+
+        let folder = self
+            .get_collection(folder_path)
+            .ok_or_else(|| anyhow!("Parent of request {} is not a collection", node_id))?;
+
+        // Requests come after the subfolders of a folder
+        let Some(req_index) = index_in_folder.checked_sub(folder.folders.len()) else {
+            bail!("Request {} is placed among the subfolders", node_id);
+        };
+
+        self.move_request((folder_path, req_index), new_path)
+    }
+
     pub fn move_request(
         &mut self,
         (folder_path, req_index): (&[usize], usize),
         new_path: &[usize],
     ) -> Result<()> {
         // Get the folder object of the parent
-        let (_, folder_obj) =
-            get_object_at_path(self, folder_path).expect("Folder object not found");
+        let (_, folder_obj) = get_object_at_path(self, folder_path)?;
 
         let req_index_in_folder = folder_obj
             .children
@@ -356,9 +604,11 @@ impl DemoFEBackend {
             .find_map(|(index, hash)| {
                 let obj = self
                     .read_object(hash)
+                    .ok()
+                    .flatten()
                     .expect("Folder point search hash failed");
 
-                if obj.fields.get("type") == Some(&"request".to_owned()) {
+                if obj.fields.get("type").and_then(SyngValue::as_str) == Some("request") {
                     Some(index + req_index)
                 } else {
                     None
@@ -366,25 +616,12 @@ impl DemoFEBackend {
             })
             .unwrap_or(req_index);
 
-        // Get request object
-        let req_obj_id = folder_obj.children[req_index_in_folder].clone();
-
-        // Update the folder object to remove the request
-        let mut new_folder_obj = folder_obj.clone();
-        new_folder_obj.children.remove(req_index_in_folder);
-
-        update_object(self, folder_path, &new_folder_obj).expect("Update folder object failed");
-
-        // Get the new folder object
-        let (_, new_folder_obj) =
-            get_object_at_path(self, new_path).expect("New folder object not found");
+        let req_path = [folder_path, &[req_index_in_folder]].concat();
 
-        // Update the new folder object to add the request to the end
-        let mut new_new_folder_obj = new_folder_obj.clone();
-        new_new_folder_obj.children.push(req_obj_id);
+        // Move the request to the end of the new folder, in a single root update
+        move_child_object(self, &req_path, new_path, ChildAdditionPosition::AddToEnd)?;
 
-        update_object(self, new_path, &new_new_folder_obj)
-            .expect("Update new folder object failed");
+        self.commit_operation("Move request")?;
 
         Ok(())
     }
@@ -11,16 +11,15 @@ pub struct ObjectGen {
 }
 
 pub fn generate_object_for_req(value: &RequestData) -> SyngObjectDef {
-    let content =
-        serde_json::to_string(&value).expect("RequestData to JSON Stringification failed");
-
     SyngObjectDef {
         fields: BTreeMap::from([
-            ("type".to_owned(), "request".to_owned()),
-            ("data".to_owned(), content),
+            ("type".to_owned(), "request".into()),
+            ("title".to_owned(), value.title.as_str().into()),
+            ("content".to_owned(), value.content.as_str().into()),
         ]),
         children: vec![],
     }
+    .with_new_node_id()
 }
 
 pub fn generate_object_for_coll(
@@ -65,11 +64,12 @@ pub fn generate_object_for_coll(
 
     SyngObjectDef {
         fields: BTreeMap::from([
-            ("type".to_owned(), "collection".to_owned()),
-            ("title".to_owned(), coll.title.to_owned()),
+            ("type".to_owned(), "collection".into()),
+            ("title".to_owned(), coll.title.as_str().into()),
         ]),
         children: [coll_hashes, req_hashes].concat(),
     }
+    .with_new_node_id()
 }
 
 impl From<&Vec<CollectionData>> for ObjectGen {
@@ -71,7 +71,7 @@ pub async fn get_sync_status(
         measure_time_async(|| async { get_current_remote_root().await.unwrap().data.unwrap() })
             .await;
 
-    let local_root_id = backend.get_root_object_id().unwrap();
+    let local_root_id = backend.get_root_object_id().unwrap().unwrap();
 
     // Check if remote state is unchanged from last sync
     if remote_root_id == last_synced_remote_root_id {